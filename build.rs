@@ -1,7 +1,60 @@
 use sqlx::postgres::PgPoolOptions;
 
+/// The commit this binary is built from, reported by `/version`.
+///
+/// Heroku builds do not include the `.git` directory, but expose the commit through `SOURCE_VERSION`.
+fn git_sha() -> String {
+    std::env::var("SOURCE_VERSION")
+        .ok()
+        .or_else(|| {
+            std::process::Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// Writes the OpenAPI document of every server type to `dir`, for generating clients.
+fn dump_openapi(dir: &str) {
+    use simple_syrup_client::openapi::document;
+    use simple_syrup_client::types::*;
+
+    let documents = [
+        (
+            "email",
+            document::<EmailAuthenticator>("EmailAuthenticator"),
+        ),
+        ("qa", document::<QAAuthenticator>("QAAuthenticator")),
+        (
+            "password",
+            document::<PasswordAuthenticator>("PasswordAuthenticator"),
+        ),
+        (
+            "biometric",
+            document::<BiometricAuthenticator>("BiometricAuthenticator"),
+        ),
+    ];
+
+    std::fs::create_dir_all(dir).expect("Could not create OPENAPI_DIR");
+    for (name, document) in documents {
+        let json = serde_json::to_string_pretty(&document).unwrap();
+        std::fs::write(format!("{}/{}.json", dir, name), json)
+            .expect("Could not write OpenAPI document");
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha());
+
+    if let Ok(dir) = std::env::var("OPENAPI_DIR") {
+        dump_openapi(&dir);
+    }
+
     let build_enabled = std::env::var("BUILD_ENABLED")
         .map(|v| v == "1")
         .unwrap_or(true); // run by default
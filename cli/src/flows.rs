@@ -0,0 +1,540 @@
+//! Enrolling a secret with a set of servers, and recovering it.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sharing::{Commitments, Share};
+
+use crate::prompt::{self, Prompt};
+use crate::servers::{Client, Server};
+
+/// What happened with one server.
+#[derive(Debug, Serialize)]
+pub struct ServerReport {
+    pub url: String,
+    pub server_ty: String,
+    /// Index of the share the server holds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<u8>,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ServerReport {
+    fn new(server: &Server, index: Option<u8>, res: Result<(), String>) -> Self {
+        Self {
+            url: server.url.clone(),
+            server_ty: server.server_ty.clone(),
+            index,
+            ok: res.is_ok(),
+            error: res.err(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnrollReport {
+    pub email: String,
+    pub threshold: u8,
+    pub verifiable: bool,
+    pub servers: Vec<ServerReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoverReport {
+    pub email: String,
+    /// The secret, as text when it is UTF-8, and base64 otherwise.
+    pub secret: String,
+    pub encoding: &'static str,
+    pub servers: Vec<ServerReport>,
+}
+
+/// The majority threshold of `servers` servers.
+pub fn default_threshold(servers: usize) -> u8 {
+    (servers / 2 + 1) as u8
+}
+
+/// Splits `secret` and registers one share with each server, prompting for its data and OTP.
+///
+/// Fails when fewer than `threshold` servers enrolled, as the secret could not be recovered.
+pub async fn enroll(
+    client: &Client,
+    prompt: &mut dyn Prompt,
+    servers: &[Server],
+    email: &str,
+    secret: &[u8],
+    threshold: u8,
+    verifiable: bool,
+) -> Result<EnrollReport, String> {
+    if servers.len() > u8::MAX as usize {
+        return Err(format!("at most {} servers are supported", u8::MAX));
+    }
+
+    let (shares, commitments) = if verifiable {
+        let (shares, commitments) = sharing::feldman::split(secret, threshold, servers.len() as u8)
+            .map_err(|e| e.to_string())?;
+        (shares, Some(commitments.encode()))
+    } else {
+        let shares =
+            sharing::split(secret, threshold, servers.len() as u8).map_err(|e| e.to_string())?;
+        (shares, None)
+    };
+
+    let mut reports = vec![];
+    for (server, share) in servers.iter().zip(&shares) {
+        let res = async {
+            let data = prompt::registration_data(prompt, server)?;
+            client
+                .register(
+                    server,
+                    email,
+                    &share.encode(),
+                    commitments.as_deref(),
+                    &data,
+                )
+                .await?;
+
+            let otp = prompt::otp(prompt, server)?;
+            client.verify_register(server, email, &otp).await
+        }
+        .await;
+
+        reports.push(ServerReport::new(server, Some(share.index), res));
+    }
+
+    let enrolled = reports.iter().filter(|report| report.ok).count();
+    if enrolled < threshold as usize {
+        return Err(format!(
+            "only {} of {} servers enrolled, {} are needed to recover",
+            enrolled,
+            servers.len(),
+            threshold
+        ));
+    }
+
+    Ok(EnrollReport {
+        email: email.into(),
+        threshold,
+        verifiable,
+        servers: reports,
+    })
+}
+
+/// A share released by a server, with the commitments it was released with.
+type Released = Result<(Share, Option<String>), String>;
+
+/// The commitments most shares were released with, if any were.
+fn majority(released: &[(&Server, Released)]) -> Option<Commitments> {
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    for (_, res) in released {
+        if let Ok((_, Some(commitments))) = res {
+            *votes.entry(commitments).or_default() += 1;
+        }
+    }
+
+    votes
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .and_then(|(commitments, _)| Commitments::decode(commitments).ok())
+}
+
+fn check(commitments: Option<&Commitments>, share: &Share) -> Result<(), String> {
+    commitments.map_or(Ok(()), |commitments| {
+        commitments.verify(share).map_err(|e| e.to_string())
+    })
+}
+
+/// Whether enough released shares match the majority commitments to recover.
+fn enough(released: &[(&Server, Released)]) -> bool {
+    let commitments = majority(released);
+    let shares: Vec<&Share> = released
+        .iter()
+        .filter_map(|(_, res)| res.as_ref().ok())
+        .map(|(share, _)| share)
+        .filter(|share| check(commitments.as_ref(), share).is_ok())
+        .collect();
+
+    shares
+        .first()
+        .is_some_and(|share| shares.len() >= share.threshold as usize)
+}
+
+/// Authenticates with servers until enough shares were released, then combines them.
+///
+/// Servers release verifiable shares with the commitments they were registered with. Each
+/// share is checked against the commitments most shares were released with, and a share not
+/// matching them is reported and not used.
+pub async fn recover(
+    client: &Client,
+    prompt: &mut dyn Prompt,
+    servers: &[Server],
+    email: &str,
+) -> Result<RecoverReport, String> {
+    let mut released: Vec<(&Server, Released)> = vec![];
+
+    for server in servers {
+        if enough(&released) {
+            break;
+        }
+
+        let res = async {
+            let authenticated = client.authenticate(server, email).await?;
+
+            let data = prompt::authentication_data(prompt, server, &authenticated)?;
+            let (secret_component, commitments) =
+                client.verify_authentication(server, email, &data).await?;
+
+            let share = Share::decode(&secret_component).map_err(|e| e.to_string())?;
+            Ok((share, commitments))
+        }
+        .await;
+
+        released.push((server, res));
+    }
+
+    let commitments = majority(&released);
+    let mut shares: Vec<Share> = vec![];
+    let mut reports = vec![];
+
+    for (server, res) in released {
+        let res = res.and_then(|(share, _)| {
+            check(commitments.as_ref(), &share)?;
+            Ok(share)
+        });
+
+        match res {
+            Ok(share) => {
+                reports.push(ServerReport::new(server, Some(share.index), Ok(())));
+                shares.push(share);
+            }
+            Err(e) => reports.push(ServerReport::new(server, None, Err(e))),
+        }
+    }
+
+    let secret = sharing::combine(&shares).map_err(|e| e.to_string())?;
+    let (secret, encoding) = match String::from_utf8(secret) {
+        Ok(secret) => (secret, "utf8"),
+        Err(e) => (base64::encode(e.into_bytes()), "base64"),
+    };
+
+    Ok(RecoverReport {
+        email: email.into(),
+        secret,
+        encoding,
+        servers: reports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+
+    use super::*;
+    use crate::prompt::Scripted;
+
+    /// Share and commitments registered with each mock server, by prefix, and the listing the
+    /// mock root serves.
+    #[derive(Default)]
+    struct State {
+        pending: HashMap<String, (String, Option<String>)>,
+        registered: HashMap<String, (String, Option<String>)>,
+        listing: Vec<Server>,
+    }
+
+    type Data = web::Data<Mutex<State>>;
+
+    /// The key pair the mock servers sign their responses with, derived from `seed`.
+    fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn public_key(seed: u8) -> String {
+        base64::encode(keypair(seed).public.as_bytes())
+    }
+
+    /// `body` signed like the servers' `Identity::signed_json`.
+    fn signed(body: &impl Serialize) -> HttpResponse {
+        let keypair = keypair(7);
+        let body = serde_json::to_vec(body).unwrap();
+        let signature = base64::encode(keypair.sign(&body).to_bytes());
+
+        HttpResponse::Ok()
+            .insert_header(("x-signature", signature))
+            .insert_header(("x-public-key", public_key(7)))
+            .content_type("application/json")
+            .body(body)
+    }
+
+    async fn listing(state: Data) -> HttpResponse {
+        signed(&state.lock().unwrap().listing)
+    }
+
+    async fn register(
+        state: Data,
+        prefix: web::Path<String>,
+        body: web::Json<serde_json::Value>,
+    ) -> HttpResponse {
+        let share = body["secret_component"].as_str().unwrap().to_string();
+        let commitments = body["commitments"].as_str().map(String::from);
+
+        state
+            .lock()
+            .unwrap()
+            .pending
+            .insert(prefix.into_inner(), (share, commitments));
+
+        HttpResponse::Ok().finish()
+    }
+
+    async fn register_verify(
+        state: Data,
+        prefix: web::Path<String>,
+        body: web::Json<serde_json::Value>,
+    ) -> HttpResponse {
+        if body["otp"] != "123456" {
+            return HttpResponse::Unauthorized().finish();
+        }
+
+        let mut state = state.lock().unwrap();
+        let pending = state.pending.remove(prefix.as_str()).unwrap();
+        state.registered.insert(prefix.into_inner(), pending);
+
+        HttpResponse::Ok().finish()
+    }
+
+    async fn authenticate(state: Data, prefix: web::Path<String>) -> HttpResponse {
+        if state
+            .lock()
+            .unwrap()
+            .registered
+            .contains_key(prefix.as_str())
+        {
+            HttpResponse::Ok().finish()
+        } else {
+            HttpResponse::NotFound().finish()
+        }
+    }
+
+    async fn authenticate_verify(
+        state: Data,
+        prefix: web::Path<String>,
+        body: web::Json<serde_json::Value>,
+    ) -> HttpResponse {
+        if body["data"] != "123456" {
+            return HttpResponse::Unauthorized().finish();
+        }
+
+        let state = state.lock().unwrap();
+        let (share, commitments) = state.registered.get(prefix.as_str()).unwrap();
+        signed(&serde_json::json!({
+            "secret_component": share,
+            "commitments": commitments,
+        }))
+    }
+
+    /// Three Email servers, under `/a`, `/b` and `/c`, listed by a root under `/root`.
+    fn mock_servers(state: Data) -> (String, Vec<Server>) {
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .route("/root/", web::get().to(listing))
+                .route("/{prefix}/register", web::post().to(register))
+                .route("/{prefix}/register/verify", web::post().to(register_verify))
+                .route("/{prefix}/authenticate", web::post().to(authenticate))
+                .route(
+                    "/{prefix}/authenticate/verify",
+                    web::post().to(authenticate_verify),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let servers: Vec<Server> = ["a", "b", "c"]
+            .iter()
+            .map(|prefix| Server {
+                url: format!("http://{}/{}", addr, prefix),
+                server_ty: "Email".into(),
+                public_key: Some(public_key(7)),
+                ready: None,
+            })
+            .collect();
+        state.lock().unwrap().listing = servers.clone();
+
+        (format!("http://{}/root", addr), servers)
+    }
+
+    fn answers(answers: &[&str]) -> Scripted {
+        Scripted(
+            answers
+                .iter()
+                .map(|answer| answer.to_string())
+                .collect::<VecDeque<_>>(),
+        )
+    }
+
+    #[actix_web::test]
+    async fn enroll_and_recover() {
+        let state = Data::new(Mutex::default());
+        let (_, servers) = mock_servers(state.clone());
+        let client = Client::new();
+
+        let report = enroll(
+            &client,
+            &mut answers(&["123456", "000000", "123456"]),
+            &servers,
+            "user@test",
+            b"foobar",
+            2,
+            true,
+        )
+        .await
+        .unwrap();
+
+        let enrolled: Vec<bool> = report.servers.iter().map(|server| server.ok).collect();
+        assert_eq!(enrolled, [true, false, true]);
+
+        // The server that failed to enroll is skipped, and no more are asked once two shares are in.
+        let report = recover(
+            &client,
+            &mut answers(&["123456", "123456"]),
+            &servers,
+            "user@test",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.secret, "foobar");
+        assert_eq!(report.encoding, "utf8");
+        assert_eq!(report.servers.len(), 3);
+        assert!(!report.servers[1].ok);
+    }
+
+    #[actix_web::test]
+    async fn reject_tampered_shares() {
+        let state = Data::new(Mutex::default());
+        let (_, servers) = mock_servers(state.clone());
+        let client = Client::new();
+
+        enroll(
+            &client,
+            &mut answers(&["123456", "123456", "123456"]),
+            &servers,
+            "user@test",
+            b"foobar",
+            2,
+            true,
+        )
+        .await
+        .unwrap();
+
+        // A share of another secret, which does not match the commitments of the others.
+        let (others, _) = sharing::feldman::split(b"barfoo", 2, 3).unwrap();
+        state.lock().unwrap().registered.get_mut("a").unwrap().0 = others[0].encode();
+
+        let report = recover(
+            &client,
+            &mut answers(&["123456", "123456", "123456"]),
+            &servers,
+            "user@test",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.secret, "foobar");
+        assert!(report.servers[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("commitments"));
+    }
+
+    #[actix_web::test]
+    async fn too_few_servers_enrolled() {
+        let state = Data::new(Mutex::default());
+        let (_, servers) = mock_servers(state);
+
+        let res = enroll(
+            &Client::new(),
+            &mut answers(&["000000", "000000", "123456"]),
+            &servers,
+            "user@test",
+            b"foobar",
+            2,
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            res.unwrap_err(),
+            "only 1 of 3 servers enrolled, 2 are needed to recover"
+        );
+    }
+
+    #[actix_web::test]
+    async fn reject_shares_signed_by_another_key() {
+        let state = Data::new(Mutex::default());
+        let (_, mut servers) = mock_servers(state.clone());
+        let client = Client::new();
+
+        enroll(
+            &client,
+            &mut answers(&["123456", "123456", "123456"]),
+            &servers,
+            "user@test",
+            b"foobar",
+            2,
+            true,
+        )
+        .await
+        .unwrap();
+
+        servers[0].public_key = Some(public_key(8));
+
+        let report = recover(
+            &client,
+            &mut answers(&["123456", "123456", "123456"]),
+            &servers,
+            "user@test",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.secret, "foobar");
+        assert!(!report.servers[0].ok);
+        assert!(report.servers[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("the key listed for the server"));
+    }
+
+    #[actix_web::test]
+    async fn verify_listing() {
+        let state = Data::new(Mutex::default());
+        let (root, servers) = mock_servers(state);
+        let client = Client::new();
+
+        assert_eq!(client.servers(&root, None).await.unwrap(), servers);
+        assert_eq!(
+            client.servers(&root, Some(&public_key(7))).await.unwrap(),
+            servers
+        );
+
+        let err = client
+            .servers(&root, Some(&public_key(8)))
+            .await
+            .unwrap_err();
+        assert!(err.starts_with("the listing is signed by"));
+    }
+}
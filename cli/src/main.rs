@@ -0,0 +1,160 @@
+//! `cpass`, enrolling a secret with a set of CryptoPass servers and recovering it.
+//!
+//! `enroll` reads the servers listed by the root's `GET /`, splits the secret into one share per
+//! server, and registers each share, prompting for the data of its factor and the OTP sent to
+//! the user. `recover` authenticates with the servers until enough shares were released, and
+//! combines them, using only shares signed by the key the root listed for their server.
+//!
+//! Prompts go to stderr and answers are read from stdin, so both can be scripted; `--json`
+//! prints the result as JSON on stdout.
+
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+
+mod flows;
+mod prompt;
+mod servers;
+
+#[derive(Debug, Parser)]
+#[clap(name = "cpass", version, about = "CryptoPass command line client")]
+struct Cli {
+    /// Root server listing the authenticator servers
+    #[clap(
+        long,
+        env = "CPASS_ROOT",
+        default_value = "http://localhost:8080",
+        global = true
+    )]
+    root: String,
+
+    /// Public key the root signs its listing with, checked when given
+    #[clap(long, env = "CPASS_ROOT_KEY", global = true)]
+    root_key: Option<String>,
+
+    /// Print the result as JSON
+    #[clap(long, global = true)]
+    json: bool,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Split a secret and register a share with every server
+    Enroll {
+        #[clap(long, env = "CPASS_EMAIL")]
+        email: String,
+
+        /// The secret, prompted for when not given
+        #[clap(long, env = "CPASS_SECRET", hide_env_values = true)]
+        secret: Option<String>,
+
+        /// Servers needed to recover the secret. Defaults to a majority
+        #[clap(long)]
+        threshold: Option<u8>,
+
+        /// Register Feldman verifiable shares, so wrong shares are detected on recovery
+        #[clap(long)]
+        verifiable: bool,
+    },
+    /// Collect shares from the servers and combine them
+    Recover {
+        #[clap(long, env = "CPASS_EMAIL")]
+        email: String,
+    },
+}
+
+fn print<T: Serialize + std::fmt::Debug>(json: bool, report: &T, human: impl FnOnce(&T)) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).expect("Could not serialize report")
+        );
+    } else {
+        human(report)
+    }
+}
+
+fn print_servers(servers: &[flows::ServerReport]) {
+    for server in servers {
+        match &server.error {
+            None => eprintln!("  ok      {} {}", server.server_ty, server.url),
+            Some(e) => eprintln!("  failed  {} {}: {}", server.server_ty, server.url, e),
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    let client = servers::Client::new();
+    let mut terminal = prompt::Terminal;
+
+    let servers = client
+        .servers(&cli.root, cli.root_key.as_deref())
+        .await
+        .map_err(|e| format!("Could not list the servers of {}: {}", cli.root, e))?;
+    if servers.is_empty() {
+        return Err(format!("{} lists no servers", cli.root));
+    }
+
+    match cli.command {
+        Command::Enroll {
+            email,
+            secret,
+            threshold,
+            verifiable,
+        } => {
+            let secret = match secret {
+                Some(secret) => secret,
+                None => prompt::Prompt::ask_hidden(&mut terminal, "Secret")?,
+            };
+            let threshold = threshold.unwrap_or_else(|| flows::default_threshold(servers.len()));
+
+            let report = flows::enroll(
+                &client,
+                &mut terminal,
+                &servers,
+                &email,
+                secret.as_bytes(),
+                threshold,
+                verifiable,
+            )
+            .await?;
+
+            print(cli.json, &report, |report| {
+                eprintln!(
+                    "Enrolled {}, {} of {} servers needed to recover:",
+                    report.email,
+                    report.threshold,
+                    report.servers.len()
+                );
+                print_servers(&report.servers);
+            });
+        }
+        Command::Recover { email } => {
+            let report = flows::recover(&client, &mut terminal, &servers, &email).await?;
+
+            print(cli.json, &report, |report| {
+                print_servers(&report.servers);
+                println!("{}", report.secret);
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    if let Err(e) = run(cli).await {
+        if json {
+            println!("{}", serde_json::json!({ "error": e }));
+        } else {
+            eprintln!("{}", e);
+        }
+        std::process::exit(1);
+    }
+}
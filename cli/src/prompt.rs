@@ -0,0 +1,147 @@
+//! Asking the user for the data of each factor.
+//!
+//! Prompts are written to stderr, so stdout only carries the result, and answers are read from
+//! stdin one line at a time, so they can also be piped in.
+
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::servers::Server;
+
+pub trait Prompt {
+    fn ask(&mut self, label: &str) -> Result<String, String>;
+
+    /// Like [`Prompt::ask`], without echoing the answer.
+    fn ask_hidden(&mut self, label: &str) -> Result<String, String>;
+}
+
+/// Prompts on the terminal.
+pub struct Terminal;
+
+impl Prompt for Terminal {
+    fn ask(&mut self, label: &str) -> Result<String, String> {
+        eprint!("{}: ", label);
+        std::io::stderr().flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        let read = std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err(format!("no answer for {}", label));
+        }
+
+        Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+    }
+
+    fn ask_hidden(&mut self, label: &str) -> Result<String, String> {
+        eprint!("{}: ", label);
+        std::io::stderr().flush().map_err(|e| e.to_string())?;
+
+        rpassword::read_password().map_err(|e| e.to_string())
+    }
+}
+
+/// Answers prompts in order, for tests.
+#[cfg(test)]
+pub struct Scripted(pub VecDeque<String>);
+
+#[cfg(test)]
+impl Prompt for Scripted {
+    fn ask(&mut self, label: &str) -> Result<String, String> {
+        self.0
+            .pop_front()
+            .ok_or_else(|| format!("no answer for {}", label))
+    }
+
+    fn ask_hidden(&mut self, label: &str) -> Result<String, String> {
+        self.ask(label)
+    }
+}
+
+fn label(server: &Server, what: &str) -> String {
+    format!("[{} {}] {}", server.server_ty, server.url, what)
+}
+
+/// The `data` registered with `server`.
+pub fn registration_data(prompt: &mut dyn Prompt, server: &Server) -> Result<Value, String> {
+    match server.server_ty.as_str() {
+        "Email" => Ok(Value::Null),
+        "QA" => {
+            let mut questions = vec![];
+            loop {
+                let what = format!(
+                    "Security question {} (empty when done)",
+                    questions.len() + 1
+                );
+                let question = prompt.ask(&label(server, &what))?;
+                if question.is_empty() {
+                    break Ok(Value::Array(questions));
+                }
+                questions.push(json!({
+                    "question": question,
+                    "answer": prompt.ask_hidden(&label(server, "Answer"))?,
+                }));
+            }
+        }
+        "Password" => Ok(json!({
+            "password": prompt.ask_hidden(&label(server, "Password"))?,
+        })),
+        "Biometric" => Ok(json!(prompt.ask(&label(server, "Device id"))?)),
+        other => Err(format!("{} servers are not supported", other)),
+    }
+}
+
+/// The OTP sent to the user to complete a registration.
+pub fn otp(prompt: &mut dyn Prompt, server: &Server) -> Result<String, String> {
+    prompt.ask(&label(server, "OTP sent to your email"))
+}
+
+/// The `data` verifying the user with `server`, once authentication started.
+///
+/// `authenticated` is what `/authenticate` answered, such as the questions a QA server asks.
+pub fn authentication_data(
+    prompt: &mut dyn Prompt,
+    server: &Server,
+    authenticated: &Value,
+) -> Result<Value, String> {
+    match server.server_ty.as_str() {
+        "Email" => Ok(json!(prompt.ask(&label(server, "OTP sent to your email"))?)),
+        "QA" => {
+            let asked = authenticated["questions"]
+                .as_array()
+                .ok_or("the server asked no questions")?;
+            let required = authenticated["required"].as_u64().unwrap_or_default();
+            eprintln!(
+                "{}",
+                label(
+                    server,
+                    &format!("Answer {} of {} questions", required, asked.len())
+                )
+            );
+
+            let mut answers = vec![];
+            for (i, question) in asked.iter().filter_map(Value::as_str).enumerate() {
+                // Questions registered before servers kept them must be typed again.
+                let question = if question.is_empty() {
+                    prompt.ask(&label(server, &format!("Security question {}", i + 1)))?
+                } else {
+                    question.to_string()
+                };
+                let answer = prompt.ask_hidden(&label(server, &question))?;
+                answers.push(json!({ "question": question, "answer": answer }));
+            }
+            Ok(Value::Array(answers))
+        }
+        "Password" => registration_data(prompt, server),
+        "Biometric" => {
+            prompt.ask(&label(server, "Approve on your device, then press enter"))?;
+            Ok(json!(""))
+        }
+        other => Err(format!("{} servers are not supported", other)),
+    }
+}
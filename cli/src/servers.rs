@@ -0,0 +1,211 @@
+//! Calls to the root listing and the authenticator servers.
+//!
+//! The root signs its listing, and each server the shares it releases, with the Ed25519 key sent
+//! in the `x-public-key` header. Shares are only accepted when signed by the key the root listed
+//! for their server.
+
+use std::convert::TryFrom;
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Headers carrying the signature of a response body, and the key it was signed with.
+const SIGNATURE_HEADER: &str = "x-signature";
+const PUBLIC_KEY_HEADER: &str = "x-public-key";
+
+/// An authenticator server, as listed by the root's `GET /`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Server {
+    pub url: String,
+    pub server_ty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<bool>,
+}
+
+/// What `POST /authenticate/verify` releases.
+#[derive(Deserialize)]
+struct Released {
+    secret_component: Option<String>,
+    #[serde(default)]
+    commitments: Option<String>,
+}
+
+pub struct Client {
+    http: reqwest::Client,
+}
+
+/// Whether `signature` is the signature of `message` by `public_key`, both in base64.
+fn verify(public_key: &str, message: &[u8], signature: &str) -> bool {
+    let public_key = base64::decode(public_key)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok());
+    let signature = base64::decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok());
+
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => public_key.verify(message, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+/// Checks that `body` was signed by `public_key`, with the signature in `headers`.
+fn signed_by(headers: &HeaderMap, body: &[u8], public_key: &str) -> Result<(), String> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    match (header(PUBLIC_KEY_HEADER), header(SIGNATURE_HEADER)) {
+        (Some(key), Some(signature)) if key == public_key => {
+            if verify(key, body, signature) {
+                Ok(())
+            } else {
+                Err("the signature is not valid".into())
+            }
+        }
+        (Some(key), Some(_)) => Err(format!("signed by {} instead of {}", key, public_key)),
+        _ => Err(format!("not signed by {}", public_key)),
+    }
+}
+
+async fn check(res: reqwest::Response) -> Result<reqwest::Response, String> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let body = res.text().await.unwrap_or_default();
+    if body.is_empty() {
+        Err(status.to_string())
+    } else {
+        Err(format!("{} ({})", body, status))
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::new(),
+        }
+    }
+
+    async fn post(&self, url: &str, path: &str, body: &Value) -> Result<reqwest::Response, String> {
+        let res = self
+            .http
+            .post(format!("{}{}", url.trim_end_matches('/'), path))
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        check(res).await
+    }
+
+    /// The servers listed by the root at `root`.
+    ///
+    /// A signed listing is checked against its signature, and must be signed by `root_key` when
+    /// one is given.
+    pub async fn servers(&self, root: &str, root_key: Option<&str>) -> Result<Vec<Server>, String> {
+        let res = self
+            .http
+            .get(format!("{}/", root.trim_end_matches('/')))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let res = check(res).await?;
+        let headers = res.headers().clone();
+        let body = res.bytes().await.map_err(|e| e.to_string())?;
+
+        let claimed = headers
+            .get(PUBLIC_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        if let Some(key) = root_key.or(claimed) {
+            signed_by(&headers, &body, key).map_err(|e| format!("the listing is {}", e))?;
+        }
+
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    }
+
+    /// Starts registration, the server then sends the user an OTP.
+    pub async fn register(
+        &self,
+        server: &Server,
+        email: &str,
+        secret_component: &str,
+        commitments: Option<&str>,
+        data: &Value,
+    ) -> Result<(), String> {
+        let body = serde_json::json!({
+            "email": email,
+            "secret_component": secret_component,
+            "commitments": commitments,
+            "data": data,
+        });
+
+        self.post(&server.url, "/register", &body).await.map(|_| ())
+    }
+
+    pub async fn verify_register(
+        &self,
+        server: &Server,
+        email: &str,
+        otp: &str,
+    ) -> Result<(), String> {
+        let body = serde_json::json!({ "email": email, "otp": otp });
+
+        self.post(&server.url, "/register/verify", &body)
+            .await
+            .map(|_| ())
+    }
+
+    /// Starts authentication, for example sending the user an OTP.
+    ///
+    /// Returns what the server answered, such as the questions a QA server asks, if anything.
+    pub async fn authenticate(&self, server: &Server, email: &str) -> Result<Value, String> {
+        let body = serde_json::json!({ "email": email });
+
+        let body = self
+            .post(&server.url, "/authenticate", &body)
+            .await?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    /// Verifies the user with `data`, releasing the server's `secret_component` and the
+    /// commitments it was registered with.
+    ///
+    /// Fails when the server is listed with a public key that did not sign what it released.
+    pub async fn verify_authentication(
+        &self,
+        server: &Server,
+        email: &str,
+        data: &Value,
+    ) -> Result<(String, Option<String>), String> {
+        let body = serde_json::json!({ "email": email, "data": data });
+
+        let res = self
+            .post(&server.url, "/authenticate/verify", &body)
+            .await?;
+        let headers = res.headers().clone();
+        let body = res.bytes().await.map_err(|e| e.to_string())?;
+
+        if let Some(public_key) = &server.public_key {
+            signed_by(&headers, &body, public_key)
+                .map_err(|e| format!("the share is {}, the key listed for the server", e))?;
+        }
+
+        let released: Released = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+
+        let secret_component = released
+            .secret_component
+            .ok_or("the server holds no share")?;
+
+        Ok((secret_component, released.commitments))
+    }
+}
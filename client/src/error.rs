@@ -0,0 +1,79 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server could not be reached, after retrying.
+    Transport(reqwest::Error),
+    /// The server rejected the request as malformed, for example a share that does not decode.
+    BadRequest(String),
+    /// The OTP, or the data of the factor, did not verify.
+    Unauthorized,
+    /// The user is not registered with the server.
+    NotFound,
+    /// Any other unsuccessful response, once retries are exhausted.
+    Status { status: StatusCode, message: String },
+    /// The response body is not what the endpoint returns.
+    Decode(String),
+}
+
+impl Error {
+    /// The error for an unsuccessful response with `body`.
+    pub(crate) fn from_response(status: StatusCode, body: &str) -> Self {
+        // Handlers reply with either plain text or a JSON string.
+        let message = serde_json::from_str::<String>(body).unwrap_or_else(|_| body.to_string());
+
+        match status {
+            StatusCode::BAD_REQUEST => Error::BadRequest(message),
+            StatusCode::UNAUTHORIZED => Error::Unauthorized,
+            StatusCode::NOT_FOUND => Error::NotFound,
+            status => Error::Status { status, message },
+        }
+    }
+
+    /// The status of the response, if the server replied.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Transport(e) => e.status(),
+            Error::BadRequest(_) => Some(StatusCode::BAD_REQUEST),
+            Error::Unauthorized => Some(StatusCode::UNAUTHORIZED),
+            Error::NotFound => Some(StatusCode::NOT_FOUND),
+            Error::Status { status, .. } => Some(*status),
+            Error::Decode(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "could not reach the server: {}", e),
+            Error::BadRequest(message) => write!(f, "bad request: {}", message),
+            Error::Unauthorized => write!(f, "not authorized"),
+            Error::NotFound => write!(f, "not found"),
+            Error::Status { status, message } if message.is_empty() => write!(f, "{}", status),
+            Error::Status { status, message } => write!(f, "{}: {}", status, message),
+            Error::Decode(e) => write!(f, "unexpected response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Error::Decode(e.to_string())
+        } else {
+            Error::Transport(e)
+        }
+    }
+}
@@ -0,0 +1,367 @@
+//! Typed async client for the authenticator servers.
+//!
+//! [`Client`] is generic over the kind of server, so the request bodies and the factor data are
+//! checked at compile time: a [`QaClient`] registers [`QuestionAnswer`]s, a [`PasswordClient`]
+//! a [`Pass`]. Requests failing to connect, or answered with `429` or `503`, are retried with
+//! exponential backoff, and unsuccessful responses are returned as an [`Error`].
+//!
+//! Only requests the server did not handle are retried, as most calls are not idempotent: a
+//! registration sends an OTP, a wrong answer counts towards a lockout. Timeouts and other gateway
+//! errors may come after the server handled the request, so they are returned instead.
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+
+// Generated code refers to this crate by name, also from within it.
+extern crate self as simple_syrup_client;
+
+mod error;
+pub mod openapi;
+pub mod types;
+
+pub use error::Error;
+pub use types::{
+    Authenticator, Pass, QaChallenge, QuestionAnswer, Released, ServerType, VerificationStatus,
+};
+
+pub type EmailClient = Client<types::EmailAuthenticator>;
+pub type QaClient = Client<types::QAAuthenticator>;
+pub type PasswordClient = Client<types::PasswordAuthenticator>;
+pub type BiometricClient = Client<types::BiometricAuthenticator>;
+
+/// Statuses with which a server refuses a request without handling it.
+const RETRY_STATUSES: [StatusCode; 2] = [
+    StatusCode::TOO_MANY_REQUESTS,
+    StatusCode::SERVICE_UNAVAILABLE,
+];
+
+/// How requests are retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    /// Attempts of each request, including the first.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled before each next one.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl Retry {
+    /// Sends every request once.
+    pub fn never() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::ZERO,
+        }
+    }
+}
+
+/// A client of the server at `url`, of kind `A`.
+pub struct Client<A> {
+    http: reqwest::Client,
+    url: String,
+    retry: Retry,
+    _server: PhantomData<fn() -> A>,
+}
+
+impl<A> Clone for Client<A> {
+    fn clone(&self) -> Self {
+        Self {
+            http: self.http.clone(),
+            url: self.url.clone(),
+            retry: self.retry,
+            _server: PhantomData,
+        }
+    }
+}
+
+impl<A: Authenticator> Client<A> {
+    /// A client of the server at `url`, including its mount prefix if any.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_http(reqwest::Client::new(), url)
+    }
+
+    /// Like [`Client::new`], sharing the connection pool and settings of `http`.
+    pub fn with_http(http: reqwest::Client, url: impl Into<String>) -> Self {
+        Self {
+            http,
+            url: url.into().trim_end_matches('/').to_string(),
+            retry: Retry::default(),
+            _server: PhantomData,
+        }
+    }
+
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<reqwest::Response, Error> {
+        let url = format!("{}{}", self.url, path);
+        let mut backoff = self.retry.backoff;
+
+        for attempt in 1.. {
+            let mut req = self.http.request(method.clone(), &url);
+            if let Some(body) = body {
+                req = req.json(body);
+            }
+            let res = req.send().await;
+
+            let retry = attempt < self.retry.attempts
+                && match &res {
+                    Ok(res) => RETRY_STATUSES.contains(&res.status()),
+                    Err(e) => e.is_connect(),
+                };
+            if retry {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                continue;
+            }
+
+            let res = res?;
+            if res.status().is_success() {
+                return Ok(res);
+            }
+
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            return Err(Error::from_response(status, &body));
+        }
+
+        unreachable!("requests are attempted until one is not retried")
+    }
+
+    async fn post<B: Serialize>(&self, path: &str, body: &B) -> Result<reqwest::Response, Error> {
+        self.send(Method::POST, path, Some(body)).await
+    }
+
+    /// The kind of the server.
+    pub async fn server_ty(&self) -> Result<ServerType, Error> {
+        Ok(self
+            .send::<()>(Method::GET, "/ty", None)
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Starts registering `secret_component`, the server then sends the user an OTP.
+    ///
+    /// `commitments` are required when `secret_component` is a verifiable share.
+    pub async fn register(
+        &self,
+        email: &str,
+        secret_component: &str,
+        commitments: Option<&str>,
+        data: A::Data,
+    ) -> Result<(), Error> {
+        let req = A::register_req(
+            email.into(),
+            secret_component.into(),
+            commitments.map(String::from),
+            data,
+        );
+
+        self.post("/register", &req).await.map(|_| ())
+    }
+
+    /// Completes registration with the OTP sent to the user.
+    pub async fn verify_register(&self, email: &str, otp: &str) -> Result<(), Error> {
+        let req = A::verify_register_req(email.into(), otp.into());
+
+        self.post("/register/verify", &req).await.map(|_| ())
+    }
+
+    /// Starts authentication, for example sending the user an OTP.
+    pub async fn authenticate(&self, email: &str) -> Result<(), Error> {
+        let req = A::auth_req(email.into());
+
+        self.post("/authenticate", &req).await.map(|_| ())
+    }
+
+    /// Verifies the user with `data`, returning the `secret_component` they registered, and the
+    /// commitments of a verifiable share.
+    pub async fn verify_authentication(
+        &self,
+        email: &str,
+        data: A::Data,
+    ) -> Result<Released, Error> {
+        let req = A::verify_auth_req(email.into(), data);
+
+        let released: Released = self
+            .post("/authenticate/verify", &req)
+            .await?
+            .json()
+            .await?;
+
+        match released.secret_component {
+            Some(_) => Ok(released),
+            None => Err(Error::Decode("no secret_component".into())),
+        }
+    }
+
+    /// The status of the user, `None` if they never registered.
+    pub async fn status(&self, email: &str) -> Result<Option<VerificationStatus>, Error> {
+        let req = A::auth_req(email.into());
+
+        let body = self.post("/status", &req).await?.text().await?;
+        if body.is_empty() {
+            return Ok(None);
+        }
+
+        serde_json::from_str(&body).map_err(|e| Error::Decode(e.to_string()))
+    }
+}
+
+impl Client<types::QAAuthenticator> {
+    /// Starts authentication like [`Client::authenticate`], returning the questions to answer.
+    pub async fn challenge(&self, email: &str) -> Result<QaChallenge, Error> {
+        let req = <types::QAAuthenticator as Authenticator>::auth_req(email.into());
+
+        Ok(self.post("/authenticate", &req).await?.json().await?)
+    }
+
+    /// Standard questions to offer users, who may also register their own.
+    pub async fn questions(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .send::<()>(Method::GET, "/questions", None)
+            .await?
+            .json()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+
+    /// A server failing its first `failures` requests to `/authenticate` with `status`.
+    fn flaky_server(failures: usize, status: StatusCode) -> (String, web::Data<AtomicUsize>) {
+        let requests = web::Data::new(AtomicUsize::new(0));
+
+        let data = requests.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route(
+                    "/authenticate",
+                    web::post().to(move |requests: web::Data<AtomicUsize>| async move {
+                        if requests.fetch_add(1, Ordering::SeqCst) < failures {
+                            HttpResponse::new(status)
+                        } else {
+                            HttpResponse::Ok().json("")
+                        }
+                    }),
+                )
+                .route(
+                    "/register",
+                    web::post().to(|| async {
+                        HttpResponse::BadRequest().body("secret_component is not a valid share")
+                    }),
+                )
+                .route(
+                    "/authenticate/verify",
+                    web::post().to(|| async { HttpResponse::Unauthorized().json("no rows") }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        (url, requests)
+    }
+
+    fn fast_retry() -> Retry {
+        Retry {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[actix_web::test]
+    async fn retries_unavailable_servers() {
+        let (url, requests) = flaky_server(2, StatusCode::SERVICE_UNAVAILABLE);
+        let client = EmailClient::new(url).retry(fast_retry());
+
+        client.authenticate("user@test").await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn gives_up_after_attempts() {
+        let (url, requests) = flaky_server(5, StatusCode::SERVICE_UNAVAILABLE);
+        let client = EmailClient::new(url).retry(fast_retry());
+
+        let e = client.authenticate("user@test").await.unwrap_err();
+        assert_eq!(e.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn handled_requests_are_not_retried() {
+        let (url, requests) = flaky_server(1, StatusCode::GATEWAY_TIMEOUT);
+        let client = EmailClient::new(url).retry(fast_retry());
+
+        let e = client.authenticate("user@test").await.unwrap_err();
+        assert_eq!(e.status(), Some(StatusCode::GATEWAY_TIMEOUT));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn structured_errors() {
+        let (url, _) = flaky_server(0, StatusCode::SERVICE_UNAVAILABLE);
+        let client = PasswordClient::new(url).retry(Retry::never());
+
+        let e = client
+            .register("user@test", "share", None, Pass::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, Error::BadRequest(message) if message == "secret_component is not a valid share")
+        );
+
+        let e = client
+            .verify_authentication("user@test", Pass::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::Unauthorized));
+
+        let e = client.status("user@test").await.unwrap_err();
+        assert!(matches!(e, Error::NotFound));
+    }
+
+    #[actix_web::test]
+    async fn unreachable_server() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let client = QaClient::new(url).retry(fast_retry());
+        let e = client.authenticate("user@test").await.unwrap_err();
+        assert!(matches!(e, Error::Transport(_)));
+    }
+}
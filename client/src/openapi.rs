@@ -0,0 +1,777 @@
+//! OpenAPI 3 description of the authenticator servers.
+//!
+//! `#[PassServer]` and `#[PassRequests]` implement [`Schema`] for the request types they generate,
+//! and [`Requests`] for the server, so [`document`] describes the routes of any kind of server.
+//! Routes a kind of server serves next to the generated ones, such as [`Questions`], are
+//! described by a [`Route`]. Servers serve their document at `/openapi.json`.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::types::{
+    AddDeviceRequest, Device, DevicesRequest, Pass, QaChallenge, QuestionAnswer, Released,
+    RevokeDeviceRequest, ServerType, SessionRequest, SessionResponse, VerificationStatus,
+};
+
+/// Schemas described once under `#/components/schemas`, and referenced.
+#[derive(Debug, Default)]
+pub struct Components(BTreeMap<&'static str, Value>);
+
+impl Components {
+    /// The schema of `T`, a reference to it if it is named.
+    pub fn reference<T: Schema + ?Sized>(&mut self) -> Value {
+        let name = match T::NAME {
+            Some(name) => name,
+            None => return T::schema(self),
+        };
+
+        if !self.0.contains_key(name) {
+            // Reserved first, so a type referring to itself does not recurse forever.
+            self.0.insert(name, Value::Null);
+            let schema = T::schema(self);
+            self.0.insert(name, schema);
+        }
+
+        json!({ "$ref": format!("#/components/schemas/{}", name) })
+    }
+}
+
+/// A type with an OpenAPI schema.
+pub trait Schema {
+    /// Name of the schema in the components, `None` to describe it inline.
+    const NAME: Option<&'static str> = None;
+
+    fn schema(components: &mut Components) -> Value;
+}
+
+/// The request types of a server.
+pub trait Requests {
+    type Register: Schema;
+    type VerifyRegister: Schema;
+    type Auth: Schema;
+    type VerifyAuth: Schema;
+    /// What `/authenticate` answers with, `()` for an empty response.
+    type Authenticated: Schema;
+
+    /// The routes served next to the generated ones, by path.
+    fn extra(_: &mut Components) -> Vec<(&'static str, Value)> {
+        vec![]
+    }
+
+    /// Scope of the routes, under the mount prefix of the server, prepended to the paths.
+    const PREFIX: &'static str = "";
+    /// Paths of the routes the server does not serve.
+    const EXCLUDED: &'static [&'static str] = &[];
+}
+
+/// A route a kind of server serves next to the generated ones.
+pub trait Route {
+    const PATH: &'static str;
+
+    /// The path item describing the route.
+    fn item(components: &mut Components) -> Value;
+}
+
+/// No body, as `/authenticate` answers most servers.
+impl Schema for () {
+    fn schema(_: &mut Components) -> Value {
+        Value::Null
+    }
+}
+
+impl Schema for String {
+    fn schema(_: &mut Components) -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl Schema for bool {
+    fn schema(_: &mut Components) -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+macro_rules! integer_schema {
+    ($($ty:ty),*) => {
+        $(
+            impl Schema for $ty {
+                fn schema(_: &mut Components) -> Value {
+                    json!({ "type": "integer", "minimum": <$ty>::MIN, "maximum": <$ty>::MAX })
+                }
+            }
+        )*
+    };
+}
+
+integer_schema!(u8, u16, u32, u64, usize, i8, i16, i32, i64);
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({ "type": "array", "items": components.reference::<T>() })
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    fn schema(components: &mut Components) -> Value {
+        let schema = components.reference::<T>();
+
+        // Siblings of a `$ref` are ignored, so a nullable reference is wrapped.
+        if schema.get("$ref").is_some() {
+            json!({ "allOf": [schema], "nullable": true })
+        } else {
+            let mut schema = schema;
+            schema["nullable"] = json!(true);
+            schema
+        }
+    }
+}
+
+impl Schema for QuestionAnswer {
+    const NAME: Option<&'static str> = Some("QuestionAnswer");
+
+    fn schema(components: &mut Components) -> Value {
+        json!({
+            "type": "object",
+            "required": ["question", "answer"],
+            "properties": {
+                "question": components.reference::<String>(),
+                "answer": components.reference::<String>(),
+            },
+        })
+    }
+}
+
+impl Schema for QaChallenge {
+    const NAME: Option<&'static str> = Some("QaChallenge");
+
+    fn schema(components: &mut Components) -> Value {
+        json!({
+            "type": "object",
+            "required": ["questions", "required"],
+            "properties": {
+                "questions": components.reference::<Vec<String>>(),
+                "required": components.reference::<usize>(),
+            },
+        })
+    }
+}
+
+impl Schema for Pass {
+    const NAME: Option<&'static str> = Some("Pass");
+
+    fn schema(components: &mut Components) -> Value {
+        json!({
+            "type": "object",
+            "required": ["password"],
+            "properties": {
+                "password": components.reference::<String>(),
+            },
+        })
+    }
+}
+
+/// An object schema with `fields`, each a name and whether it is required.
+macro_rules! object_schema {
+    ($($ty:ident { $($field:ident: $field_ty:ty),* $(,)? })*) => {
+        $(
+            impl Schema for $ty {
+                const NAME: Option<&'static str> = Some(stringify!($ty));
+
+                fn schema(components: &mut Components) -> Value {
+                    json!({
+                        "type": "object",
+                        "required": [$(stringify!($field)),*],
+                        "properties": {
+                            $(stringify!($field): components.reference::<$field_ty>(),)*
+                        },
+                    })
+                }
+            }
+        )*
+    };
+}
+
+object_schema! {
+    Device { device_id: String, label: String, revoked: bool }
+    SessionRequest { email: String }
+    SessionResponse { session: String }
+    DevicesRequest { email: String, session: String }
+    AddDeviceRequest { email: String, session: String, device_id: String, label: String }
+    RevokeDeviceRequest { email: String, session: String, device_id: String }
+}
+
+impl Schema for Released {
+    const NAME: Option<&'static str> = Some("Released");
+
+    fn schema(components: &mut Components) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "secret_component": components.reference::<Option<String>>(),
+                "commitments": components.reference::<Option<String>>(),
+            },
+        })
+    }
+}
+
+impl Schema for VerificationStatus {
+    const NAME: Option<&'static str> = Some("VerificationStatus");
+
+    fn schema(_: &mut Components) -> Value {
+        json!({
+            "type": "string",
+            "enum": ["Requested", "Verified", "RequestAuth"],
+        })
+    }
+}
+
+impl Schema for ServerType {
+    const NAME: Option<&'static str> = Some("ServerType");
+
+    fn schema(_: &mut Components) -> Value {
+        json!({
+            "type": "string",
+            "enum": ["Email", "QA", "Password", "Biometric"],
+        })
+    }
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// A response signed with the server's identity.
+fn signed_response(description: &str, schema: Value) -> Value {
+    let mut response = json_response(description, schema);
+    response["headers"] = json!({
+        "x-signature": {
+            "description": "Ed25519 signature of the body, in base64",
+            "schema": { "type": "string" },
+        },
+        "x-public-key": {
+            "description": "Public key the body was signed with, in base64",
+            "schema": { "type": "string" },
+        },
+    });
+    response
+}
+
+fn empty_response(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn post(summary: &str, body: Value, responses: Value) -> Value {
+    json!({
+        "post": {
+            "summary": summary,
+            "requestBody": json_body(body),
+            "responses": responses,
+        },
+    })
+}
+
+fn get(summary: &str, responses: Value) -> Value {
+    json!({
+        "get": {
+            "summary": summary,
+            "responses": responses,
+        },
+    })
+}
+
+/// A message signed by the identity of a peer, as the servers send each other.
+fn signed_message(message: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": ["message", "public_key", "signature"],
+        "properties": {
+            "message": message,
+            "public_key": { "type": "string" },
+            "signature": {
+                "type": "string",
+                "description": "Ed25519 signature of the JSON encoded message, in base64",
+            },
+        },
+    })
+}
+
+/// `GET /questions` of the QA servers.
+pub struct Questions;
+
+impl Route for Questions {
+    const PATH: &'static str = "/questions";
+
+    fn item(components: &mut Components) -> Value {
+        get(
+            "Standard security questions clients may offer users to register",
+            json!({
+                "200": json_response("The questions", components.reference::<Vec<String>>()),
+            }),
+        )
+    }
+}
+
+/// `POST /device/approval` of the biometric servers, pushed by the device API.
+pub struct DeviceApproval;
+
+impl Route for DeviceApproval {
+    const PATH: &'static str = "/device/approval";
+
+    fn item(_: &mut Components) -> Value {
+        post(
+            "Decision of a device on an authentication or a change to the devices, pushed by the device API",
+            json!({
+                "type": "object",
+                "required": ["approval", "signature"],
+                "properties": {
+                    "approval": {
+                        "type": "object",
+                        "required": ["deviceId", "requestId", "approved"],
+                        "properties": {
+                            "deviceId": { "type": "string" },
+                            "requestId": { "type": "string" },
+                            "approved": { "type": "boolean" },
+                        },
+                    },
+                    "signature": {
+                        "type": "string",
+                        "description": "Signature of the JSON encoded approval, by the key of the device API",
+                    },
+                },
+            }),
+            json!({
+                "200": empty_response("The decision was applied"),
+                "403": empty_response("The signature is not valid"),
+                "404": empty_response("No such request waits for the device, or approvals are not pushed"),
+            }),
+        )
+    }
+}
+
+/// `POST /devices/session` of the biometric servers.
+pub struct DevicesSession;
+
+impl Route for DevicesSession {
+    const PATH: &'static str = "/devices/session";
+
+    fn item(components: &mut Components) -> Value {
+        post(
+            "Opens a session managing the devices, once a device approved the authentication requested",
+            components.reference::<SessionRequest>(),
+            json!({
+                "200": json_response("The session", components.reference::<SessionResponse>()),
+                "401": empty_response("No device approved an authentication"),
+            }),
+        )
+    }
+}
+
+/// `POST /devices` of the biometric servers.
+pub struct Devices;
+
+impl Route for Devices {
+    const PATH: &'static str = "/devices";
+
+    fn item(components: &mut Components) -> Value {
+        post(
+            "The devices of the user, revoked ones included",
+            components.reference::<DevicesRequest>(),
+            json!({
+                "200": json_response("The devices", components.reference::<Vec<Device>>()),
+                "401": empty_response("The session is not open"),
+            }),
+        )
+    }
+}
+
+/// The responses of a change to the devices, which the devices are asked to approve.
+fn device_change() -> Value {
+    json!({
+        "200": empty_response("The active devices were asked to approve the change"),
+        "400": empty_response("The change is not valid"),
+        "401": empty_response("The session is not open"),
+        "409": empty_response("Another change waits for an approval"),
+    })
+}
+
+/// `POST /devices/add` of the biometric servers.
+pub struct AddDevice;
+
+impl Route for AddDevice {
+    const PATH: &'static str = "/devices/add";
+
+    fn item(components: &mut Components) -> Value {
+        post(
+            "Enrolls a device, once an active device approved it",
+            components.reference::<AddDeviceRequest>(),
+            device_change(),
+        )
+    }
+}
+
+/// `POST /devices/revoke` of the biometric servers.
+pub struct RevokeDevice;
+
+impl Route for RevokeDevice {
+    const PATH: &'static str = "/devices/revoke";
+
+    fn item(components: &mut Components) -> Value {
+        post(
+            "Revokes a device, once another active device approved it",
+            components.reference::<RevokeDeviceRequest>(),
+            device_change(),
+        )
+    }
+}
+
+/// `POST /devices/verify` of the biometric servers.
+pub struct VerifyDevices;
+
+impl Route for VerifyDevices {
+    const PATH: &'static str = "/devices/verify";
+
+    fn item(components: &mut Components) -> Value {
+        post(
+            "Applies the change waiting for the devices, once one of them approved it",
+            components.reference::<DevicesRequest>(),
+            json!({
+                "200": json_response("The devices", components.reference::<Vec<Device>>()),
+                "401": empty_response("The session is not open, or no device approved the change yet"),
+                "404": empty_response("No change waits for an approval"),
+            }),
+        )
+    }
+}
+
+/// The OpenAPI 3 document of a server with the requests of `R`, titled `title`.
+pub fn document<R: Requests>(title: &str) -> Value {
+    let mut components = Components::default();
+
+    let register = components.reference::<R::Register>();
+    let verify_register = components.reference::<R::VerifyRegister>();
+    let auth = components.reference::<R::Auth>();
+    let verify_auth = components.reference::<R::VerifyAuth>();
+    let authenticated = match components.reference::<R::Authenticated>() {
+        Value::Null => empty_response("Authentication requested"),
+        schema => json_response(
+            "Authentication requested, with what the user must authenticate with",
+            schema,
+        ),
+    };
+    let status = components.reference::<VerificationStatus>();
+    let server_ty = components.reference::<ServerType>();
+    let released = components.reference::<Released>();
+    let readiness = json!({
+        "type": "object",
+        "required": ["database", "migrations", "backend", "errors"],
+        "properties": {
+            "database": components.reference::<bool>(),
+            "migrations": components.reference::<bool>(),
+            "backend": components.reference::<bool>(),
+            "errors": components.reference::<Vec<String>>(),
+        },
+    });
+
+    let paths = json!({
+        "/register": post(
+            "Starts registering a share, emailing the user an OTP",
+            register,
+            json!({
+                "200": empty_response("OTP sent"),
+                "400": empty_response("The share is malformed or does not match the commitments"),
+            }),
+        ),
+        "/register/verify": post(
+            "Completes registration with the OTP",
+            verify_register,
+            json!({
+                "200": empty_response("Registered"),
+                "401": empty_response("Wrong or expired OTP"),
+            }),
+        ),
+        "/authenticate": post(
+            "Starts authentication, for example emailing the user an OTP",
+            auth.clone(),
+            json!({
+                "200": authenticated,
+                "401": empty_response("The user is not registered"),
+            }),
+        ),
+        "/authenticate/verify": post(
+            "Verifies the user, releasing their share",
+            verify_auth,
+            json!({
+                "200": signed_response("The share registered as secret_component, and the commitments of a verifiable share", released),
+                "401": empty_response("The data did not verify"),
+            }),
+        ),
+        "/status": post(
+            "Status of the user, an empty body if they are not registered",
+            auth,
+            json!({
+                "200": json_response("Status of the user", status),
+            }),
+        ),
+        "/ty": get(
+            "Kind of the server",
+            json!({
+                "200": json_response("Kind of the server", server_ty.clone()),
+            }),
+        ),
+        "/identity": get(
+            "Public key the server signs its responses with",
+            json!({
+                "200": json_response("Identity of the server", json!({
+                    "type": "object",
+                    "required": ["server_ty", "public_key"],
+                    "properties": {
+                        "server_ty": server_ty,
+                        "public_key": { "type": "string" },
+                    },
+                })),
+            }),
+        ),
+        "/openapi.json": get(
+            "This document",
+            json!({
+                "200": json_response("OpenAPI 3 document", json!({ "type": "object" })),
+            }),
+        ),
+        "/refresh/share": post(
+            "Index, threshold and epoch of the share of a user, asked by a peer dealing a refresh",
+            signed_message(json!({
+                "type": "object",
+                "required": ["email"],
+                "properties": {
+                    "email": { "type": "string", "description": "Hash of the email of the user" },
+                },
+            })),
+            json!({
+                "200": json_response("The public part of the share", json!({
+                    "type": "object",
+                    "required": ["index", "threshold", "epoch"],
+                    "properties": {
+                        "index": components.reference::<u8>(),
+                        "threshold": components.reference::<u8>(),
+                        "epoch": components.reference::<u32>(),
+                    },
+                })),
+                "403": empty_response("The message is not signed by a trusted peer"),
+                "404": empty_response("The server takes no part in refreshes, or holds no share of the user"),
+            }),
+        ),
+        "/refresh": post(
+            "Adds a share of zero dealt by a peer to the share of a user, once every trusted peer dealt theirs",
+            signed_message(json!({
+                "type": "object",
+                "required": ["email", "epoch", "delta"],
+                "properties": {
+                    "email": { "type": "string", "description": "Hash of the email of the user" },
+                    "epoch": components.reference::<u32>(),
+                    "delta": components.reference::<String>(),
+                    "commitments": components.reference::<Option<String>>(),
+                },
+            })),
+            json!({
+                "200": empty_response("The share was refreshed"),
+                "202": empty_response("The share of zero waits for those of the other peers"),
+                "400": empty_response("The share of zero is not valid"),
+                "403": empty_response("The message is not signed by a trusted peer"),
+                "404": empty_response("The server takes no part in refreshes, or holds no share of the user"),
+                "409": empty_response("The share of zero is not for the next epoch"),
+            }),
+        ),
+        "/readyz": get(
+            "Whether the database, its migrations and the backend of the server are ready",
+            json!({
+                "200": json_response("Ready", readiness.clone()),
+                "503": json_response("Not ready, with the failing checks in errors", readiness),
+            }),
+        ),
+        "/version": get(
+            "Build of the server",
+            json!({
+                "200": json_response("Version, commit and features of the build", json!({
+                    "type": "object",
+                    "required": ["version", "git_sha", "features", "server_ty"],
+                    "properties": {
+                        "version": components.reference::<String>(),
+                        "git_sha": components.reference::<String>(),
+                        "features": components.reference::<Vec<String>>(),
+                        "server_ty": server_ty.clone(),
+                    },
+                })),
+            }),
+        ),
+        "/metrics": get(
+            "Metrics of the server",
+            json!({
+                "200": {
+                    "description": "Metrics in the Prometheus text format",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            }),
+        ),
+    });
+    let mut paths = match paths {
+        Value::Object(paths) => paths,
+        _ => unreachable!("paths are an object"),
+    };
+
+    for (path, item) in R::extra(&mut components) {
+        paths.insert(path.into(), item);
+    }
+    for path in R::EXCLUDED {
+        paths.remove(*path);
+    }
+    let paths: serde_json::Map<String, Value> = paths
+        .into_iter()
+        .map(|(path, item)| (format!("{}{}", R::PREFIX, path), item))
+        .collect();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": title,
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": components.0 },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BiometricAuthenticator, EmailAuthenticator, QAAuthenticator};
+
+    #[test]
+    fn request_schemas() {
+        let spec = document::<QAAuthenticator>("QAAuthenticator");
+        let schemas = &spec["components"]["schemas"];
+
+        let register = &schemas["QAAuthenticatorRegisterReq"];
+        assert_eq!(
+            register["required"],
+            json!(["email", "secret_component", "data"])
+        );
+        assert_eq!(
+            register["properties"]["data"],
+            json!({
+                "type": "array",
+                "items": { "$ref": "#/components/schemas/QuestionAnswer" },
+            })
+        );
+        assert_eq!(
+            register["properties"]["commitments"],
+            json!({ "type": "string", "nullable": true })
+        );
+        assert_eq!(
+            schemas["QuestionAnswer"]["required"],
+            json!(["question", "answer"])
+        );
+
+        assert_eq!(
+            spec["paths"]["/register"]["post"]["requestBody"]["content"]["application/json"]
+                ["schema"],
+            json!({ "$ref": "#/components/schemas/QAAuthenticatorRegisterReq" })
+        );
+    }
+
+    #[test]
+    fn auth_responses() {
+        let spec = document::<QAAuthenticator>("QAAuthenticator");
+
+        assert_eq!(
+            spec["paths"]["/authenticate"]["post"]["responses"]["200"]["content"]
+                ["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/QaChallenge" })
+        );
+        assert_eq!(
+            spec["components"]["schemas"]["QaChallenge"]["required"],
+            json!(["questions", "required"])
+        );
+        assert_eq!(
+            spec["paths"]["/questions"]["get"]["responses"]["200"]["content"]["application/json"]
+                ["schema"],
+            json!({ "type": "array", "items": { "type": "string" } })
+        );
+
+        let spec = document::<EmailAuthenticator>("EmailAuthenticator");
+        let authenticated = &spec["paths"]["/authenticate"]["post"]["responses"]["200"];
+        assert!(authenticated.get("content").is_none());
+        assert!(spec["paths"].get("/questions").is_none());
+    }
+
+    #[test]
+    fn device_routes() {
+        let spec = document::<BiometricAuthenticator>("BiometricAuthenticator");
+        let paths = &spec["paths"];
+
+        for (path, request) in [
+            ("/devices/session", "SessionRequest"),
+            ("/devices", "DevicesRequest"),
+            ("/devices/add", "AddDeviceRequest"),
+            ("/devices/revoke", "RevokeDeviceRequest"),
+            ("/devices/verify", "DevicesRequest"),
+        ] {
+            assert_eq!(
+                paths[path]["post"]["requestBody"]["content"]["application/json"]["schema"],
+                json!({ "$ref": format!("#/components/schemas/{}", request) }),
+                "{}",
+                path
+            );
+        }
+        assert!(paths["/device/approval"].get("post").is_some());
+        assert_eq!(
+            spec["components"]["schemas"]["Device"]["required"],
+            json!(["device_id", "label", "revoked"])
+        );
+
+        let spec = document::<QAAuthenticator>("QAAuthenticator");
+        assert!(spec["paths"].get("/devices").is_none());
+    }
+
+    #[test]
+    fn ignored_data_is_not_sent() {
+        let spec = document::<EmailAuthenticator>("EmailAuthenticator");
+        let schemas = &spec["components"]["schemas"];
+
+        let register = &schemas["EmailAuthenticatorRegisterReq"];
+        assert_eq!(register["required"], json!(["email", "secret_component"]));
+        assert!(register["properties"].get("data").is_none());
+
+        assert_eq!(
+            schemas["EmailAuthenticatorVerifyAuthReq"]["properties"]["data"],
+            json!({ "type": "string" })
+        );
+    }
+
+    #[test]
+    fn released_share() {
+        let spec = document::<EmailAuthenticator>("EmailAuthenticator");
+
+        let verified = &spec["paths"]["/authenticate/verify"]["post"]["responses"]["200"];
+        assert_eq!(
+            verified["content"]["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/Released" })
+        );
+        assert!(verified["headers"].get("x-signature").is_some());
+        assert_eq!(
+            spec["components"]["schemas"]["Released"]["properties"]["commitments"],
+            json!({ "type": "string", "nullable": true })
+        );
+        assert!(spec["paths"].get("/commitments").is_none());
+    }
+}
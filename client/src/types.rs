@@ -0,0 +1,165 @@
+//! Types exchanged with the authenticator servers.
+//!
+//! The request types are generated by [`derive::PassRequests`], and the servers' `#[PassServer]`
+//! deserializes these same types, so they match what each server expects.
+
+use derive::PassRequests;
+use serde::{Deserialize, Serialize};
+
+/// Where a user is in registration and authentication, as returned by `/status`.
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum VerificationStatus {
+    Requested,
+    Verified,
+    RequestAuth,
+}
+
+/// The kind of an authenticator server, as returned by `/ty` and listed by the root.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ServerType {
+    Email,
+    QA,
+    Password,
+    Biometric,
+}
+
+/// A security question and its answer. Users register several, and answer some of them.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Default)]
+pub struct QuestionAnswer {
+    pub question: String,
+    pub answer: String,
+}
+
+/// The questions a QA server asks, as returned by `/authenticate`.
+///
+/// Each is answered with a [`QuestionAnswer`] repeating it, and `required` of them must be
+/// answered correctly.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Default)]
+pub struct QaChallenge {
+    pub questions: Vec<String>,
+    pub required: usize,
+}
+
+/// What `/authenticate/verify` releases to a verified user, signed by the server.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Default)]
+pub struct Released {
+    pub secret_component: Option<String>,
+    /// The commitments a verifiable share was registered with, to check it against.
+    pub commitments: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Default)]
+pub struct Pass {
+    pub password: String,
+}
+
+/// A device a user authenticates with on a biometric server.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Device {
+    pub device_id: String,
+    /// Empty for the device the user registered with.
+    pub label: String,
+    /// Revoked devices are kept, so that they cannot be enrolled again without an approval.
+    pub revoked: bool,
+}
+
+/// Opens a session managing the devices of a user, with `/devices/session`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionResponse {
+    /// Sent with the requests managing the devices, until the session expires.
+    pub session: String,
+}
+
+/// Lists the devices with `/devices`, or applies an approved change with `/devices/verify`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevicesRequest {
+    pub email: String,
+    pub session: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddDeviceRequest {
+    pub email: String,
+    pub session: String,
+    pub device_id: String,
+    pub label: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokeDeviceRequest {
+    pub email: String,
+    pub session: String,
+    pub device_id: String,
+}
+
+/// How the servers of a kind keep the data users register with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Store {
+    /// Not sent when registering.
+    Ignored,
+    Hashed,
+    Stored,
+}
+
+#[PassRequests(data(String), store(Ignored), ty(ServerType::Email))]
+pub struct EmailAuthenticator;
+
+#[PassRequests(
+    data(Vec<QuestionAnswer>),
+    store(Stored),
+    ty(ServerType::QA),
+    authenticated(QaChallenge),
+    extra(crate::openapi::Questions)
+)]
+pub struct QAAuthenticator;
+
+#[PassRequests(data(Pass), store(Hashed), ty(ServerType::Password))]
+pub struct PasswordAuthenticator;
+
+#[PassRequests(
+    data(String),
+    store(Stored),
+    ty(ServerType::Biometric),
+    extra(
+        crate::openapi::DeviceApproval,
+        crate::openapi::DevicesSession,
+        crate::openapi::Devices,
+        crate::openapi::AddDevice,
+        crate::openapi::RevokeDevice,
+        crate::openapi::VerifyDevices
+    )
+)]
+pub struct BiometricAuthenticator;
+
+/// The request types of one kind of server, implemented by [`derive::PassRequests`].
+pub trait Authenticator {
+    const SERVER_TY: ServerType;
+    const STORE: Store;
+
+    /// What the user registers, and authenticates with.
+    type Data: Serialize;
+
+    type RegisterReq: Serialize;
+    type VerifyRegisterReq: Serialize;
+    type AuthReq: Serialize;
+    type VerifyAuthReq: Serialize;
+
+    fn register_req(
+        email: String,
+        secret_component: String,
+        commitments: Option<String>,
+        data: Self::Data,
+    ) -> Self::RegisterReq;
+
+    fn verify_register_req(email: String, otp: String) -> Self::VerifyRegisterReq;
+
+    fn auth_req(email: String) -> Self::AuthReq;
+
+    fn verify_auth_req(email: String, data: Self::Data) -> Self::VerifyAuthReq;
+}
@@ -52,6 +52,14 @@ const setEnvs = async (activeServers, {name, ty}, stage) => {
         return exec(`heroku config:set -a ${name} SENDGRID_KEY=${SENDGRID_KEY}`)
     })
     .then(console.log)
+    .then(() => exec(`heroku config:get -a ${name} IDENTITY_SECRET_KEY`))
+    .then(({stdout}) => {
+        // Kept across deploys, as other servers and clients trust its public key
+        if (stdout.trim()) return
+        const key = require('crypto').randomBytes(32).toString('base64')
+        return exec(`heroku config:set -a ${name} IDENTITY_SECRET_KEY=${key}`)
+    })
+    .then(console.log)
     .then(() => {
         switch (ty) {
             case 'Email':
@@ -1,8 +1,12 @@
 
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
-use syn::{parse_macro_input, Attribute, AttributeArgs, Ident, Meta, NestedMeta, Data, DeriveInput, Field, Lit};
+use proc_macro2::Span;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parenthesized, parse_macro_input, Attribute, Data, DeriveInput, Field, Fields, GenericParam, Generics, Ident, LitBool, LitStr, Path, Token, Type, Visibility};
 
+mod openapi;
 mod server;
 mod tests;
 
@@ -21,146 +25,469 @@ pub(crate) struct DerivedRequest {
 
 #[derive(Debug)]
 pub(crate) struct Idents {
-    base: Ident,
+    base: Type,
     request_register: Ident,
     verify_register: Ident,
     request_auth: Ident,
     verify_auth: Ident,
 }
 
+/// The request types a `PassServer` deserializes, generated by `#[PassRequests]` on `path`.
+#[derive(Debug)]
+pub(crate) struct ServerRequests {
+    pub(crate) path: Path,
+    /// How the server keeps the data, which must agree with the requests.
+    pub(crate) data_storage_ty: DataStorage,
+}
+
+impl ServerRequests {
+    /// The item `name` of the `Authenticator` implementation of the requests.
+    pub(crate) fn ty(&self, name: &str) -> TokenStream2 {
+        let path = &self.path;
+        let name = Ident::new(name, Span::call_site());
+
+        quote::quote! { <#path as simple_syrup_client::Authenticator>::#name }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct DeriveData {
     pub(crate) ident: Ident,
+    pub(crate) vis: Visibility,
+    pub(crate) generics: Generics,
     pub(crate) sub_attrs: Vec<Attribute>,
     pub(crate) fields: Vec<Field>,
-    pub(crate) request: DerivedRequest,
-    pub(crate) server_ty: NestedMeta,
+    pub(crate) request: ServerRequests,
+    pub(crate) server_ty: TokenStream2,
+    pub(crate) routes: Routes,
     pub(crate) ignore_tests: bool,
 }
 
-impl From<(Vec<NestedMeta>, TokenStream)> for DeriveData {
-    fn from(tokens: (Vec<NestedMeta>, TokenStream)) -> Self {
-        let (args, tok) = tokens;
-        let DeriveInput {
-            data, ident, attrs, ..
-        } = syn::parse(tok).unwrap();
+/// Which routes `configure` registers, and where.
+#[derive(Debug, Default)]
+pub(crate) struct Routes {
+    /// Scope of the routes, under the mount prefix of the server.
+    pub(crate) prefix: Option<LitStr>,
+    /// Names of the generated routes left out, from `server::ROUTES`.
+    pub(crate) exclude: Vec<Ident>,
+    /// Services defined next to the struct, registered with the generated routes.
+    pub(crate) extra: Vec<Path>,
+}
+
+/// The arguments of `#[PassServer(...)]` and `#[PassRequests(...)]`.
+#[derive(Default)]
+struct Args {
+    requests: Option<Path>,
+    data: Option<Type>,
+    store: Option<DataStorage>,
+    ty: Option<Path>,
+    authenticated: Option<Type>,
+    extra: Vec<Path>,
+    prefix: Option<LitStr>,
+    routes: Option<Routes>,
+    ignore_tests: bool,
+}
+
+/// One `key(...)` argument, before its value is parsed for the key.
+struct RawArg {
+    key: Ident,
+    /// What is in the parentheses, `None` if the key is not followed by any.
+    value: Option<TokenStream2>,
+    /// The whole argument, for errors.
+    tokens: TokenStream2,
+}
+
+impl Parse for RawArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let fork = input.fork();
+        let key: Ident = input.parse()?;
 
-        let fields = match data {
-            Data::Struct(data) => data.fields.into_iter().collect(),
-            _ => unimplemented!("DeriveData is only for a struct"),
+        let value = if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            Some(content.parse()?)
+        } else {
+            // Skipped up to the next argument, so the error covers the whole of it.
+            while !input.is_empty() && !input.peek(Token![,]) {
+                input.parse::<proc_macro2::TokenTree>()?;
+            }
+            None
         };
 
-        let mut data: Option<Ident> = None;
-        let mut store: Option<DataStorage> = None;
-        let mut ty: Option<NestedMeta> = None;
-        let mut ignore_tests: bool = false;
-
-        args.iter().for_each(|v| match v {
-            NestedMeta::Meta(meta) => {
-                let name = meta.path().get_ident().unwrap();
-                match name.to_string().as_str() {
-                    "data" => {
-                        let nested = match meta {
-                            Meta::List(list) => &list.nested,
-                            _ => panic!("data must be a list"),
-                        };
-
-                        let first = nested.first();
-                        data = first.map(|nested| match nested {
-                            NestedMeta::Lit(_) => panic!("data must be an identifier"),
-                            NestedMeta::Meta(meta) => meta.path().get_ident().unwrap().clone(),
-                        });
-                    }
-                    "store" => {
-                        let nested = match meta {
-                            Meta::List(list) => &list.nested,
-                            _ => panic!("data must be a list"),
-                        };
-
-                        let first = nested.first();
-                        store = first.map(|nested| match nested {
-                            NestedMeta::Lit(_) => panic!("data must be an identifier"),
-                            NestedMeta::Meta(meta) => {
-                                match meta.path().get_ident().unwrap().to_string().as_str() {
-                                    "Ignored" => DataStorage::Ignored,
-                                    "Hashed" => DataStorage::Hashed,
-                                    "Stored" => DataStorage::Stored,
-                                    _ => unimplemented!("That period is not supported (support: Ignored, Hashed, Stored)"),
-                                }
-                            }
-                        });
+        let mut tokens = TokenStream2::new();
+        while !fork.is_empty() && !fork.peek(Token![,]) {
+            tokens.extend(std::iter::once(fork.parse::<proc_macro2::TokenTree>()?));
+        }
+
+        Ok(Self { key, value, tokens })
+    }
+}
+
+/// Parses the `value` of an argument as `T`, or fails with `expected`.
+fn parse_value<T: Parse>(arg: &RawArg, example: &str, expected: &str) -> syn::Result<T> {
+    let value = arg.value.as_ref().ok_or_else(|| {
+        syn::Error::new_spanned(&arg.tokens, format!("expected `{}({})`", arg.key, example))
+    })?;
+
+    if value.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &arg.tokens,
+            format!("expected `{}({})`", arg.key, example),
+        ));
+    }
+
+    syn::parse2(value.clone()).map_err(|_| syn::Error::new_spanned(value, expected))
+}
+
+impl Args {
+    /// Parses `args`, which may only use the keys in `allowed`.
+    fn parse(args: Vec<RawArg>, allowed: &[&str]) -> syn::Result<Self> {
+        let mut parsed = Args::default();
+        let mut seen: Vec<String> = vec![];
+        let mut errors: Option<syn::Error> = None;
+
+        for arg in &args {
+            if let Err(e) = parsed.parse_arg(arg, allowed, &mut seen) {
+                match &mut errors {
+                    Some(errors) => errors.combine(e),
+                    None => errors = Some(e),
+                }
+            }
+        }
+
+        match errors {
+            Some(errors) => Err(errors),
+            None => Ok(parsed),
+        }
+    }
+
+    fn parse_arg(&mut self, arg: &RawArg, allowed: &[&str], seen: &mut Vec<String>) -> syn::Result<()> {
+        let key = arg.key.to_string();
+        if !allowed.contains(&key.as_str()) {
+            let expected = allowed
+                .iter()
+                .map(|key| format!("`{}`", key))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            return Err(syn::Error::new_spanned(
+                &arg.key,
+                format!("unknown argument `{}`, expected one of {}", key, expected),
+            ));
+        }
+        if seen.contains(&key) {
+            return Err(syn::Error::new_spanned(
+                &arg.key,
+                format!("`{}` is given more than once", key),
+            ));
+        }
+        seen.push(key.clone());
+
+        match key.as_str() {
+            "requests" => {
+                self.requests = Some(parse_value(
+                    arg,
+                    "simple_syrup_client::types::QAAuthenticator",
+                    "expected a path to a `#[PassRequests]` struct, such as `simple_syrup_client::types::QAAuthenticator`",
+                )?);
+            }
+            "data" => {
+                self.data = Some(parse_value(arg, "String", "expected a type, such as `String`")?);
+            }
+            "store" => {
+                let store: Ident = parse_value(arg, "Hashed", "expected an identifier, such as `Hashed`")?;
+                self.store = Some(match store.to_string().as_str() {
+                    "Ignored" => DataStorage::Ignored,
+                    "Hashed" => DataStorage::Hashed,
+                    "Stored" => DataStorage::Stored,
+                    other => {
+                        return Err(syn::Error::new_spanned(
+                            store,
+                            format!(
+                                "unknown storage `{}`, expected `Ignored`, `Hashed` or `Stored`",
+                                other
+                            ),
+                        ))
                     }
-                    "ty" => {
-                        let nested = match meta {
-                            Meta::List(list) => &list.nested,
-                            _ => panic!("data must be a list"),
-                        };
-
-                        let first = nested.first();
-                        ty = first.map(|nested| match nested {
-                            NestedMeta::Lit(_) => panic!("data must be an identifier"),
-                            NestedMeta::Meta(_) => nested.clone() 
-                        });
-                    },
-                    "ignore_tests" => {
-                        let nested = match meta {
-                            Meta::List(list) => &list.nested,
-                            _ => panic!("data must be a list"),
-                        };
-
-                        let first = nested.first();
-                        ignore_tests = first.map(|f| {
-                            match f {
-                                NestedMeta::Lit(bool) => {
-                                    match bool {
-                                        Lit::Bool(v) => v.value,
-                                        _ => panic!("data must be a bool"),
-                                    }
-                                },
-                                _ => panic!("data must be a bool literal"),
-                            }
-                        }).unwrap_or_default();
+                });
+            }
+            "ty" => {
+                self.ty = Some(parse_value(
+                    arg,
+                    "ServerType::QA",
+                    "expected a path to the server type, such as `ServerType::QA`",
+                )?);
+            }
+            "authenticated" => {
+                self.authenticated = Some(parse_value(arg, "QaChallenge", "expected a type, such as `QaChallenge`")?);
+            }
+            "extra" => {
+                let List(routes) = parse_value::<List<Path>>(
+                    arg,
+                    "Questions",
+                    "expected the paths of `Route` descriptions, such as `Questions`",
+                )?;
+                self.extra = routes;
+            }
+            "prefix" => {
+                let prefix: LitStr = parse_value(arg, "\"/qa\"", "expected a string, such as `\"/qa\"`")?;
+                let value = prefix.value();
+                if !value.starts_with('/') || value.ends_with('/') {
+                    return Err(syn::Error::new_spanned(
+                        prefix,
+                        "the prefix starts with a `/` and does not end with one, such as `\"/qa\"`",
+                    ));
+                }
+                self.prefix = Some(prefix);
+            }
+            "routes" => {
+                self.routes = Some(parse_routes(arg)?);
+            }
+            "ignore_tests" => {
+                let value: LitBool = parse_value(arg, "true", "expected `true` or `false`")?;
+                self.ignore_tests = value.value;
+            }
+            _ => unreachable!("allowed keys are all parsed"),
+        }
+
+        Ok(())
+    }
+
+    /// `value`, or an error naming the missing `key`.
+    fn required<T>(value: Option<T>, key: &str, example: &str) -> syn::Result<T> {
+        value.ok_or_else(|| {
+            syn::Error::new(
+                Span::call_site(),
+                format!("missing `{}`, such as `{}({})`", key, key, example),
+            )
+        })
+    }
+}
+
+/// `routes(exclude(status, ...), extra(handler, ...))`.
+fn parse_routes(arg: &RawArg) -> syn::Result<Routes> {
+    let RawArgs(args) = parse_value(arg, "exclude(status)", "expected `exclude(...)` or `extra(...)`")?;
+
+    let mut routes = Routes::default();
+    let mut seen: Vec<String> = vec![];
+    for arg in args {
+        let key = arg.key.to_string();
+        if seen.contains(&key) {
+            return Err(syn::Error::new_spanned(
+                &arg.key,
+                format!("`{}` is given more than once", key),
+            ));
+        }
+        seen.push(key.clone());
+
+        match key.as_str() {
+            "exclude" => {
+                let List(names) = parse_value::<List<Ident>>(
+                    &arg,
+                    "status",
+                    "expected the names of generated routes, such as `status`",
+                )?;
+
+                for name in names {
+                    if !server::ROUTES.iter().any(|(route, _, _)| name == route) {
+                        let expected = server::ROUTES
+                            .iter()
+                            .map(|(route, _, _)| format!("`{}`", route))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+                        return Err(syn::Error::new_spanned(
+                            &name,
+                            format!("unknown route `{}`, expected one of {}", name, expected),
+                        ));
                     }
-                    _ => unimplemented!(),
+                    routes.exclude.push(name);
                 }
             }
-            _ => unimplemented!(),
-        });
+            "extra" => {
+                let List(handlers) = parse_value::<List<Path>>(
+                    &arg,
+                    "questions",
+                    "expected the paths of services, such as `questions`",
+                )?;
 
+                routes.extra.extend(handlers);
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &arg.key,
+                    format!("unknown argument `{}`, expected `exclude` or `extra`", key),
+                ))
+            }
+        }
+    }
+
+    Ok(routes)
+}
+
+/// A comma separated list.
+struct List<T>(Vec<T>);
+
+impl<T: Parse> Parse for List<T> {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let items = Punctuated::<T, Token![,]>::parse_terminated(input)?;
+
+        Ok(Self(items.into_iter().collect()))
+    }
+}
+
+/// The comma separated arguments of an attribute.
+struct RawArgs(Vec<RawArg>);
+
+impl Parse for RawArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let args = Punctuated::<RawArg, Token![,]>::parse_terminated(input)?;
+
+        Ok(Self(args.into_iter().collect()))
+    }
+}
+
+impl DerivedRequest {
+    fn new(ident: &Ident, data: Type, store: DataStorage) -> Self {
         let request_register = Ident::new(&format!("{}RegisterReq", ident), ident.span());
         let verify_register = Ident::new(&format!("{}VerifyRegisterReq", ident), ident.span());
         let request_auth = Ident::new(&format!("{}AuthReq", ident), ident.span());
         let verify_auth = Ident::new(&format!("{}VerifyAuthReq", ident), ident.span());
 
         let idents = Idents {
-            base: data.expect("Must provide a Request Data Type!"),
+            base: data,
             request_register,
             verify_register,
             request_auth,
             verify_auth,
         };
 
-        let request = DerivedRequest {
+        Self {
             idents,
-            data_storage_ty: store.expect("Must provide a Data Storage Type!"),
-        };
+            data_storage_ty: store,
+        }
+    }
+}
 
-        Self {
+/// The struct a macro is applied to, rejecting enums and unions.
+fn parse_struct(input: TokenStream, macro_name: &str) -> syn::Result<(DeriveInput, Fields)> {
+    let input: DeriveInput = syn::parse(input)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => data.fields.clone(),
+        Data::Enum(data) => {
+            return Err(syn::Error::new_spanned(
+                data.enum_token,
+                format!("{} can only be applied to a struct", macro_name),
+            ))
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                format!("{} can only be applied to a struct", macro_name),
+            ))
+        }
+    };
+
+    Ok((input, fields))
+}
+
+impl DeriveData {
+    fn parse(args: Vec<RawArg>, input: TokenStream) -> syn::Result<Self> {
+        let (
+            DeriveInput {
+                ident,
+                vis,
+                generics,
+                attrs,
+                ..
+            },
             fields,
+        ) = parse_struct(input, "PassServer")?;
+
+        if let Fields::Unnamed(fields) = &fields {
+            return Err(syn::Error::new_spanned(
+                fields,
+                "PassServer structs must have named fields, as a `base` field is added",
+            ));
+        }
+
+        // The handlers are not generic, they get the struct with its default parameters.
+        for param in &generics.params {
+            match param {
+                GenericParam::Type(param) if param.default.is_none() => {
+                    return Err(syn::Error::new_spanned(
+                        param,
+                        "type parameters of PassServer structs need a default, used by the generated handlers",
+                    ))
+                }
+                GenericParam::Lifetime(param) => {
+                    return Err(syn::Error::new_spanned(
+                        param,
+                        "PassServer structs are shared between requests, so cannot borrow",
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        let Args {
+            requests,
+            store,
+            prefix,
+            routes,
+            ignore_tests,
+            ..
+        } = Args::parse(args, &["requests", "store", "prefix", "routes", "ignore_tests"])?;
+
+        let requests = Args::required(requests, "requests", "simple_syrup_client::types::QAAuthenticator")?;
+        let store = Args::required(store, "store", "Hashed")?;
+
+        let routes = Routes {
+            prefix,
+            ..routes.unwrap_or_default()
+        };
+        if (routes.prefix.is_some() || !routes.exclude.is_empty()) && !ignore_tests {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "`prefix` and `routes(exclude(...))` need `ignore_tests(true)`, as the generated tests call the default routes",
+            ));
+        }
+
+        let request = ServerRequests {
+            path: requests,
+            data_storage_ty: store,
+        };
+        let server_ty = request.ty("SERVER_TY");
+
+        Ok(Self {
+            fields: fields.into_iter().collect(),
             sub_attrs: attrs,
             ident,
+            vis,
+            generics,
             request,
-            server_ty: ty.expect("Must provide a Server Type!"),
-            ignore_tests
-        }
+            server_ty,
+            routes,
+            ignore_tests,
+        })
     }
 }
 
+/// Generates the handlers of an authenticator server, and `configure` registering them.
+///
+/// The server deserializes the request types of `requests(...)`, a `#[PassRequests]` struct, and
+/// is of its server type. `store(...)` is how the server keeps the data, which must be the `store`
+/// of the requests. `prefix("/qa")` scopes the routes, `routes(exclude(status))` leaves generated routes out and
+/// `routes(extra(questions))` registers services defined next to the struct with them.
 #[proc_macro_attribute]
 #[allow(non_snake_case)]
 pub fn PassServer(attr: TokenStream, input: TokenStream) -> TokenStream {
-    let args = parse_macro_input!(attr as AttributeArgs);
-    let derived = (args, input).into();
+    let RawArgs(args) = parse_macro_input!(attr as RawArgs);
+    let derived = match DeriveData::parse(args, input) {
+        Ok(derived) => derived,
+        Err(e) => return e.to_compile_error().into(),
+    };
     let server = server::derive(&derived);
 
     let tests = if derived.ignore_tests {
@@ -180,3 +507,58 @@ pub fn PassServer(attr: TokenStream, input: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+/// Generates the request types of a kind of server, named like the struct, and implements
+/// `Authenticator` with them.
+///
+/// `data(...)` is what users register and authenticate with, `store(...)` how the servers keep it
+/// and `ty(...)` their server type. The servers deserialize these types, with `#[PassServer]`, so
+/// clients build exactly the bodies the servers expect.
+///
+/// `authenticated(QaChallenge)` is what `/authenticate` answers with, and `extra(Questions)`
+/// describes the routes the servers serve next to the generated ones, for their OpenAPI documents.
+#[proc_macro_attribute]
+#[allow(non_snake_case)]
+pub fn PassRequests(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let RawArgs(args) = parse_macro_input!(attr as RawArgs);
+
+    let parsed = parse_struct(input, "PassRequests").and_then(|(item, _)| {
+        let Args {
+            data,
+            store,
+            ty,
+            authenticated,
+            extra,
+            ..
+        } = Args::parse(args, &["data", "store", "ty", "authenticated", "extra"])?;
+
+        Ok((
+            item,
+            Args::required(data, "data", "String")?,
+            Args::required(store, "store", "Hashed")?,
+            Args::required(ty, "ty", "ServerType::QA")?,
+            authenticated,
+            extra,
+        ))
+    });
+    let (item, data, store, server_ty, authenticated, extra) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let request = DerivedRequest::new(&item.ident, data, store);
+    let requests = server::derive_requests(&request);
+    let authenticator = server::derive_authenticator(&item.ident, &request, &server_ty);
+    let schemas = openapi::derive_schemas(&item.ident, &item.generics, &request, authenticated.as_ref(), &extra);
+
+    quote::quote! {
+        #item
+
+        #requests
+
+        #authenticator
+
+        #schemas
+    }
+    .into()
+}
@@ -0,0 +1,153 @@
+use crate::DataStorage;
+
+use super::{DeriveData, DerivedRequest, Idents};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Generics, Ident, Path, Type};
+
+/// Implements `Schema` for a request type with `fields`, each a name, type and whether it is required.
+fn derive_schema(ident: &Ident, fields: &[(&str, TokenStream2, bool)]) -> TokenStream2 {
+    let required = fields
+        .iter()
+        .filter(|(_, _, required)| *required)
+        .map(|(name, _, _)| name);
+    let names = fields.iter().map(|(name, _, _)| name);
+    let types = fields.iter().map(|(_, ty, _)| ty);
+
+    quote! {
+        impl simple_syrup_client::openapi::Schema for #ident {
+            const NAME: Option<&'static str> = Some(stringify!(#ident));
+
+            fn schema(components: &mut simple_syrup_client::openapi::Components) -> serde_json::Value {
+                serde_json::json!({
+                    "type": "object",
+                    "required": [#(#required),*],
+                    "properties": {
+                        #(#names: components.reference::<#types>(),)*
+                    },
+                })
+            }
+        }
+    }
+}
+
+/// OpenAPI schemas of the request types of `ident`, generated by `derive_requests`, with what
+/// `/authenticate` answers and the `extra` routes.
+pub(crate) fn derive_schemas(
+    ident: &Ident,
+    generics: &Generics,
+    request: &DerivedRequest,
+    authenticated: Option<&Type>,
+    extra: &[Path],
+) -> TokenStream2 {
+    let Idents {
+        request_auth,
+        request_register,
+        verify_register,
+        verify_auth,
+        base,
+    } = &request.idents;
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let authenticated = authenticated.map_or_else(|| quote! { () }, |ty| quote! { #ty });
+    let string = quote! { String };
+
+    let mut register_fields = vec![
+        ("email", string.clone(), true),
+        ("secret_component", string.clone(), true),
+        ("commitments", quote! { Option<String> }, false),
+    ];
+    match request.data_storage_ty {
+        DataStorage::Stored | DataStorage::Hashed => {
+            register_fields.push(("data", quote! { #base }, true))
+        }
+        DataStorage::Ignored => {}
+    }
+
+    let register = derive_schema(request_register, &register_fields);
+    let ver_register = derive_schema(
+        verify_register,
+        &[("email", string.clone(), true), ("otp", string.clone(), true)],
+    );
+    let auth = derive_schema(request_auth, &[("email", string.clone(), true)]);
+    let ver_auth = derive_schema(
+        verify_auth,
+        &[("email", string, true), ("data", quote! { #base }, true)],
+    );
+
+    quote! {
+        #register
+
+        #ver_register
+
+        #auth
+
+        #ver_auth
+
+        impl #impl_generics simple_syrup_client::openapi::Requests for #ident #ty_generics #where_clause {
+            type Register = #request_register;
+            type VerifyRegister = #verify_register;
+            type Auth = #request_auth;
+            type VerifyAuth = #verify_auth;
+            type Authenticated = #authenticated;
+
+            fn extra(components: &mut simple_syrup_client::openapi::Components) -> Vec<(&'static str, serde_json::Value)> {
+                vec![#((
+                    <#extra as simple_syrup_client::openapi::Route>::PATH,
+                    <#extra as simple_syrup_client::openapi::Route>::item(components),
+                )),*]
+            }
+        }
+    }
+}
+
+/// `Requests` of a server: the request types it references, under its own routes.
+pub(crate) fn derive_server_requests(input: &DeriveData) -> TokenStream2 {
+    let DeriveData {
+        ident,
+        generics,
+        request,
+        routes,
+        ..
+    } = input;
+    let requests = &request.path;
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let prefix = routes.prefix.as_ref().map(|prefix| prefix.value()).unwrap_or_default();
+    let excluded = crate::server::ROUTES
+        .iter()
+        .filter(|(name, _, _)| routes.exclude.iter().any(|excluded| excluded == name))
+        .flat_map(|(_, _, paths)| paths.iter());
+
+    quote! {
+        impl #impl_generics simple_syrup_client::openapi::Requests for #ident #ty_generics #where_clause {
+            type Register = <#requests as simple_syrup_client::openapi::Requests>::Register;
+            type VerifyRegister = <#requests as simple_syrup_client::openapi::Requests>::VerifyRegister;
+            type Auth = <#requests as simple_syrup_client::openapi::Requests>::Auth;
+            type VerifyAuth = <#requests as simple_syrup_client::openapi::Requests>::VerifyAuth;
+            type Authenticated = <#requests as simple_syrup_client::openapi::Requests>::Authenticated;
+
+            fn extra(components: &mut simple_syrup_client::openapi::Components) -> Vec<(&'static str, serde_json::Value)> {
+                <#requests as simple_syrup_client::openapi::Requests>::extra(components)
+            }
+
+            const PREFIX: &'static str = #prefix;
+            const EXCLUDED: &'static [&'static str] = &[#(#excluded),*];
+        }
+    }
+}
+
+pub(crate) fn derive_openapi(input: &DeriveData) -> TokenStream2 {
+    let DeriveData {
+        ident, server_ty, ..
+    } = input;
+
+    quote! {
+        #[actix_web::get("/openapi.json")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn openapi(req: actix_web::HttpRequest) -> impl actix_web::Responder {
+            actix_web::HttpResponseBuilder::new(StatusCode::OK)
+                .json(simple_syrup_client::openapi::document::<#ident>(stringify!(#ident)))
+        }
+    }
+}
@@ -1,33 +1,54 @@
 use crate::DataStorage;
 
-use super::{DeriveData, Idents};
-use proc_macro2::TokenStream as TokenStream2;
+use super::{DeriveData, DerivedRequest, Idents, Routes};
+use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
+use syn::{Ident, Path};
 
 pub(crate) fn derive_register(input: &DeriveData) -> TokenStream2 {
-    let DeriveData { ident, request, .. } = input;
+    let DeriveData {
+        ident,
+        request,
+        server_ty,
+        ..
+    } = input;
 
-    let req_ident = &request.idents.request_register;
-    let data_type = &request.idents.base;
+    let req_ident = request.ty("RegisterReq");
+    let data_type = request.ty("Data");
 
     let data = match request.data_storage_ty {
         DataStorage::Stored => quote! {
-            &request.data
+            &data
         },
         DataStorage::Hashed => quote! {
             &{
                 let mut hasher = DefaultHasher::new();
-                request.data.hash(&mut hasher);
+                data.hash(&mut hasher);
                 hasher.finish()
             }
         },
         DataStorage::Ignored => quote! {
-            &#data_type::default()
+            &<#data_type>::default()
         },
     };
 
+    // Servers ignoring the data are not sent any to validate.
+    let validate = match request.data_storage_ty {
+        DataStorage::Stored | DataStorage::Hashed => quote! {
+            let data = match authenticator.validate_registration(&request.data).await {
+                Ok(data) => data,
+                Err(e) => {
+                    crate::metrics::event(#server_ty, "register_invalid");
+                    return actix_web::HttpResponse::from(e);
+                }
+            };
+        },
+        DataStorage::Ignored => quote! {},
+    };
+
     quote! {
         #[actix_web::post("/register")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty, email = crate::telemetry::redact(&request.email)))]
         pub async fn register(req: actix_web::HttpRequest, request: actix_web::web::Json<#req_ident>) -> impl actix_web::Responder {
             let authenticator = req.app_data::<#ident>().unwrap();
 
@@ -36,20 +57,59 @@ pub(crate) fn derive_register(input: &DeriveData) -> TokenStream2 {
             let email = &request.email;
             let secret_component = &request.secret_component;
 
+            let share = match sharing::Share::decode(secret_component) {
+                Ok(share) => share,
+                Err(e) => return actix_web::HttpResponse::BadRequest()
+                    .body(format!("secret_component is not a valid share: {}", e)),
+            };
 
-            authenticator.base.prepare(email, secret_component, #data)
-                .await
+            if share.epoch != 0 {
+                return actix_web::HttpResponse::BadRequest()
+                    .body("secret_component must be a new share, not a refreshed one");
+            }
+
+            match (&request.commitments, share.scheme) {
+                (Some(commitments), _) => {
+                    if let Err(e) = sharing::Commitments::decode(commitments).and_then(|c| c.verify(&share)) {
+                        return actix_web::HttpResponse::BadRequest()
+                            .body(format!("secret_component does not match the commitments: {}", e));
+                    }
+                },
+                (None, sharing::Scheme::Feldman) => {
+                    return actix_web::HttpResponse::BadRequest()
+                        .body("commitments are required for verifiable shares");
+                },
+                (None, _) => {},
+            }
+
+            #validate
+
+            let res = authenticator.base.prepare(email, secret_component, request.commitments.as_deref(), #data)
+                .await;
+
+            if res.status().is_success() {
+                crate::metrics::event(#server_ty, "register_requested");
+                crate::audit::record(&authenticator.base.pool, #server_ty, crate::audit::AuditEvent::RegisterRequested, email, crate::audit::Origin::of(&req, &authenticator.base.trusted_proxies)).await;
+            }
+
+            res
         }
     }
 }
 
 pub(crate) fn derive_register_verify(input: &DeriveData) -> TokenStream2 {
-    let DeriveData { ident, request, .. } = input;
+    let DeriveData {
+        ident,
+        request,
+        server_ty,
+        ..
+    } = input;
 
-    let req_ident = &request.idents.verify_register;
+    let req_ident = request.ty("VerifyRegisterReq");
 
     quote! {
         #[actix_web::post("/register/verify")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty, email = crate::telemetry::redact(&request.email)))]
         pub async fn register_check(req: actix_web::HttpRequest, request: actix_web::web::Json<#req_ident>) -> impl actix_web::Responder {
             let authenticator = req.app_data::<#ident>().unwrap();
 
@@ -61,40 +121,61 @@ pub(crate) fn derive_register_verify(input: &DeriveData) -> TokenStream2 {
             match authenticator.base.get_prepared(email)
                 .await
                 .into_iter()
-                .find(|(id, _, _)| BaseAuthenticator::verify(&id, otp))
+                .find(|(id, _, _, _)| authenticator.base.verify(&id, otp))
                 {
-                    Some((_, sec, data)) => {
-                        authenticator.base.verify_register(email, &sec, data)
-                        .await
-                        .unwrap_or_else(|| actix_web::HttpResponseBuilder::new(StatusCode::OK).finish())
+                    Some((_, sec, commitments, data)) => {
+                        let event = match authenticator.base.get_authenticated_id(email).await {
+                            Some(_) => crate::audit::AuditEvent::Rotated,
+                            None => crate::audit::AuditEvent::RegisterVerified,
+                        };
+
+                        match authenticator.base.verify_register(email, &sec, commitments, data).await {
+                            Some(err) => err,
+                            None => {
+                                crate::metrics::event(#server_ty, "register_verified");
+                                crate::metrics::transition(#server_ty, &VerificationStatus::Verified);
+                                crate::audit::record(&authenticator.base.pool, #server_ty, event, email, crate::audit::Origin::of(&req, &authenticator.base.trusted_proxies)).await;
+                                actix_web::HttpResponseBuilder::new(StatusCode::OK).finish()
+                            }
+                        }
                     },
-                    None => return actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).finish()
+                    None => {
+                        crate::metrics::event(#server_ty, "register_failed");
+                        actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).finish()
+                    }
                 }
         }
     }
 }
 
 pub(crate) fn derive_authenticate(input: &DeriveData) -> TokenStream2 {
-    let DeriveData { ident, request, .. } = input;
+    let DeriveData {
+        ident,
+        request,
+        server_ty,
+        ..
+    } = input;
 
-    let req_ident = &request.idents.request_auth;
+    let req_ident = request.ty("AuthReq");
 
     quote! {
         #[actix_web::post("/authenticate")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty, email = crate::telemetry::redact(&request.email)))]
         pub async fn auth(req: actix_web::HttpRequest, request: actix_web::web::Json<#req_ident>) -> impl actix_web::Responder {
-            use crate::api::TestDefault;
             let authenticator = req.app_data::<#ident>().unwrap();
 
             let request = request.0;
 
             let email = &request.email;
 
-            let auth_data = authenticator.authenticate(email).await;
-            if (!cfg!(test)) {
-                if let Some(e) = auth_data { return e };
+            // Servers may answer with what the user must authenticate with, such as the questions
+            // to answer, once the status is updated.
+            let auth_data = match authenticator.authenticate(email).await {
+                Some(e) if !cfg!(test) && !e.status().is_success() => return e,
+                auth_data => auth_data,
             };
 
-            sqlx::query!(
+            let res = sqlx::query!(
                 "UPDATE authenticated SET status=$2 WHERE email=$1 AND status=$3 OR status=$4 RETURNING id;",
                 BaseAuthenticator::hash(email),
                 VerificationStatus::RequestAuth as VerificationStatus,
@@ -102,21 +183,41 @@ pub(crate) fn derive_authenticate(input: &DeriveData) -> TokenStream2 {
                 VerificationStatus::RequestAuth as VerificationStatus
             )
                 .fetch_one(&authenticator.base.pool)
-                .await
-                .map(|_| actix_web::HttpResponseBuilder::new(StatusCode::OK).finish())
-                .or_test_default_else(|| auth_data.unwrap())
+                .await;
+
+            if res.is_ok() {
+                crate::audit::record(&authenticator.base.pool, #server_ty, crate::audit::AuditEvent::AuthRequested, email, crate::audit::Origin::of(&req, &authenticator.base.trusted_proxies)).await;
+            }
+
+            res
+                .map(|_| {
+                    crate::metrics::event(#server_ty, "auth_requested");
+                    crate::metrics::transition(#server_ty, &VerificationStatus::RequestAuth);
+                    // Tests read the OTP sent, if any, from the response.
+                    auth_data.unwrap_or_else(|| if cfg!(test) {
+                        actix_web::HttpResponseBuilder::new(StatusCode::OK).json("")
+                    } else {
+                        actix_web::HttpResponseBuilder::new(StatusCode::OK).finish()
+                    })
+                })
                 .unwrap_or_else(|e| actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json(e.to_string()))
         }
     }
 }
 
 pub(crate) fn derive_verify_authentication(input: &DeriveData) -> TokenStream2 {
-    let DeriveData { ident, request, .. } = input;
+    let DeriveData {
+        ident,
+        request,
+        server_ty,
+        ..
+    } = input;
 
-    let req_ident = &request.idents.verify_auth;
+    let req_ident = request.ty("VerifyAuthReq");
 
     quote! {
         #[actix_web::post("/authenticate/verify")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty, email = crate::telemetry::redact(&request.email)))]
         pub async fn auth_check(req: actix_web::HttpRequest, request: actix_web::web::Json<#req_ident>) -> impl actix_web::Responder {
             let authenticator = req.app_data::<#ident>().unwrap();
 
@@ -125,18 +226,41 @@ pub(crate) fn derive_verify_authentication(input: &DeriveData) -> TokenStream2 {
             let email = &request.email;
 
             match authenticator.verify_authentication(email, &request.data).await {
-                Some(err) => err,
+                Some(err) => {
+                    crate::metrics::event(#server_ty, "auth_failed");
+                    crate::audit::record(&authenticator.base.pool, #server_ty, crate::audit::AuditEvent::AuthFailed, email, crate::audit::Origin::of(&req, &authenticator.base.trusted_proxies)).await;
+                    err
+                },
                 None => {
-                    sqlx::query!("UPDATE authenticated SET status=$2 WHERE email=$1 AND status=$3 RETURNING secret_component;",
+                    // Verifiable shares are released with their commitments, only to the user.
+                    let res = sqlx::query!("UPDATE authenticated SET status=$2 WHERE email=$1 AND status=$3 RETURNING secret_component, commitments;",
                         BaseAuthenticator::hash(email),
                         VerificationStatus::Verified as VerificationStatus,
                         VerificationStatus::RequestAuth as VerificationStatus,
                     )
                         .fetch_one(&authenticator.base.pool)
-                        .await
-                        .map(|rec| rec.secret_component)
-                        .map(|s: Option<String>| actix_web::HttpResponseBuilder::new(StatusCode::OK).json(s))
-                        .unwrap_or_else(|e| actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json(e.to_string()))
+                        .await;
+
+                    let event = match res {
+                        Ok(_) => crate::audit::AuditEvent::AuthVerified,
+                        Err(_) => crate::audit::AuditEvent::AuthFailed,
+                    };
+                    crate::audit::record(&authenticator.base.pool, #server_ty, event, email, crate::audit::Origin::of(&req, &authenticator.base.trusted_proxies)).await;
+
+                    res
+                        .map(|rec| crate::api::Released {
+                            secret_component: rec.secret_component,
+                            commitments: rec.commitments,
+                        })
+                        .map(|released| {
+                            crate::metrics::event(#server_ty, "auth_verified");
+                            crate::metrics::transition(#server_ty, &VerificationStatus::Verified);
+                            authenticator.base.identity.signed_json(StatusCode::OK, &released)
+                        })
+                        .unwrap_or_else(|e| {
+                            crate::metrics::event(#server_ty, "auth_failed");
+                            actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json(e.to_string())
+                        })
                 }
             }
         }
@@ -144,12 +268,18 @@ pub(crate) fn derive_verify_authentication(input: &DeriveData) -> TokenStream2 {
 }
 
 pub(crate) fn derive_status(input: &DeriveData) -> TokenStream2 {
-    let DeriveData { ident, request, .. } = input;
+    let DeriveData {
+        ident,
+        request,
+        server_ty,
+        ..
+    } = input;
 
-    let req_ident = &request.idents.request_auth;
+    let req_ident = request.ty("AuthReq");
 
     quote! {
         #[actix_web::post("/status")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty, email = crate::telemetry::redact(&request.email)))]
         pub async fn status_check(req: actix_web::HttpRequest, request: actix_web::web::Json<#req_ident>) -> impl actix_web::Responder {
             use sqlx::Row;
 
@@ -170,20 +300,109 @@ pub(crate) fn derive_status(input: &DeriveData) -> TokenStream2 {
     }
 }
 
+pub(crate) fn derive_refresh(input: &DeriveData) -> TokenStream2 {
+    let DeriveData {
+        ident, server_ty, ..
+    } = input;
+
+    quote! {
+        #[actix_web::post("/refresh/share")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn refresh_share(req: actix_web::HttpRequest, request: actix_web::web::Json<crate::refresh::Signed<crate::refresh::ShareQuery>>) -> impl actix_web::Responder {
+            let authenticator = req.app_data::<#ident>().unwrap();
+            let options = req.app_data::<actix_web::web::Data<crate::config::RefreshOptions>>().unwrap();
+
+            match crate::refresh::share_info(&authenticator.base, options, request.0).await {
+                Ok(info) => actix_web::HttpResponseBuilder::new(StatusCode::OK).json(info),
+                Err(e) => actix_web::HttpResponse::from(e),
+            }
+        }
+
+        #[actix_web::post("/refresh")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn refresh_update(req: actix_web::HttpRequest, request: actix_web::web::Json<crate::refresh::Signed<crate::refresh::RefreshUpdate>>) -> impl actix_web::Responder {
+            let authenticator = req.app_data::<#ident>().unwrap();
+            let options = req.app_data::<actix_web::web::Data<crate::config::RefreshOptions>>().unwrap();
+
+            match crate::refresh::receive(&authenticator.base, #server_ty, options, request.0).await {
+                Ok(refreshed) => {
+                    if refreshed == crate::refresh::Refreshed::Applied {
+                        crate::metrics::event(#server_ty, "share_refreshed");
+                    }
+                    actix_web::HttpResponse::from(refreshed)
+                },
+                Err(e) => {
+                    tracing::warn!("Rejected share of zero: {}", e);
+                    actix_web::HttpResponse::from(e)
+                },
+            }
+        }
+    }
+}
+
 pub(crate) fn derive_meta(input: &DeriveData) -> TokenStream2 {
-    let server_ty = &input.server_ty;
+    let DeriveData {
+        ident, server_ty, ..
+    } = input;
 
     quote! {
         #[actix_web::get("/ty")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
         pub async fn server_ty(req: actix_web::HttpRequest) -> impl actix_web::Responder {
             actix_web::HttpResponseBuilder::new(StatusCode::OK).json(serde_json::to_value(#server_ty).unwrap())
         }
+
+        #[actix_web::get("/identity")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn identity(req: actix_web::HttpRequest) -> impl actix_web::Responder {
+            let authenticator = req.app_data::<#ident>().unwrap();
+
+            actix_web::HttpResponseBuilder::new(StatusCode::OK).json(crate::identity::IdentityInfo {
+                server_ty: #server_ty,
+                public_key: authenticator.base.identity.public_key(),
+            })
+        }
     }
 }
 
-pub(crate) fn derive_req(input: &DeriveData) -> TokenStream2 {
-    let DeriveData { request, .. } = input;
+pub(crate) fn derive_health(input: &DeriveData) -> TokenStream2 {
+    let DeriveData {
+        ident, server_ty, ..
+    } = input;
+
+    quote! {
+        #[actix_web::get("/readyz")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn readyz(req: actix_web::HttpRequest) -> impl actix_web::Responder {
+            let authenticator = req.app_data::<#ident>().unwrap();
+
+            let readiness = crate::api::health::Readiness::check(
+                &authenticator.base,
+                authenticator.backend_ready(),
+            )
+            .await;
+
+            actix_web::HttpResponse::from(readiness)
+        }
+
+        #[actix_web::get("/metrics")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn metrics(req: actix_web::HttpRequest) -> impl actix_web::Responder {
+            let authenticator = req.app_data::<#ident>().unwrap();
+
+            crate::metrics::render(#server_ty, &authenticator.base.pool, authenticator.base.max_connections)
+        }
+
+        #[actix_web::get("/version")]
+        #[tracing::instrument(skip_all, fields(server_ty = ?#server_ty))]
+        pub async fn version(req: actix_web::HttpRequest) -> impl actix_web::Responder {
+            actix_web::HttpResponseBuilder::new(StatusCode::OK)
+                .json(crate::api::health::VersionInfo::new(#server_ty))
+        }
+    }
+}
 
+pub(crate) fn derive_requests(request: &DerivedRequest) -> TokenStream2 {
     let Idents {
         request_auth,
         request_register,
@@ -196,16 +415,20 @@ pub(crate) fn derive_req(input: &DeriveData) -> TokenStream2 {
         DataStorage::Stored | DataStorage::Hashed => quote! {
             #[derive(Debug, Deserialize, Serialize)]
             pub struct #request_register {
-                email: String,
-                secret_component: String,
-                data: #base
+                pub email: String,
+                pub secret_component: String,
+                #[serde(default)]
+                pub commitments: Option<String>,
+                pub data: #base
             }
         },
         DataStorage::Ignored => quote! {
             #[derive(Debug, Deserialize, Serialize)]
             pub struct #request_register {
-                email: String,
-                secret_component: String,
+                pub email: String,
+                pub secret_component: String,
+                #[serde(default)]
+                pub commitments: Option<String>,
             }
         },
     };
@@ -216,22 +439,92 @@ pub(crate) fn derive_req(input: &DeriveData) -> TokenStream2 {
 
         #[derive(Debug, Deserialize, Serialize)]
         pub struct #verify_register {
-            email: String,
-            otp: String,
+            pub email: String,
+            pub otp: String,
         }
 
         #[derive(Debug, Deserialize, Serialize)]
         pub struct #request_auth {
-            email: String,
+            pub email: String,
         }
 
 
         #[derive(Debug, Deserialize, Serialize)]
         pub struct #verify_auth {
-            email: String,
-            data: #base
+            pub email: String,
+            pub data: #base
+        }
+
+    }
+}
+
+/// Implements `Authenticator` for `ident`, with the request types of `derive_requests` and the
+/// server type `server_ty`.
+pub(crate) fn derive_authenticator(ident: &Ident, request: &DerivedRequest, server_ty: &Path) -> TokenStream2 {
+    let Idents {
+        request_auth,
+        request_register,
+        verify_register,
+        verify_auth,
+        base,
+    } = &request.idents;
+
+    let (store, register_req) = match request.data_storage_ty {
+        DataStorage::Stored => (quote! { Stored }, quote! { #request_register { email, secret_component, commitments, data } }),
+        DataStorage::Hashed => (quote! { Hashed }, quote! { #request_register { email, secret_component, commitments, data } }),
+        DataStorage::Ignored => (quote! { Ignored }, quote! { #request_register { email, secret_component, commitments } }),
+    };
+    let data = match request.data_storage_ty {
+        DataStorage::Stored | DataStorage::Hashed => quote! { data },
+        DataStorage::Ignored => quote! { _data },
+    };
+
+    quote! {
+        impl simple_syrup_client::Authenticator for #ident {
+            const SERVER_TY: simple_syrup_client::ServerType = #server_ty;
+            const STORE: simple_syrup_client::types::Store = simple_syrup_client::types::Store::#store;
+
+            type Data = #base;
+
+            type RegisterReq = #request_register;
+            type VerifyRegisterReq = #verify_register;
+            type AuthReq = #request_auth;
+            type VerifyAuthReq = #verify_auth;
+
+            fn register_req(email: String, secret_component: String, commitments: Option<String>, #data: #base) -> #request_register {
+                #register_req
+            }
+
+            fn verify_register_req(email: String, otp: String) -> #verify_register {
+                #verify_register { email, otp }
+            }
+
+            fn auth_req(email: String) -> #request_auth {
+                #request_auth { email }
+            }
+
+            fn verify_auth_req(email: String, data: #base) -> #verify_auth {
+                #verify_auth { email, data }
+            }
         }
+    }
+}
 
+/// Fails to compile when the server keeps the data differently than its requests declare, such
+/// as ignoring data its clients send.
+fn derive_store_check(input: &DeriveData) -> TokenStream2 {
+    let store = match input.request.data_storage_ty {
+        DataStorage::Stored => quote! { Stored },
+        DataStorage::Hashed => quote! { Hashed },
+        DataStorage::Ignored => quote! { Ignored },
+    };
+    let requests = &input.request.path;
+
+    quote! {
+        const _: () = assert!(
+            matches!(<#requests as simple_syrup_client::Authenticator>::STORE, simple_syrup_client::types::Store::#store),
+            "the `store` of the server differs from the `store` of its requests"
+        );
     }
 }
 
@@ -239,13 +532,16 @@ impl From<DeriveData> for TokenStream2 {
     fn from(data: DeriveData) -> Self {
         let DeriveData {
             ident,
+            vis,
+            generics,
             fields,
             sub_attrs,
             ..
         } = data;
+        let where_clause = &generics.where_clause;
         quote! {
             #(#sub_attrs)*
-            pub struct #ident {
+            #vis struct #ident #generics #where_clause {
                 pub base: super::base::BaseAuthenticator,
                 #(#fields,)*
             }
@@ -253,6 +549,50 @@ impl From<DeriveData> for TokenStream2 {
     }
 }
 
+/// The generated routes, by the name `routes(exclude(...))` takes: their handlers and paths.
+pub(crate) const ROUTES: &[(&str, &[&str], &[&str])] = &[
+    ("register", &["register"], &["/register"]),
+    ("register_verify", &["register_check"], &["/register/verify"]),
+    ("authenticate", &["auth"], &["/authenticate"]),
+    ("authenticate_verify", &["auth_check"], &["/authenticate/verify"]),
+    ("status", &["status_check"], &["/status"]),
+    ("refresh", &["refresh_share", "refresh_update"], &["/refresh/share", "/refresh"]),
+    ("ty", &["server_ty"], &["/ty"]),
+    ("identity", &["identity"], &["/identity"]),
+    ("openapi", &["openapi"], &["/openapi.json"]),
+    ("readyz", &["readyz"], &["/readyz"]),
+    ("version", &["version"], &["/version"]),
+    ("metrics", &["metrics"], &["/metrics"]),
+];
+
+/// `configure`, registering the routes that are not excluded and the extra ones.
+fn derive_configure(input: &DeriveData) -> TokenStream2 {
+    let Routes { prefix, exclude, extra } = &input.routes;
+
+    let handlers = ROUTES
+        .iter()
+        .filter(|(name, _, _)| !exclude.iter().any(|excluded| excluded == name))
+        .flat_map(|(_, handlers, _)| handlers.iter())
+        .map(|handler| Ident::new(handler, Span::call_site()));
+
+    let services = quote! {
+        #(.service(handlers::#handlers))*
+        #(.service(#extra))*
+    };
+
+    let services = match prefix {
+        Some(prefix) => quote! { .service(actix_web::web::scope(#prefix) #services) },
+        None => services,
+    };
+
+    quote! {
+        /// Registers the routes of the server, which expect it in the app data.
+        pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
+            cfg #services;
+        }
+    }
+}
+
 pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
     let register = derive_register(input);
     let ver_register = derive_register_verify(input);
@@ -260,25 +600,43 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
     let ver_auth = derive_verify_authentication(input);
     let meta_data = derive_meta(input);
     let status = derive_status(input);
+    let refresh = derive_refresh(input);
+    let health = derive_health(input);
 
-    let request_structures = derive_req(input);
+    let store_check = derive_store_check(input);
+    let requests = crate::openapi::derive_server_requests(input);
+    let openapi = crate::openapi::derive_openapi(input);
+    let configure = derive_configure(input);
 
     quote! {
 
-        #request_structures
+        #store_check
 
-        #meta_data
+        #requests
 
-        #register
+        #configure
 
-        #ver_register
+        mod handlers {
+            use super::*;
 
-        #auth
+            #meta_data
 
-        #ver_auth
+            #openapi
 
-        #status
+            #health
 
+            #register
+
+            #ver_register
+
+            #auth
+
+            #ver_auth
+
+            #status
+
+            #refresh
+        }
 
     }
 }
@@ -5,18 +5,130 @@ use quote::quote;
 pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
     let server_ty = &input.server_ty;
 
-    let data_ty = &input.request.idents.base;
+    let ident = &input.ident;
 
     quote! {
         #[cfg(test)]
         mod generated_tests {
             use super::*;
 
+            #[actix_web::test]
+            async fn lifecycle() {
+                use crate::api::Fixture;
+                let app = crate::config::Config::test(#server_ty).await;
+
+                let secret = crate::test::share();
+
+                let otp = app.register(&secret, &<#ident>::registration_data()).await;
+
+                app.verify_register(&otp).await;
+
+                assert_eq!(app.get_status().await, crate::api::VerificationStatus::Verified);
+
+                let authenticated = app.auth().await;
+
+                assert_eq!(app.get_status().await, crate::api::VerificationStatus::RequestAuth);
+
+                let secret_component = app.verify_auth(&<#ident>::correct_data(authenticated)).await;
+
+                assert_eq!(secret_component, Some(secret));
+                assert_eq!(app.get_status().await, crate::api::VerificationStatus::Verified);
+            }
+
+            #[actix_web::test]
+            async fn malformed_share_register() {
+                let app = crate::config::Config::test(#server_ty).await;
+                let app = crate::test::build_test_app!(app).await;
+
+                let req = actix_web::test::TestRequest::post()
+                    .uri("/register")
+                    .set_json(serde_json::json!({
+                        "email": "benjcape@gmail.com",
+                        "secret_component": "foobar",
+                        "data": <#ident as crate::api::Fixture>::registration_data()
+                    }))
+                    .to_request();
+
+                let res = actix_web::test::call_service(&app, req).await;
+
+                assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            }
+
+            #[actix_web::test]
+            async fn verifiable_share_register() {
+                let app = crate::config::Config::test(#server_ty).await;
+                let public_key = app.identity.public_key();
+
+                let (shares, commitments) = sharing::feldman::split(b"foobar", 2, 3).unwrap();
+                let (_, others) = sharing::feldman::split(b"foobar", 2, 3).unwrap();
+
+                let app = crate::test::build_test_app!(app).await;
+                let registration = |commitments: Option<String>| actix_web::test::TestRequest::post()
+                    .uri("/register")
+                    .set_json(serde_json::json!({
+                        "email": "benjcape@gmail.com",
+                        "secret_component": shares[0].encode(),
+                        "commitments": commitments,
+                        "data": <#ident as crate::api::Fixture>::registration_data()
+                    }))
+                    .to_request();
+
+                let res = actix_web::test::call_service(&app, registration(None)).await;
+                assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+                let res = actix_web::test::call_service(&app, registration(Some(others.encode()))).await;
+                assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+                let otp: String = actix_web::test::call_and_read_body_json(&app, registration(Some(commitments.encode()))).await;
+
+                let req = actix_web::test::TestRequest::post()
+                    .uri("/register/verify")
+                    .set_json(serde_json::json!({
+                        "email": "benjcape@gmail.com",
+                        "otp": otp
+                    }))
+                    .to_request();
+                assert!(actix_web::test::call_service(&app, req).await.status().is_success());
+
+                // The commitments are released with the share, to the user only.
+                let req = actix_web::test::TestRequest::post()
+                    .uri("/authenticate")
+                    .set_json(serde_json::json!({ "email": "benjcape@gmail.com" }))
+                    .to_request();
+                let authenticated: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+
+                let req = actix_web::test::TestRequest::post()
+                    .uri("/authenticate/verify")
+                    .set_json(serde_json::json!({
+                        "email": "benjcape@gmail.com",
+                        "data": <#ident as crate::api::Fixture>::correct_data(authenticated)
+                    }))
+                    .to_request();
+                let res = actix_web::test::call_service(&app, req).await;
+                let signature = res.headers().get(crate::identity::SIGNATURE_HEADER).unwrap().to_str().unwrap().to_string();
+
+                let body = actix_web::test::read_body(res).await;
+                assert!(crate::identity::verify(&public_key, &body, &signature));
+
+                let released: crate::api::Released = serde_json::from_slice(&body).unwrap();
+                assert_eq!(released.secret_component, Some(shares[0].encode()));
+                let published = sharing::Commitments::decode(&released.commitments.unwrap()).unwrap();
+                assert_eq!(published.verify(&shares[0]), Ok(()));
+                assert_eq!(published.verify(&shares[1]), Ok(()));
+
+                let req = actix_web::test::TestRequest::post()
+                    .uri("/commitments")
+                    .set_json(serde_json::json!({ "email": "benjcape@gmail.com" }))
+                    .to_request();
+                let res = actix_web::test::call_service(&app, req).await;
+                assert_eq!(res.status(), actix_web::http::StatusCode::NOT_FOUND);
+            }
+
             #[actix_web::test]
             async fn bad_otp_verify_register() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let _ = app.register("foobar", &#data_ty::default()).await;
+                let _ = app.register(&crate::test::share(), &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 let app = crate::test::build_test_app!(app).await;
 
@@ -39,7 +151,7 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
             async fn no_otp_verify_register() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let _ = app.register("foobar", &#data_ty::default()).await;
+                let _ = app.register(&crate::test::share(), &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 let app = crate::test::build_test_app!(app).await;
 
@@ -61,7 +173,7 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
             async fn bad_verify_register() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let otp = app.register("foobar", &#data_ty::default()).await;
+                let otp = app.register(&crate::test::share(), &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 let app = crate::test::build_test_app!(app).await;
 
@@ -84,7 +196,7 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
             async fn no_verify_register() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let _ = app.register("foobar", &#data_ty::default()).await;
+                let _ = app.register(&crate::test::share(), &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 let app = crate::test::build_test_app!(app).await;
 
@@ -104,9 +216,9 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
             async fn bad_authenticate() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let secret = "foobar-test-email";
+                let secret = crate::test::share();
 
-                let otp = app.register(secret, &#data_ty::default()).await;
+                let otp = app.register(&secret, &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 app.verify_register(&otp).await;
 
@@ -131,9 +243,9 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
             async fn no_email_authenticate() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let secret = "foobar-test-email";
+                let secret = crate::test::share();
 
-                let otp = app.register(secret, &#data_ty::default()).await;
+                let otp = app.register(&secret, &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 app.verify_register(&otp).await;
 
@@ -156,9 +268,9 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
             async fn no_data_verify_authenticate() {
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let secret = "foobar-test-email";
+                let secret = crate::test::share();
 
-                let otp = app.register(secret, &#data_ty::default()).await;
+                let otp = app.register(&secret, &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 app.verify_register(&otp).await;
 
@@ -186,12 +298,11 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
 
             #[actix_web::test]
             async fn bad_data_verify_authenticate() {
-                use crate::api::ServerData;
                 let app = crate::config::Config::test(#server_ty).await;
 
-                let secret = "foobar-test-email";
+                let secret = crate::test::share();
 
-                let otp = app.register(secret, &#data_ty::default()).await;
+                let otp = app.register(&secret, &<#ident as crate::api::Fixture>::registration_data()).await;
 
                 app.verify_register(&otp).await;
 
@@ -211,7 +322,7 @@ pub(crate) fn derive(input: &DeriveData) -> TokenStream2 {
                     .uri("/authenticate/verify")
                     .set_json(serde_json::json!({
                         "email": "benjcape@gmail.com",
-                        "data": #data_ty::bad_data()
+                        "data": <#ident as crate::api::Fixture>::incorrect_data()
                     }))
                     .to_request();
 
@@ -0,0 +1,6 @@
+use derive::PassServer;
+
+#[PassServer(requests(types::QAAuthenticator), store(Hashed), ignore_tests(1))]
+pub struct QAAuthenticator {}
+
+fn main() {}
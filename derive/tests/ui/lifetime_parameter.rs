@@ -0,0 +1,8 @@
+use derive::PassServer;
+
+#[PassServer(requests(types::QAAuthenticator), store(Hashed))]
+pub struct QAAuthenticator<'a> {
+    name: &'a str,
+}
+
+fn main() {}
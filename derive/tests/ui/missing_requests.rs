@@ -0,0 +1,6 @@
+use derive::PassServer;
+
+#[PassServer(store(Hashed))]
+pub struct QAAuthenticator {}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use derive::PassServer;
+
+#[PassServer(requests(types::QAAuthenticator))]
+pub struct QAAuthenticator {}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use derive::PassRequests;
+
+#[PassRequests(data(String), store(Hashed))]
+pub struct QAAuthenticator;
+
+fn main() {}
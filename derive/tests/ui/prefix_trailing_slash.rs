@@ -0,0 +1,6 @@
+use derive::PassServer;
+
+#[PassServer(requests(types::QAAuthenticator), store(Hashed), prefix("/qa/"), ignore_tests(true))]
+pub struct QAAuthenticator {}
+
+fn main() {}
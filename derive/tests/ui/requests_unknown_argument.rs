@@ -0,0 +1,6 @@
+use derive::PassRequests;
+
+#[PassRequests(data(String), store(Hashed), ty(ServerType::QA), prefix("/qa"))]
+pub struct QAAuthenticator;
+
+fn main() {}
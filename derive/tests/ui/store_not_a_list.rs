@@ -0,0 +1,6 @@
+use derive::PassServer;
+
+#[PassServer(requests(types::QAAuthenticator), store = "Hashed")]
+pub struct QAAuthenticator {}
+
+fn main() {}
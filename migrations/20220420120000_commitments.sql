@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE
+  prepare
+ADD
+  COLUMN commitments VARCHAR;
+
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN commitments VARCHAR;
@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN epoch BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS refresh_updates (
+  email VARCHAR NOT NULL,
+  epoch BIGINT NOT NULL,
+  dealer VARCHAR NOT NULL,
+  delta VARCHAR NOT NULL,
+  commitments VARCHAR,
+  PRIMARY KEY (email, epoch, dealer)
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN auth_request VARCHAR;
@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN failed_attempts integer NOT NULL DEFAULT 0;
+
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN locked_until bigint;
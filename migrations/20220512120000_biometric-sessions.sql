@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN device_session text;
+
+ALTER TABLE
+  authenticated
+ADD
+  COLUMN device_session_expires bigint;
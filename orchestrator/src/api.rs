@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::{get, post, put, web, HttpResponse, HttpResponseBuilder};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::authenticators;
+use crate::policy::{Policy, PolicyStore, Server};
+use crate::session::{FactorStatus, Session};
+
+/// Shared state of the orchestrator.
+pub struct Orchestrator {
+    pub servers: Vec<Server>,
+    /// Threshold of users without a policy of their own.
+    pub threshold: usize,
+    /// Threshold secrets are split with, the least a policy may require.
+    pub min_threshold: usize,
+    pub session_ttl: Duration,
+    pub client: reqwest::Client,
+    pub sessions: Mutex<HashMap<Uuid, Session>>,
+    pub policies: PolicyStore,
+}
+
+impl Orchestrator {
+    pub fn new(
+        servers: Vec<Server>,
+        threshold: usize,
+        min_threshold: usize,
+        session_ttl: Duration,
+        policies: PolicyStore,
+    ) -> Self {
+        Self {
+            servers,
+            threshold,
+            min_threshold,
+            session_ttl,
+            client: reqwest::Client::new(),
+            sessions: Mutex::new(HashMap::new()),
+            policies,
+        }
+    }
+
+    fn policy(&self, email: &str) -> Policy {
+        self.policies
+            .get(email)
+            .unwrap_or_else(|| Policy::default_for(&self.servers, self.threshold))
+    }
+
+    fn expire_sessions(&self) {
+        let ttl = self.session_ttl;
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, session| session.created.elapsed() <= ttl);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OrchestratorError {
+    /// The session does not exist, or has expired.
+    UnknownSession,
+    /// The server is not a factor of the session.
+    UnknownFactor,
+    /// The session has not met its threshold yet.
+    Incomplete,
+    InvalidPolicy(String),
+    /// The policy could not be saved.
+    Storage(String),
+}
+
+impl std::fmt::Display for OrchestratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownSession => write!(f, "unknown or expired session"),
+            Self::UnknownFactor => write!(f, "the server is not a factor of this session"),
+            Self::Incomplete => write!(f, "the session has not met its threshold"),
+            Self::InvalidPolicy(e) => write!(f, "invalid policy: {}", e),
+            Self::Storage(e) => write!(f, "could not save the policy: {}", e),
+        }
+    }
+}
+
+impl From<OrchestratorError> for HttpResponse {
+    fn from(e: OrchestratorError) -> Self {
+        let status = match e {
+            OrchestratorError::UnknownSession => StatusCode::NOT_FOUND,
+            OrchestratorError::UnknownFactor | OrchestratorError::InvalidPolicy(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            OrchestratorError::Incomplete => StatusCode::FORBIDDEN,
+            OrchestratorError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpResponseBuilder::new(status).json(e.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyRequest {
+    /// Url of the server to verify with.
+    pub url: String,
+    /// Forwarded as the `data` of `/authenticate/verify`.
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PolicyRequest {
+    /// A complete session of the user, proving they may change their policy.
+    pub session_id: Uuid,
+    pub policy: Policy,
+}
+
+/// Starts a session, calling `/authenticate` on every factor of the user's policy.
+#[post("/sessions")]
+pub async fn start_session(
+    orchestrator: web::Data<Orchestrator>,
+    request: web::Json<StartRequest>,
+) -> HttpResponse {
+    orchestrator.expire_sessions();
+
+    let email = request.0.email;
+    let mut session = Session::new(
+        email.clone(),
+        orchestrator.policy(&email),
+        &orchestrator.servers,
+    );
+
+    for factor in session.factors.iter_mut() {
+        if let Err(e) =
+            authenticators::authenticate(&orchestrator.client, &factor.url, &email).await
+        {
+            tracing::warn!("Could not start authentication with {}: {}", factor.url, e);
+            factor.status = FactorStatus::Failed;
+            factor.error = Some(e);
+        }
+    }
+
+    let id = Uuid::new_v4();
+    let view = session.view(id);
+    orchestrator.sessions.lock().unwrap().insert(id, session);
+
+    HttpResponseBuilder::new(StatusCode::CREATED).json(view)
+}
+
+#[get("/sessions/{id}")]
+pub async fn session_status(
+    orchestrator: web::Data<Orchestrator>,
+    id: web::Path<Uuid>,
+) -> HttpResponse {
+    orchestrator.expire_sessions();
+
+    match orchestrator.sessions.lock().unwrap().get(&id) {
+        Some(session) => HttpResponseBuilder::new(StatusCode::OK).json(session.view(*id)),
+        None => OrchestratorError::UnknownSession.into(),
+    }
+}
+
+/// Verifies one factor, releasing the shares once the threshold is met.
+#[post("/sessions/{id}/verify")]
+pub async fn verify_factor(
+    orchestrator: web::Data<Orchestrator>,
+    id: web::Path<Uuid>,
+    request: web::Json<VerifyRequest>,
+) -> HttpResponse {
+    orchestrator.expire_sessions();
+
+    let id = *id;
+    let VerifyRequest { url, data } = request.0;
+
+    // The lock is not held while waiting on the server.
+    let email = match orchestrator.sessions.lock().unwrap().get(&id) {
+        Some(session) if session.factors.iter().any(|factor| factor.url == url) => {
+            session.email.clone()
+        }
+        Some(_) => return OrchestratorError::UnknownFactor.into(),
+        None => return OrchestratorError::UnknownSession.into(),
+    };
+    let server = orchestrator
+        .servers
+        .iter()
+        .find(|server| server.url == url)
+        .unwrap();
+
+    let res = authenticators::verify(&orchestrator.client, server, &email, &data).await;
+
+    let mut sessions = orchestrator.sessions.lock().unwrap();
+    let session = match sessions.get_mut(&id) {
+        Some(session) => session,
+        None => return OrchestratorError::UnknownSession.into(),
+    };
+    let factor = session.factor_mut(&url).unwrap();
+
+    match res {
+        Ok(share) => {
+            factor.status = FactorStatus::Verified;
+            factor.error = None;
+            factor.share = Some(share);
+        }
+        Err(e) if factor.status != FactorStatus::Verified => {
+            factor.status = FactorStatus::Failed;
+            factor.error = Some(e);
+        }
+        Err(_) => {}
+    }
+
+    HttpResponseBuilder::new(StatusCode::OK).json(session.view(id))
+}
+
+/// Chooses the factors, and threshold, of the user of a complete session.
+#[put("/policy")]
+pub async fn set_policy(
+    orchestrator: web::Data<Orchestrator>,
+    request: web::Json<PolicyRequest>,
+) -> HttpResponse {
+    orchestrator.expire_sessions();
+
+    let PolicyRequest { session_id, policy } = request.0;
+
+    let email = match orchestrator.sessions.lock().unwrap().get(&session_id) {
+        Some(session) if session.is_complete() => session.email.clone(),
+        Some(_) => return OrchestratorError::Incomplete.into(),
+        None => return OrchestratorError::UnknownSession.into(),
+    };
+
+    if let Err(e) = policy.validate(&orchestrator.servers, orchestrator.min_threshold) {
+        return OrchestratorError::InvalidPolicy(e).into();
+    }
+
+    if let Err(e) = orchestrator.policies.set(email, policy.clone()) {
+        tracing::error!("Could not save a policy: {}", e);
+        return OrchestratorError::Storage(e.to_string()).into();
+    }
+
+    HttpResponseBuilder::new(StatusCode::OK).json(policy)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(start_session)
+        .service(session_status)
+        .service(verify_factor)
+        .service(set_policy);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App, HttpServer};
+
+    use super::*;
+    use crate::session::SessionView;
+
+    /// An authenticator server accepting `"correct"` as verification data.
+    fn mock_server(server_ty: &'static str) -> Server {
+        let server = HttpServer::new(move || {
+            App::new()
+                .route(
+                    "/authenticate",
+                    web::post().to(|| async { HttpResponse::Ok().json("") }),
+                )
+                .route(
+                    "/authenticate/verify",
+                    web::post().to(move |request: web::Json<serde_json::Value>| async move {
+                        if request["data"] == "correct" {
+                            HttpResponse::Ok().json(serde_json::json!({
+                                "secret_component": format!("{} share", server_ty),
+                                "commitments": null,
+                            }))
+                        } else {
+                            HttpResponse::Unauthorized().finish()
+                        }
+                    }),
+                )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        Server {
+            url,
+            server_ty: server_ty.into(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn releases_shares_at_threshold() {
+        let servers = vec![
+            mock_server("Email"),
+            mock_server("QA"),
+            mock_server("Password"),
+        ];
+        let urls: Vec<String> = servers.iter().map(|server| server.url.clone()).collect();
+
+        let orchestrator = web::Data::new(Orchestrator::new(
+            servers,
+            2,
+            2,
+            Duration::from_secs(60),
+            PolicyStore::default(),
+        ));
+        let app = test::init_service(
+            App::new()
+                .app_data(orchestrator.clone())
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/sessions")
+            .set_json(StartRequest {
+                email: "benjcape@gmail.com".into(),
+            })
+            .to_request();
+        let session: SessionView = test::call_and_read_body_json(&app, req).await;
+        assert!(session
+            .factors
+            .iter()
+            .all(|factor| factor.status == FactorStatus::Requested));
+
+        let verify_request = |url: &str, data: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/sessions/{}/verify", session.session_id))
+                .set_json(VerifyRequest {
+                    url: url.into(),
+                    data: data.into(),
+                })
+                .to_request()
+        };
+
+        let view: SessionView =
+            test::call_and_read_body_json(&app, verify_request(&urls[0], "correct")).await;
+        assert!(!view.complete);
+        assert!(view.shares.is_none());
+
+        let view: SessionView =
+            test::call_and_read_body_json(&app, verify_request(&urls[1], "wrong")).await;
+        assert_eq!(view.factors[1].status, FactorStatus::Failed);
+        assert!(!view.complete);
+
+        let view: SessionView =
+            test::call_and_read_body_json(&app, verify_request(&urls[2], "correct")).await;
+        assert!(view.complete);
+        let shares: Vec<_> = view
+            .shares
+            .unwrap()
+            .into_iter()
+            .map(|share| share.secret_component.unwrap())
+            .collect();
+        assert_eq!(shares, ["Email share", "Password share"]);
+
+        // Once complete, the user may require only their QA and Password factors, but not fewer
+        // than the shares the secret is split for.
+        let policy_request = |threshold| {
+            test::TestRequest::put()
+                .uri("/policy")
+                .set_json(PolicyRequest {
+                    session_id: session.session_id,
+                    policy: Policy {
+                        threshold,
+                        factors: urls[1..].to_vec(),
+                    },
+                })
+                .to_request()
+        };
+        assert_eq!(
+            test::call_service(&app, policy_request(1)).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            test::call_service(&app, policy_request(2)).await.status(),
+            StatusCode::OK
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/sessions")
+            .set_json(StartRequest {
+                email: "benjcape@gmail.com".into(),
+            })
+            .to_request();
+        let session: SessionView = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(session.factors.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn policy_requires_complete_session() {
+        let servers = vec![mock_server("Email")];
+
+        let orchestrator = web::Data::new(Orchestrator::new(
+            servers,
+            1,
+            1,
+            Duration::from_secs(60),
+            PolicyStore::default(),
+        ));
+        let app = test::init_service(
+            App::new()
+                .app_data(orchestrator.clone())
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/sessions")
+            .set_json(StartRequest {
+                email: "benjcape@gmail.com".into(),
+            })
+            .to_request();
+        let session: SessionView = test::call_and_read_body_json(&app, req).await;
+
+        let req = test::TestRequest::put()
+            .uri("/policy")
+            .set_json(PolicyRequest {
+                session_id: session.session_id,
+                policy: Policy {
+                    threshold: 1,
+                    factors: vec![],
+                },
+            })
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/sessions/{}", Uuid::new_v4()))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
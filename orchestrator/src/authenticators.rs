@@ -0,0 +1,81 @@
+//! Calls to the authenticator servers.
+
+use crate::policy::Server;
+use crate::session::Share;
+
+/// Headers authenticator servers sign a released share with.
+const SIGNATURE_HEADER: &str = "x-signature";
+const PUBLIC_KEY_HEADER: &str = "x-public-key";
+
+async fn error(res: reqwest::Response) -> String {
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+
+    format!("{} ({})", body, status)
+}
+
+/// Starts authentication, for example sending the user an OTP.
+#[tracing::instrument(skip(client, email))]
+pub async fn authenticate(client: &reqwest::Client, url: &str, email: &str) -> Result<(), String> {
+    let res = client
+        .post(format!("{}/authenticate", url))
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(error(res).await)
+    }
+}
+
+/// What `/authenticate/verify` releases.
+#[derive(serde::Deserialize)]
+struct Released {
+    secret_component: Option<String>,
+    #[serde(default)]
+    commitments: Option<String>,
+}
+
+/// Verifies the user with `data`, releasing the server's `secret_component` and the
+/// commitments it was registered with.
+#[tracing::instrument(skip(client, email, data), fields(url = %server.url))]
+pub async fn verify(
+    client: &reqwest::Client,
+    server: &Server,
+    email: &str,
+    data: &serde_json::Value,
+) -> Result<Share, String> {
+    let res = client
+        .post(format!("{}/authenticate/verify", server.url))
+        .json(&serde_json::json!({ "email": email, "data": data }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(error(res).await);
+    }
+
+    let header = |name: &str| {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let signature = header(SIGNATURE_HEADER);
+    let public_key = header(PUBLIC_KEY_HEADER);
+
+    let released: Released = res.json().await.map_err(|e| e.to_string())?;
+
+    Ok(Share {
+        url: server.url.clone(),
+        server_ty: server.server_ty.clone(),
+        secret_component: released.secret_component,
+        commitments: released.commitments,
+        signature,
+        public_key,
+    })
+}
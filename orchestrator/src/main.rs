@@ -0,0 +1,102 @@
+//! Releases the shares of a user once they authenticate with k of their n factors.
+//!
+//! The orchestrator drives the user through `/authenticate` and `/authenticate/verify` on each
+//! authenticator server of their policy, tracking progress in a session. Once the threshold of the
+//! policy is met, the session returns the `secret_component` of every verified server, along with
+//! the commitments and signature it was released with.
+//!
+//! The orchestrator sees every share it releases, so it must be trusted as much as the client.
+
+use std::time::Duration;
+
+use actix_web::{web, App, HttpServer};
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+
+mod api;
+mod authenticators;
+mod policy;
+mod session;
+
+#[derive(Debug, Parser)]
+#[clap(version, about = "CryptoPass k-of-n share release orchestrator")]
+struct Cli {
+    #[clap(long, env = "PORT", default_value = "8090")]
+    port: u16,
+
+    /// Authenticator servers, in the JSON format of the servers' `ACTIVE_SERVERS`
+    #[clap(long, env = "ACTIVE_SERVERS")]
+    active_servers: String,
+
+    /// Factors required of users without a policy. Defaults to every server
+    #[clap(long, env = "THRESHOLD")]
+    threshold: Option<usize>,
+
+    /// Threshold secrets are split with, the fewest factors a policy may require. Defaults to
+    /// THRESHOLD
+    #[clap(long, env = "MIN_THRESHOLD")]
+    min_threshold: Option<usize>,
+
+    /// JSON file the policies chosen by users are saved to
+    #[clap(long, env = "POLICY_FILE", default_value = "policies.json")]
+    policy_file: std::path::PathBuf,
+
+    /// Seconds a session lasts
+    #[clap(long, env = "SESSION_TTL", default_value = "600")]
+    session_ttl: u64,
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .init();
+
+    let cli = Cli::parse();
+
+    let servers: Vec<policy::Server> =
+        serde_json::from_str(&cli.active_servers).unwrap_or_else(|e| {
+            eprintln!("ACTIVE_SERVERS is not correctly formatted: {}", e);
+            std::process::exit(1)
+        });
+
+    let threshold = cli.threshold.unwrap_or(servers.len());
+    let min_threshold = cli.min_threshold.unwrap_or(threshold);
+    if let Err(e) =
+        policy::Policy::default_for(&servers, threshold).validate(&servers, min_threshold)
+    {
+        eprintln!("Invalid THRESHOLD: {}", e);
+        std::process::exit(1)
+    }
+
+    let policies = policy::PolicyStore::open(&cli.policy_file).unwrap_or_else(|e| {
+        eprintln!("Could not load {}: {}", cli.policy_file.display(), e);
+        std::process::exit(1)
+    });
+
+    tracing::info!(
+        "[orchestrator]: 0.0.0.0:{}, {} of {} servers",
+        cli.port,
+        threshold,
+        servers.len()
+    );
+
+    let orchestrator = web::Data::new(api::Orchestrator::new(
+        servers,
+        threshold,
+        min_threshold,
+        Duration::from_secs(cli.session_ttl),
+        policies,
+    ));
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(orchestrator.clone())
+            .configure(api::configure)
+    })
+    .bind(("0.0.0.0", cli.port))?
+    .run()
+    .await
+}
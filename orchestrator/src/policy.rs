@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// An authenticator server, as listed in `ACTIVE_SERVERS`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Server {
+    pub url: String,
+    pub server_ty: String,
+}
+
+/// Which factors a user authenticates with, and how many of them must succeed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Policy {
+    pub threshold: usize,
+    /// Urls of the servers the user is driven through.
+    pub factors: Vec<String>,
+}
+
+impl Policy {
+    /// Every server, with `threshold` of them required.
+    pub fn default_for(servers: &[Server], threshold: usize) -> Self {
+        Self {
+            threshold,
+            factors: servers.iter().map(|server| server.url.clone()).collect(),
+        }
+    }
+
+    /// Checks the policy can be satisfied by `servers`, and requires at least `min_threshold`
+    /// factors.
+    ///
+    /// `min_threshold` is the threshold secrets are split with, so a policy requiring fewer
+    /// factors could not release enough shares to recover them.
+    pub fn validate(&self, servers: &[Server], min_threshold: usize) -> Result<(), String> {
+        if let Some(unknown) = self
+            .factors
+            .iter()
+            .find(|url| !servers.iter().any(|server| &server.url == *url))
+        {
+            return Err(format!("{} is not an active server", unknown));
+        }
+
+        let mut factors = self.factors.clone();
+        factors.sort();
+        factors.dedup();
+        if factors.len() != self.factors.len() {
+            return Err("factors must not repeat a server".into());
+        }
+
+        if self.threshold < min_threshold.max(1) || self.threshold > self.factors.len() {
+            return Err(format!(
+                "threshold must be between {} and the number of factors ({})",
+                min_threshold.max(1),
+                self.factors.len()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Policies chosen by users, by email.
+///
+/// When opened from a file, every change is written back to it, so policies outlive the process.
+/// The default store keeps them in memory only.
+#[derive(Default)]
+pub struct PolicyStore {
+    path: Option<PathBuf>,
+    policies: Mutex<HashMap<String, Policy>>,
+}
+
+impl PolicyStore {
+    /// Loads the policies saved at `path`, which is created on the first change.
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+
+        let policies = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            policies: Mutex::new(policies),
+        })
+    }
+
+    pub fn get(&self, email: &str) -> Option<Policy> {
+        self.policies.lock().unwrap().get(email).cloned()
+    }
+
+    /// Sets the policy of a user, which is not changed when it cannot be saved.
+    pub fn set(&self, email: String, policy: Policy) -> std::io::Result<()> {
+        let mut policies = self.policies.lock().unwrap();
+        let previous = policies.insert(email.clone(), policy);
+
+        let saved = match &self.path {
+            Some(path) => Self::save(path, &policies),
+            None => Ok(()),
+        };
+        if saved.is_err() {
+            match previous {
+                Some(previous) => policies.insert(email, previous),
+                None => policies.remove(&email),
+            };
+        }
+
+        saved
+    }
+
+    /// Writes a copy next to `path` first, so a crash never leaves it half written.
+    fn save(path: &Path, policies: &HashMap<String, Policy>) -> std::io::Result<()> {
+        let tmp = path.with_extension("tmp");
+
+        std::fs::write(&tmp, serde_json::to_vec(policies)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> Vec<Server> {
+        ["Email", "QA", "Password"]
+            .iter()
+            .map(|ty| Server {
+                url: format!("https://{}.test", ty.to_lowercase()),
+                server_ty: ty.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_policy() {
+        let policy = Policy::default_for(&servers(), 2);
+
+        assert_eq!(policy.factors.len(), 3);
+        assert_eq!(policy.validate(&servers(), 2), Ok(()));
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let policy = |threshold, factors: &[&str]| Policy {
+            threshold,
+            factors: factors.iter().map(|url| url.to_string()).collect(),
+        };
+
+        assert!(policy(1, &["https://unknown.test"])
+            .validate(&servers(), 1)
+            .is_err());
+        assert!(policy(2, &["https://qa.test", "https://qa.test"])
+            .validate(&servers(), 1)
+            .is_err());
+        assert!(policy(0, &["https://qa.test"])
+            .validate(&servers(), 1)
+            .is_err());
+        assert!(policy(2, &["https://qa.test"])
+            .validate(&servers(), 1)
+            .is_err());
+        assert!(policy(1, &["https://qa.test"])
+            .validate(&servers(), 1)
+            .is_ok());
+
+        // Fewer factors than the secrets are split for could not recover them.
+        let both = &["https://qa.test", "https://email.test"];
+        assert_eq!(
+            policy(1, both).validate(&servers(), 2),
+            Err("threshold must be between 2 and the number of factors (2)".into())
+        );
+        assert!(policy(2, both).validate(&servers(), 2).is_ok());
+    }
+
+    #[test]
+    fn saved_policies() {
+        let path = std::env::temp_dir().join(format!("policies-{}.json", uuid::Uuid::new_v4()));
+        let policy = Policy::default_for(&servers(), 2);
+
+        let store = PolicyStore::open(&path).unwrap();
+        assert_eq!(store.get("benjcape@gmail.com"), None);
+        store
+            .set("benjcape@gmail.com".into(), policy.clone())
+            .unwrap();
+
+        let reopened = PolicyStore::open(&path).unwrap();
+        assert_eq!(reopened.get("benjcape@gmail.com"), Some(policy));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
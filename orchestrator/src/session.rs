@@ -0,0 +1,171 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::policy::{Policy, Server};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FactorStatus {
+    /// `/authenticate` succeeded, waiting for the user to verify.
+    Requested,
+    Verified,
+    /// The last attempt failed, the user may try to verify again.
+    Failed,
+}
+
+/// The `secret_component` released by a server, with the signature it was sent with.
+///
+/// The signature covers the `{ secret_component, commitments }` body the server released.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Share {
+    pub url: String,
+    pub server_ty: String,
+    pub secret_component: Option<String>,
+    /// Commitments of a verifiable share.
+    pub commitments: Option<String>,
+    pub signature: Option<String>,
+    pub public_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Factor {
+    pub url: String,
+    pub server_ty: String,
+    pub status: FactorStatus,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub share: Option<Share>,
+}
+
+/// Progress of a user through the factors of their policy.
+#[derive(Debug)]
+pub struct Session {
+    pub email: String,
+    pub policy: Policy,
+    pub factors: Vec<Factor>,
+    pub created: Instant,
+}
+
+impl Session {
+    pub fn new(email: String, policy: Policy, servers: &[Server]) -> Self {
+        let factors = policy
+            .factors
+            .iter()
+            .filter_map(|url| servers.iter().find(|server| &server.url == url))
+            .map(|server| Factor {
+                url: server.url.clone(),
+                server_ty: server.server_ty.clone(),
+                status: FactorStatus::Requested,
+                error: None,
+                share: None,
+            })
+            .collect();
+
+        Self {
+            email,
+            policy,
+            factors,
+            created: Instant::now(),
+        }
+    }
+
+    pub fn factor_mut(&mut self, url: &str) -> Option<&mut Factor> {
+        self.factors.iter_mut().find(|factor| factor.url == url)
+    }
+
+    pub fn verified(&self) -> usize {
+        self.factors
+            .iter()
+            .filter(|factor| factor.status == FactorStatus::Verified)
+            .count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.verified() >= self.policy.threshold
+    }
+
+    /// The released shares, once the threshold is met.
+    pub fn shares(&self) -> Option<Vec<Share>> {
+        if !self.is_complete() {
+            return None;
+        }
+
+        Some(
+            self.factors
+                .iter()
+                .filter_map(|factor| factor.share.clone())
+                .collect(),
+        )
+    }
+
+    pub fn view(&self, id: Uuid) -> SessionView {
+        SessionView {
+            session_id: id,
+            threshold: self.policy.threshold,
+            complete: self.is_complete(),
+            factors: self.factors.clone(),
+            shares: self.shares(),
+        }
+    }
+}
+
+/// What clients see of a session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionView {
+    pub session_id: Uuid,
+    pub threshold: usize,
+    pub complete: bool,
+    pub factors: Vec<Factor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares: Option<Vec<Share>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(threshold: usize) -> Session {
+        let servers: Vec<Server> = ["Email", "QA", "Password"]
+            .iter()
+            .map(|ty| Server {
+                url: format!("https://{}.test", ty.to_lowercase()),
+                server_ty: ty.to_string(),
+            })
+            .collect();
+
+        Session::new(
+            "benjcape@gmail.com".into(),
+            Policy::default_for(&servers, threshold),
+            &servers,
+        )
+    }
+
+    fn verify(session: &mut Session, url: &str) {
+        let factor = session.factor_mut(url).unwrap();
+        factor.status = FactorStatus::Verified;
+        factor.share = Some(Share {
+            url: url.into(),
+            server_ty: factor.server_ty.clone(),
+            secret_component: Some(format!("share of {}", url)),
+            commitments: None,
+            signature: None,
+            public_key: None,
+        });
+    }
+
+    #[test]
+    fn shares_are_released_at_threshold() {
+        let mut session = session(2);
+
+        verify(&mut session, "https://email.test");
+        session.factor_mut("https://qa.test").unwrap().status = FactorStatus::Failed;
+        assert!(!session.is_complete());
+        assert_eq!(session.shares(), None);
+
+        verify(&mut session, "https://password.test");
+        assert!(session.is_complete());
+        assert_eq!(session.shares().unwrap().len(), 2);
+    }
+}
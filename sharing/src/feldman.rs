@@ -0,0 +1,437 @@
+//! Feldman verifiable secret sharing over the Ristretto group.
+//!
+//! The secret is cut into chunks of [`CHUNK_LEN`] bytes, each the constant term of a random
+//! polynomial over the Ristretto scalars. Along with the shares, the dealer publishes commitments
+//! `C_k = a_k·G` to the coefficients of every polynomial. Anyone holding them can check a share
+//! lies on the polynomials, `f(i)·G = Σ i^k·C_k`, without learning the secret, so a server
+//! returning a wrong `secret_component` is caught and attributed.
+//!
+//! The commitments include `chunk·G` for every chunk, so each chunk is padded with [`PAD_LEN`]
+//! random bytes: otherwise a short or guessable secret could be found by trying candidates
+//! against them. The padding is shared along with the secret, and dropped when combining.
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_TABLE;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::Identity;
+use rand::RngCore;
+
+use crate::{checked, checksummed, Error, Scheme, Share};
+
+/// Bytes of the secret held by each scalar.
+pub const CHUNK_LEN: usize = 14;
+/// Random bytes padding each chunk, at least.
+pub const PAD_LEN: usize = 16;
+/// Bytes of a chunk and its padding. The next byte holds the length of the chunk, which keeps
+/// every chunk scalar canonical.
+const PADDED_LEN: usize = CHUNK_LEN + PAD_LEN;
+const SCALAR_LEN: usize = 32;
+
+/// The commitments to the polynomials of a split, published at enrollment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commitments {
+    pub threshold: u8,
+    /// `threshold` points per chunk of the secret, lowest degree first.
+    points: Vec<CompressedRistretto>,
+}
+
+impl Commitments {
+    /// Encodes the commitments as base64 of `version | threshold | points | checksum`.
+    pub fn encode(&self) -> String {
+        let mut bytes = vec![Scheme::Feldman as u8, self.threshold];
+        for point in &self.points {
+            bytes.extend_from_slice(point.as_bytes());
+        }
+
+        checksummed(bytes)
+    }
+
+    /// Decodes and checks commitments produced by [`Commitments::encode`].
+    pub fn decode(encoded: &str) -> Result<Self, Error> {
+        let bytes = base64::decode(encoded.trim()).map_err(|_| Error::MalformedCommitments)?;
+
+        if bytes.len() < 2 + SCALAR_LEN {
+            return Err(Error::MalformedCommitments);
+        }
+        if bytes[0] != Scheme::Feldman as u8 {
+            return Err(Error::UnsupportedVersion(bytes[0]));
+        }
+
+        let content = checked(&bytes)?;
+        let threshold = content[1];
+        let points = &content[2..];
+
+        if threshold == 0 || !points.len().is_multiple_of(SCALAR_LEN * threshold as usize) {
+            return Err(Error::MalformedCommitments);
+        }
+
+        let points = points
+            .chunks_exact(SCALAR_LEN)
+            .map(|point| {
+                let point = CompressedRistretto::from_slice(point);
+                point
+                    .decompress()
+                    .map(|_| point)
+                    .ok_or(Error::MalformedCommitments)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { threshold, points })
+    }
+
+    /// Checks `share` is one of the shares these commitments were published with.
+    pub fn verify(&self, share: &Share) -> Result<(), Error> {
+        if share.scheme != Scheme::Feldman {
+            return Err(Error::NotVerifiable);
+        }
+
+        let values = scalars(&share.payload)?;
+        let threshold = self.threshold as usize;
+        if share.threshold != self.threshold || values.len() * threshold != self.points.len() {
+            return Err(Error::Mismatch);
+        }
+
+        let x = Scalar::from(share.index as u64);
+
+        for (value, points) in values.iter().zip(self.points.chunks_exact(threshold)) {
+            let expected = points
+                .iter()
+                .rev()
+                .try_fold(RistrettoPoint::identity(), |acc, point| {
+                    point.decompress().map(|point| acc * x + point)
+                })
+                .ok_or(Error::MalformedCommitments)?;
+
+            if value * &RISTRETTO_BASEPOINT_TABLE != expected {
+                return Err(Error::InvalidShare(share.index));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The commitments after a refresh adding the shares of zero dealt with `deltas`.
+    ///
+    /// Every polynomial of a delta must have a constant term of zero, committed to as the
+    /// identity, or the refresh would change the secret.
+    pub fn refresh(&self, deltas: &[Commitments]) -> Result<Commitments, Error> {
+        let mut points: Vec<RistrettoPoint> = self
+            .points
+            .iter()
+            .map(|point| point.decompress().ok_or(Error::MalformedCommitments))
+            .collect::<Result<_, _>>()?;
+
+        for delta in deltas {
+            if delta.threshold != self.threshold || delta.points.len() != points.len() {
+                return Err(Error::Mismatch);
+            }
+            let zero = RistrettoPoint::identity().compress();
+            if delta
+                .points
+                .chunks_exact(self.threshold as usize)
+                .any(|points| points[0] != zero)
+            {
+                return Err(Error::NonZeroDelta);
+            }
+
+            for (point, delta) in points.iter_mut().zip(&delta.points) {
+                *point += delta.decompress().ok_or(Error::MalformedCommitments)?;
+            }
+        }
+
+        Ok(Commitments {
+            threshold: self.threshold,
+            points: points.iter().map(RistrettoPoint::compress).collect(),
+        })
+    }
+}
+
+/// Splits `secret` into `shares` verifiable shares, any `threshold` of which recover it.
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<(Vec<Share>, Commitments), Error> {
+    split_with_rng(secret, threshold, shares, &mut rand::rngs::OsRng)
+}
+
+/// [`split`], drawing the polynomial coefficients from `rng`.
+pub fn split_with_rng<R: RngCore>(
+    secret: &[u8],
+    threshold: u8,
+    shares: u8,
+    rng: &mut R,
+) -> Result<(Vec<Share>, Commitments), Error> {
+    if threshold == 0 || threshold > shares {
+        return Err(Error::InvalidThreshold { threshold, shares });
+    }
+    if secret.is_empty() {
+        return Err(Error::EmptySecret);
+    }
+
+    let mut result: Vec<Share> = (1..=shares)
+        .map(|index| Share {
+            scheme: Scheme::Feldman,
+            threshold,
+            index,
+            epoch: 0,
+            payload: Vec::new(),
+        })
+        .collect();
+    let mut points = Vec::new();
+
+    for chunk in secret.chunks(CHUNK_LEN) {
+        let coefficients: Vec<Scalar> = std::iter::once(chunk_scalar(chunk, rng))
+            .chain((1..threshold).map(|_| random_scalar(rng)))
+            .collect();
+
+        points.extend(
+            coefficients
+                .iter()
+                .map(|coefficient| (coefficient * &RISTRETTO_BASEPOINT_TABLE).compress()),
+        );
+
+        for share in result.iter_mut() {
+            share
+                .payload
+                .extend_from_slice(eval(&coefficients, share.index).as_bytes());
+        }
+    }
+
+    Ok((result, Commitments { threshold, points }))
+}
+
+/// Shares of zero for `indices`, shaped like `like` and for the epoch after it, with the
+/// commitments to their polynomials.
+pub(crate) fn zero_shares<R: RngCore>(
+    like: &Share,
+    indices: &[u8],
+    rng: &mut R,
+) -> Result<(Vec<Share>, Commitments), Error> {
+    let chunks = scalars(&like.payload)?.len();
+
+    let mut result: Vec<Share> = indices
+        .iter()
+        .map(|index| Share {
+            payload: Vec::with_capacity(like.payload.len()),
+            index: *index,
+            epoch: like.epoch + 1,
+            ..like.clone()
+        })
+        .collect();
+    let mut points = Vec::with_capacity(chunks * like.threshold as usize);
+
+    for _ in 0..chunks {
+        let coefficients: Vec<Scalar> = std::iter::once(Scalar::zero())
+            .chain((1..like.threshold).map(|_| random_scalar(rng)))
+            .collect();
+
+        points.extend(
+            coefficients
+                .iter()
+                .map(|coefficient| (coefficient * &RISTRETTO_BASEPOINT_TABLE).compress()),
+        );
+
+        for share in result.iter_mut() {
+            share
+                .payload
+                .extend_from_slice(eval(&coefficients, share.index).as_bytes());
+        }
+    }
+
+    Ok((
+        result,
+        Commitments {
+            threshold: like.threshold,
+            points,
+        },
+    ))
+}
+
+/// Adds the payloads of two shares of the same shape.
+pub(crate) fn add(a: &[u8], b: &[u8]) -> Result<Vec<u8>, Error> {
+    let (a, b) = (scalars(a)?, scalars(b)?);
+    if a.len() != b.len() {
+        return Err(Error::Mismatch);
+    }
+
+    Ok(a.iter()
+        .zip(&b)
+        .flat_map(|(a, b)| (a + b).to_bytes())
+        .collect())
+}
+
+fn eval(coefficients: &[Scalar], index: u8) -> Scalar {
+    let x = Scalar::from(index as u64);
+
+    coefficients
+        .iter()
+        .rev()
+        .fold(Scalar::zero(), |acc, coefficient| acc * x + coefficient)
+}
+
+/// Recovers the secret from `threshold` shares, already checked to come from the same split.
+pub(crate) fn combine(shares: &[Share]) -> Result<Vec<u8>, Error> {
+    let values = shares
+        .iter()
+        .map(|share| scalars(&share.payload))
+        .collect::<Result<Vec<_>, _>>()?;
+    let xs: Vec<Scalar> = shares
+        .iter()
+        .map(|share| Scalar::from(share.index as u64))
+        .collect();
+
+    let basis: Vec<Scalar> = xs
+        .iter()
+        .enumerate()
+        .map(|(i, xi)| {
+            xs.iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .fold(Scalar::one(), |basis, (_, xj)| {
+                    basis * xj * (xj - xi).invert()
+                })
+        })
+        .collect();
+
+    let mut secret = Vec::new();
+    for chunk in 0..values[0].len() {
+        let scalar = values
+            .iter()
+            .zip(&basis)
+            .fold(Scalar::zero(), |acc, (value, basis)| {
+                acc + value[chunk] * basis
+            });
+
+        let bytes = scalar.to_bytes();
+        let len = bytes[PADDED_LEN] as usize;
+        if len == 0 || len > CHUNK_LEN || bytes[PADDED_LEN + 1..].iter().any(|byte| *byte != 0) {
+            return Err(Error::Mismatch);
+        }
+
+        secret.extend_from_slice(&bytes[..len]);
+    }
+
+    Ok(secret)
+}
+
+/// Checks the payload of a share is a sequence of canonical scalars.
+pub(crate) fn scalars(payload: &[u8]) -> Result<Vec<Scalar>, Error> {
+    if payload.is_empty() || !payload.len().is_multiple_of(SCALAR_LEN) {
+        return Err(Error::Malformed);
+    }
+
+    payload
+        .chunks_exact(SCALAR_LEN)
+        .map(|bytes| {
+            let mut scalar = [0u8; SCALAR_LEN];
+            scalar.copy_from_slice(bytes);
+            Scalar::from_canonical_bytes(scalar).ok_or(Error::Malformed)
+        })
+        .collect()
+}
+
+fn chunk_scalar<R: RngCore>(chunk: &[u8], rng: &mut R) -> Scalar {
+    let mut bytes = [0u8; SCALAR_LEN];
+    bytes[..chunk.len()].copy_from_slice(chunk);
+    rng.fill_bytes(&mut bytes[chunk.len()..PADDED_LEN]);
+    bytes[PADDED_LEN] = chunk.len() as u8;
+
+    Scalar::from_canonical_bytes(bytes).expect("chunk scalars are below 2^248")
+}
+
+fn random_scalar<R: RngCore>(rng: &mut R) -> Scalar {
+    let mut bytes = [0u8; 64];
+    rng.fill_bytes(&mut bytes);
+
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn verify_shares() {
+        let (shares, commitments) = split(b"correct horse battery staple", 2, 3).unwrap();
+        let (others, _) = split(b"correct horse battery staple", 2, 3).unwrap();
+
+        for share in &shares {
+            assert_eq!(commitments.verify(share), Ok(()));
+        }
+        assert_eq!(commitments.verify(&others[0]), Err(Error::InvalidShare(1)));
+
+        let shamir = crate::split(b"correct horse battery staple", 2, 3).unwrap();
+        assert_eq!(commitments.verify(&shamir[0]), Err(Error::NotVerifiable));
+    }
+
+    #[test]
+    fn padded_chunks() {
+        let (shares, commitments) = split(b"42", 1, 2).unwrap();
+        let (_, again) = split(b"42", 1, 2).unwrap();
+
+        // Trying the secret against the commitments does not find it.
+        let mut unpadded = [0u8; SCALAR_LEN];
+        unpadded[..2].copy_from_slice(b"42");
+        unpadded[PADDED_LEN] = 2;
+        let guess = Scalar::from_canonical_bytes(unpadded).unwrap();
+        assert_ne!(
+            commitments.points[0],
+            (&guess * &RISTRETTO_BASEPOINT_TABLE).compress()
+        );
+        assert_ne!(commitments.points, again.points);
+        assert_eq!(crate::combine(&shares[..1]).unwrap(), b"42");
+    }
+
+    #[test]
+    fn malformed_commitments() {
+        let (_, commitments) = split(b"secret", 2, 3).unwrap();
+
+        let mut bytes = base64::decode(commitments.encode()).unwrap();
+        bytes[5] ^= 1;
+        assert!(Commitments::decode(&base64::encode(&bytes)).is_err());
+
+        assert_eq!(
+            Commitments::decode("foobar"),
+            Err(Error::MalformedCommitments)
+        );
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn verified_shares_recover_the_secret(
+            secret in prop::collection::vec(any::<u8>(), 1..100),
+            (threshold, count) in (1u8..5).prop_flat_map(|t| (Just(t), t..6)),
+        ) {
+            let (shares, commitments) = split(&secret, threshold, count).unwrap();
+            let commitments = Commitments::decode(&commitments.encode()).unwrap();
+
+            for share in &shares {
+                let share = Share::decode(&share.encode()).unwrap();
+                prop_assert_eq!(commitments.verify(&share), Ok(()));
+            }
+
+            prop_assert_eq!(crate::combine(&shares[shares.len() - threshold as usize..]).unwrap(), secret);
+        }
+
+        #[test]
+        fn tampered_shares_fail_verification(
+            secret in prop::collection::vec(any::<u8>(), 1..100),
+            position in any::<prop::sample::Index>(),
+            delta in 1u64..,
+        ) {
+            let (shares, commitments) = split(&secret, 2, 3).unwrap();
+
+            let mut values = scalars(&shares[0].payload).unwrap();
+            let position = position.index(values.len());
+            values[position] += Scalar::from(delta);
+
+            let tampered = Share {
+                payload: values.iter().flat_map(|value| value.to_bytes()).collect(),
+                ..shares[0].clone()
+            };
+
+            prop_assert_eq!(commitments.verify(&tampered), Err(Error::InvalidShare(1)));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Arithmetic in GF(2^8), with the AES polynomial x^8 + x^4 + x^3 + x + 1.
+//!
+//! Multiplication does not use lookup tables, so its timing does not depend on secret bytes.
+
+/// Addition, and subtraction, are XOR.
+pub fn add(a: u8, b: u8) -> u8 {
+    a ^ b
+}
+
+pub fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+
+    for _ in 0..8 {
+        // Masks instead of branches on the bits of the operands.
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+
+    product
+}
+
+/// The multiplicative inverse, `a^254`. Zero has no inverse, and maps to zero.
+pub fn inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exp = 254u8;
+
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exp >>= 1;
+    }
+
+    result
+}
+
+pub fn div(a: u8, b: u8) -> u8 {
+    mul(a, inv(b))
+}
+
+/// Evaluates the polynomial with `coefficients`, constant term first, at `x`.
+pub fn eval(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |acc, coefficient| add(mul(acc, x), *coefficient))
+}
+
+/// Interpolates the polynomial through `points` and evaluates it at zero.
+pub fn interpolate_at_zero(points: &[(u8, u8)]) -> u8 {
+    points.iter().enumerate().fold(0, |acc, (i, (xi, yi))| {
+        let basis = points
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(1, |basis, (_, (xj, _))| mul(basis, div(*xj, add(*xj, *xi))));
+
+        add(acc, mul(*yi, basis))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_products() {
+        // FIPS-197, section 4.2
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+        assert_eq!(mul(0x57, 0x13), 0xfe);
+    }
+
+    #[test]
+    fn inverses() {
+        assert_eq!(inv(0), 0);
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inv(a)), 1, "{}", a);
+        }
+    }
+}
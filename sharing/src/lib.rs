@@ -0,0 +1,426 @@
+//! Shamir secret sharing over GF(256).
+//!
+//! A secret is split byte by byte: each byte is the constant term of a random polynomial of degree
+//! `threshold - 1`, and share `i` holds the evaluations of every polynomial at `x = i`. Any
+//! `threshold` shares recover the secret, fewer reveal nothing about it.
+//!
+//! Shares are exchanged as the `secret_component` of the authenticator servers, encoded with
+//! [`Share::encode`], so a server can check a share is well formed without learning anything.
+//! Shares split with [`feldman::split`] can also be checked against the commitments published
+//! with them, catching a server returning a wrong share.
+//!
+//! Shares can be refreshed without reconstructing the secret by adding shares of zero to them,
+//! see [`refresh`]. Every refresh moves them to the next epoch, and shares of different epochs
+//! do not combine.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+pub mod feldman;
+mod gf256;
+pub mod refresh;
+
+pub use feldman::Commitments;
+
+/// Bytes of the version, threshold, index and epoch opening an encoded share.
+const HEADER_LEN: usize = 7;
+/// Bytes of the truncated SHA-256 checksum closing an encoded share.
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The threshold must be at least 1, and at most the number of shares.
+    InvalidThreshold {
+        threshold: u8,
+        shares: u8,
+    },
+    EmptySecret,
+    /// Fewer shares than their threshold were given to [`combine`].
+    TooFewShares {
+        threshold: u8,
+        given: usize,
+    },
+    /// Two shares with the same index were given to [`combine`].
+    DuplicateIndex(u8),
+    /// The shares do not come from the same split.
+    Mismatch,
+    /// The shares were refreshed a different number of times.
+    EpochMismatch,
+    /// The share is not base64, or too short.
+    Malformed,
+    UnsupportedVersion(u8),
+    BadChecksum,
+    /// The commitments are not base64, or not points of the group.
+    MalformedCommitments,
+    /// A plain Shamir share was checked against commitments.
+    NotVerifiable,
+    /// The share with this index does not match the commitments.
+    InvalidShare(u8),
+    /// Commitments added in a refresh do not commit to shares of zero.
+    NonZeroDelta,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidThreshold { threshold, shares } => write!(
+                f,
+                "threshold {} must be between 1 and the number of shares ({})",
+                threshold, shares
+            ),
+            Self::EmptySecret => write!(f, "the secret is empty"),
+            Self::TooFewShares { threshold, given } => {
+                write!(f, "{} shares are needed, {} were given", threshold, given)
+            }
+            Self::DuplicateIndex(index) => write!(f, "share {} was given twice", index),
+            Self::Mismatch => write!(f, "the shares do not belong to the same secret"),
+            Self::EpochMismatch => write!(f, "the shares are from different refresh epochs"),
+            Self::Malformed => write!(f, "the share is malformed"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "share encoding version {} is not supported", version)
+            }
+            Self::BadChecksum => write!(f, "the share checksum does not match"),
+            Self::MalformedCommitments => write!(f, "the commitments are malformed"),
+            Self::NotVerifiable => write!(f, "the share has no commitments to be checked against"),
+            Self::InvalidShare(index) => {
+                write!(f, "share {} does not match the commitments", index)
+            }
+            Self::NonZeroDelta => write!(f, "the refresh does not add shares of zero"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How a share was produced, and the version byte of its encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Shamir over GF(256), one byte of payload per byte of secret.
+    Shamir = 1,
+    /// Feldman over the Ristretto group, one scalar of payload per chunk of secret.
+    Feldman = 2,
+}
+
+/// A single share of a secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub scheme: Scheme,
+    /// Number of shares needed to recover the secret.
+    pub threshold: u8,
+    /// The `x` coordinate of the share, from 1.
+    pub index: u8,
+    /// Number of times the share was refreshed.
+    pub epoch: u32,
+    /// The evaluations of the polynomials of the secret at `index`.
+    pub payload: Vec<u8>,
+}
+
+impl Share {
+    /// Encodes the share as base64 of `version | threshold | index | epoch | payload | checksum`.
+    ///
+    /// The epoch is 4 bytes, big endian.
+    ///
+    /// The checksum is the first 4 bytes of the SHA-256 of everything before it.
+    pub fn encode(&self) -> String {
+        let mut bytes = vec![self.scheme as u8, self.threshold, self.index];
+        bytes.extend_from_slice(&self.epoch.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+
+        checksummed(bytes)
+    }
+
+    /// Decodes and checks a share produced by [`Share::encode`].
+    pub fn decode(encoded: &str) -> Result<Self, Error> {
+        let bytes = base64::decode(encoded.trim()).map_err(|_| Error::Malformed)?;
+
+        // Header, at least one byte of payload, and the checksum.
+        if bytes.len() < HEADER_LEN + 1 + CHECKSUM_LEN {
+            return Err(Error::Malformed);
+        }
+        let scheme = match bytes[0] {
+            1 => Scheme::Shamir,
+            2 => Scheme::Feldman,
+            version => return Err(Error::UnsupportedVersion(version)),
+        };
+
+        let content = checked(&bytes)?;
+
+        let share = Self {
+            scheme,
+            threshold: content[1],
+            index: content[2],
+            epoch: u32::from_be_bytes([content[3], content[4], content[5], content[6]]),
+            payload: content[HEADER_LEN..].to_vec(),
+        };
+        if share.threshold == 0 || share.index == 0 {
+            return Err(Error::Malformed);
+        }
+        if scheme == Scheme::Feldman {
+            feldman::scalars(&share.payload)?;
+        }
+
+        Ok(share)
+    }
+}
+
+/// Appends the checksum to `bytes`, and encodes them as base64.
+fn checksummed(mut bytes: Vec<u8>) -> String {
+    let checksum = Sha256::digest(&bytes);
+    bytes.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+
+    base64::encode(bytes)
+}
+
+/// Checks the checksum closing `bytes`, returning what it covers.
+fn checked(bytes: &[u8]) -> Result<&[u8], Error> {
+    let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    if Sha256::digest(content)[..CHECKSUM_LEN] != *checksum {
+        return Err(Error::BadChecksum);
+    }
+
+    Ok(content)
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+impl FromStr for Share {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(s)
+    }
+}
+
+impl TryFrom<&str> for Share {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::decode(value)
+    }
+}
+
+/// Splits `secret` into `shares` shares, any `threshold` of which recover it.
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>, Error> {
+    split_with_rng(secret, threshold, shares, &mut rand::rngs::OsRng)
+}
+
+/// [`split`], drawing the polynomial coefficients from `rng`.
+pub fn split_with_rng<R: RngCore>(
+    secret: &[u8],
+    threshold: u8,
+    shares: u8,
+    rng: &mut R,
+) -> Result<Vec<Share>, Error> {
+    if threshold == 0 || threshold > shares {
+        return Err(Error::InvalidThreshold { threshold, shares });
+    }
+    if secret.is_empty() {
+        return Err(Error::EmptySecret);
+    }
+
+    let mut result: Vec<Share> = (1..=shares)
+        .map(|index| Share {
+            scheme: Scheme::Shamir,
+            threshold,
+            index,
+            epoch: 0,
+            payload: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    let mut coefficients = vec![0u8; threshold as usize];
+    for byte in secret {
+        coefficients[0] = *byte;
+        rng.fill_bytes(&mut coefficients[1..]);
+
+        for share in result.iter_mut() {
+            share.payload.push(gf256::eval(&coefficients, share.index));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Recovers the secret from at least `threshold` shares of the same split.
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>, Error> {
+    let first = shares.first().ok_or(Error::TooFewShares {
+        threshold: 1,
+        given: 0,
+    })?;
+
+    for (i, share) in shares.iter().enumerate() {
+        if share.scheme != first.scheme
+            || share.threshold != first.threshold
+            || share.payload.len() != first.payload.len()
+            || share.payload.is_empty()
+        {
+            return Err(Error::Mismatch);
+        }
+        if share.epoch != first.epoch {
+            return Err(Error::EpochMismatch);
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(Error::DuplicateIndex(share.index));
+        }
+    }
+
+    if shares.len() < first.threshold as usize {
+        return Err(Error::TooFewShares {
+            threshold: first.threshold,
+            given: shares.len(),
+        });
+    }
+
+    let shares = &shares[..first.threshold as usize];
+
+    if first.scheme == Scheme::Feldman {
+        return feldman::combine(shares);
+    }
+
+    Ok((0..first.payload.len())
+        .map(|i| {
+            let points: Vec<(u8, u8)> = shares
+                .iter()
+                .map(|share| (share.index, share.payload[i]))
+                .collect();
+
+            gf256::interpolate_at_zero(&points)
+        })
+        .collect())
+}
+
+/// Decodes every share, then [`combine`]s them.
+pub fn combine_encoded<S: AsRef<str>>(shares: &[S]) -> Result<Vec<u8>, Error> {
+    let shares = shares
+        .iter()
+        .map(|share| Share::decode(share.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    combine(&shares)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn split_and_combine() {
+        let shares = split(b"correct horse battery staple", 2, 3).unwrap();
+
+        assert_eq!(
+            combine(&shares[1..]).unwrap(),
+            b"correct horse battery staple"
+        );
+        assert_eq!(
+            combine(&shares[..1]),
+            Err(Error::TooFewShares {
+                threshold: 2,
+                given: 1
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_splits() {
+        assert_eq!(
+            split(b"secret", 4, 3),
+            Err(Error::InvalidThreshold {
+                threshold: 4,
+                shares: 3
+            })
+        );
+        assert_eq!(split(b"", 2, 3), Err(Error::EmptySecret));
+    }
+
+    #[test]
+    fn invalid_combinations() {
+        let shares = split(b"secret", 2, 3).unwrap();
+        let others = split(b"other secret", 2, 3).unwrap();
+
+        assert_eq!(
+            combine(&[shares[0].clone(), shares[0].clone()]),
+            Err(Error::DuplicateIndex(1))
+        );
+        assert_eq!(
+            combine(&[shares[0].clone(), others[1].clone()]),
+            Err(Error::Mismatch)
+        );
+    }
+
+    #[test]
+    fn malformed_encodings() {
+        let encoded = split(b"secret", 2, 3).unwrap()[0].encode();
+
+        let mut bytes = base64::decode(&encoded).unwrap();
+        bytes[4] ^= 1;
+        assert_eq!(
+            Share::decode(&base64::encode(&bytes)),
+            Err(Error::BadChecksum)
+        );
+
+        bytes[0] = 9;
+        assert_eq!(
+            Share::decode(&base64::encode(&bytes)),
+            Err(Error::UnsupportedVersion(9))
+        );
+
+        assert_eq!(Share::decode("foobar"), Err(Error::Malformed));
+        assert_eq!(Share::decode(""), Err(Error::Malformed));
+        assert_eq!(Share::decode("not base64!"), Err(Error::Malformed));
+    }
+
+    proptest! {
+        #[test]
+        fn any_threshold_of_shares_recovers_the_secret(
+            secret in prop::collection::vec(any::<u8>(), 1..64),
+            (threshold, count) in (1u8..8).prop_flat_map(|t| (Just(t), t..10)),
+            seed in any::<u64>(),
+        ) {
+            let shares = split(&secret, threshold, count).unwrap();
+
+            // Any subset of `threshold` shares, chosen by the seed.
+            let mut chosen = shares.clone();
+            let mut seed = seed;
+            while chosen.len() > threshold as usize {
+                chosen.remove((seed % chosen.len() as u64) as usize);
+                seed /= 3;
+            }
+
+            prop_assert_eq!(combine(&chosen).unwrap(), secret);
+        }
+
+        #[test]
+        fn encoding_round_trips(
+            secret in prop::collection::vec(any::<u8>(), 1..64),
+            threshold in 1u8..5,
+        ) {
+            for share in split(&secret, threshold, 5).unwrap() {
+                prop_assert_eq!(Share::decode(&share.encode()).unwrap(), share);
+            }
+        }
+
+        #[test]
+        fn corrupted_encodings_are_rejected(
+            secret in prop::collection::vec(any::<u8>(), 1..64),
+            position in any::<prop::sample::Index>(),
+            flip in 1u8..=255,
+        ) {
+            let share = &split(&secret, 2, 3).unwrap()[0];
+            let mut bytes = base64::decode(share.encode()).unwrap();
+            let position = position.index(bytes.len());
+            bytes[position] ^= flip;
+
+            prop_assert!(Share::decode(&base64::encode(&bytes)).is_err());
+        }
+    }
+}
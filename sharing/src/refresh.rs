@@ -0,0 +1,229 @@
+//! Proactive refresh of shares.
+//!
+//! Every holder deals shares of zero to all holders, at their indices, and each holder adds the
+//! shares of zero it received to its own. The secret is unchanged, but shares from before the
+//! refresh no longer combine with shares after it, so an attacker must compromise `threshold`
+//! holders within a single epoch.
+//!
+//! For verifiable shares, the dealer also publishes the commitments to its zero polynomials, and
+//! every holder adds them to the commitments of the secret.
+
+use rand::RngCore;
+
+use crate::{feldman, gf256, Commitments, Error, Scheme, Share};
+
+/// Shares of zero for the holders of `indices`, shaped like `like` and for the epoch after it.
+///
+/// Verifiable shares come with the commitments to their polynomials.
+pub fn zero_shares(
+    like: &Share,
+    indices: &[u8],
+) -> Result<(Vec<Share>, Option<Commitments>), Error> {
+    zero_shares_with_rng(like, indices, &mut rand::rngs::OsRng)
+}
+
+/// [`zero_shares`], drawing the polynomial coefficients from `rng`.
+pub fn zero_shares_with_rng<R: RngCore>(
+    like: &Share,
+    indices: &[u8],
+    rng: &mut R,
+) -> Result<(Vec<Share>, Option<Commitments>), Error> {
+    if indices.len() < like.threshold as usize {
+        return Err(Error::InvalidThreshold {
+            threshold: like.threshold,
+            shares: indices.len() as u8,
+        });
+    }
+    for (i, index) in indices.iter().enumerate() {
+        if *index == 0 {
+            return Err(Error::Malformed);
+        }
+        if indices[..i].contains(index) {
+            return Err(Error::DuplicateIndex(*index));
+        }
+    }
+
+    if like.scheme == Scheme::Feldman {
+        let (shares, commitments) = feldman::zero_shares(like, indices, rng)?;
+        return Ok((shares, Some(commitments)));
+    }
+
+    let mut shares: Vec<Share> = indices
+        .iter()
+        .map(|index| Share {
+            payload: Vec::with_capacity(like.payload.len()),
+            index: *index,
+            epoch: like.epoch + 1,
+            ..like.clone()
+        })
+        .collect();
+
+    let mut coefficients = vec![0u8; like.threshold as usize];
+    for _ in 0..like.payload.len() {
+        rng.fill_bytes(&mut coefficients[1..]);
+
+        for share in shares.iter_mut() {
+            share.payload.push(gf256::eval(&coefficients, share.index));
+        }
+    }
+
+    Ok((shares, None))
+}
+
+impl Share {
+    /// The share after adding the shares of zero dealt to it for the next epoch.
+    pub fn refresh(&self, deltas: &[Share]) -> Result<Share, Error> {
+        if deltas.is_empty() {
+            return Err(Error::TooFewShares {
+                threshold: 1,
+                given: 0,
+            });
+        }
+
+        let mut share = Share {
+            epoch: self.epoch + 1,
+            ..self.clone()
+        };
+
+        for delta in deltas {
+            if delta.epoch != share.epoch {
+                return Err(Error::EpochMismatch);
+            }
+            if delta.scheme != share.scheme
+                || delta.threshold != share.threshold
+                || delta.index != share.index
+                || delta.payload.len() != share.payload.len()
+            {
+                return Err(Error::Mismatch);
+            }
+
+            share.payload = match share.scheme {
+                Scheme::Shamir => share
+                    .payload
+                    .iter()
+                    .zip(&delta.payload)
+                    .map(|(a, b)| gf256::add(*a, *b))
+                    .collect(),
+                Scheme::Feldman => feldman::add(&share.payload, &delta.payload)?,
+            };
+        }
+
+        Ok(share)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::combine;
+
+    /// Refreshes every share, with every holder dealing.
+    fn refresh_all(shares: &[Share]) -> (Vec<Share>, Vec<Option<Commitments>>) {
+        let indices: Vec<u8> = shares.iter().map(|share| share.index).collect();
+        let dealt: Vec<_> = shares
+            .iter()
+            .map(|share| zero_shares(share, &indices).unwrap())
+            .collect();
+
+        let refreshed = shares
+            .iter()
+            .enumerate()
+            .map(|(i, share)| {
+                let deltas: Vec<Share> = dealt.iter().map(|(zeros, _)| zeros[i].clone()).collect();
+                share.refresh(&deltas).unwrap()
+            })
+            .collect();
+
+        (refreshed, dealt.into_iter().map(|(_, c)| c).collect())
+    }
+
+    #[test]
+    fn stale_shares_do_not_combine() {
+        let shares = crate::split(b"correct horse battery staple", 2, 3).unwrap();
+        let (refreshed, _) = refresh_all(&shares);
+
+        assert!(refreshed.iter().all(|share| share.epoch == 1));
+        assert_ne!(refreshed[0].payload, shares[0].payload);
+        assert_eq!(
+            combine(&[shares[0].clone(), refreshed[1].clone()]),
+            Err(Error::EpochMismatch)
+        );
+        assert_eq!(
+            shares[0].refresh(&[shares[1].clone()]),
+            Err(Error::EpochMismatch)
+        );
+    }
+
+    #[test]
+    fn refreshed_commitments_verify_refreshed_shares() {
+        let (shares, commitments) = feldman::split(b"correct horse battery staple", 2, 3).unwrap();
+        let (refreshed, dealt) = refresh_all(&shares);
+
+        let dealt: Vec<Commitments> = dealt.into_iter().map(Option::unwrap).collect();
+        let refreshed_commitments = commitments.refresh(&dealt).unwrap();
+
+        for (share, old) in refreshed.iter().zip(&shares) {
+            assert_eq!(refreshed_commitments.verify(share), Ok(()));
+            assert_eq!(
+                refreshed_commitments.verify(old),
+                Err(Error::InvalidShare(old.index))
+            );
+        }
+    }
+
+    #[test]
+    fn non_zero_deltas() {
+        let (shares, commitments) = feldman::split(b"correct horse battery staple", 2, 3).unwrap();
+
+        // A dealer sharing another secret instead of zero.
+        let (mut deltas, dealt) = feldman::split(b"correct horse battery stable", 2, 3).unwrap();
+        for delta in deltas.iter_mut() {
+            delta.epoch = 1;
+        }
+        assert_eq!(dealt.verify(&deltas[0]), Ok(()));
+        assert_eq!(commitments.refresh(&[dealt]), Err(Error::NonZeroDelta));
+
+        // Without commitments, the share is refreshed all the same.
+        assert!(shares[0].refresh(&deltas[..1]).is_ok());
+    }
+
+    #[test]
+    fn invalid_zero_shares() {
+        let share = &crate::split(b"secret", 2, 3).unwrap()[0];
+
+        assert_eq!(
+            zero_shares(share, &[1]),
+            Err(Error::InvalidThreshold {
+                threshold: 2,
+                shares: 1
+            })
+        );
+        assert_eq!(zero_shares(share, &[1, 1]), Err(Error::DuplicateIndex(1)));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn refreshing_preserves_the_secret(
+            secret in prop::collection::vec(any::<u8>(), 1..64),
+            (threshold, count) in (1u8..5).prop_flat_map(|t| (Just(t), t..6)),
+            verifiable in any::<bool>(),
+            rounds in 1usize..3,
+        ) {
+            let mut shares = if verifiable {
+                feldman::split(&secret, threshold, count).unwrap().0
+            } else {
+                crate::split(&secret, threshold, count).unwrap()
+            };
+
+            for _ in 0..rounds {
+                shares = refresh_all(&shares).0;
+            }
+
+            prop_assert_eq!(combine(&shares[count as usize - threshold as usize..]).unwrap(), secret);
+        }
+    }
+}
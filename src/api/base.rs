@@ -1,30 +1,87 @@
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
-    time::SystemTime,
+    net::IpAddr,
+    time::{Instant, SystemTime},
 };
 
 use actix_web::HttpResponse;
 use hyper::StatusCode;
-use sqlx::{types::Uuid, PgPool};
+use sqlx::types::Uuid;
 use totp_rs::TOTP;
 
 use crate::api::VerificationStatus;
+use crate::audit::{self, AuditEvent, Origin};
+use crate::config::{EmailOptions, OtpOptions, Server, ServerType};
+use crate::identity::Identity;
+use crate::metrics;
 
+/// A pending registration: its id, `secret_component`, commitments and data.
+pub type Prepared = (String, String, Option<String>, serde_json::Value);
+
+#[derive(Clone)]
 pub struct BaseAuthenticator {
     #[cfg(not(test))]
     pub sg_client: sendgrid::SGClient,
     pub pool: sqlx::Pool<sqlx::Postgres>,
+    pub max_connections: u32,
+    pub otp: OtpOptions,
+    pub email: EmailOptions,
+    pub identity: Identity,
+    /// Proxies trusted to forward the client address recorded in the audit log.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+// Written out, as the SendGrid client and email options would print the API key.
+impl std::fmt::Debug for BaseAuthenticator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BaseAuthenticator")
+            .field("pool", &self.pool)
+            .field("max_connections", &self.max_connections)
+            .field("otp", &self.otp)
+            .field("email_from", &self.email.from)
+            .field("public_key", &self.identity.public_key())
+            .finish()
+    }
 }
 
 impl BaseAuthenticator {
-    pub fn new(pool: PgPool) -> Self {
-        #[cfg(not(test))]
-        let my_secret_key = std::env::var("SENDGRID_KEY").expect("need SENDGRID_KEY to test");
+    pub fn new(server: &Server) -> Self {
         Self {
             #[cfg(not(test))]
-            sg_client: sendgrid::SGClient::new(my_secret_key),
-            pool,
+            sg_client: sendgrid::SGClient::new(server.email.sendgrid_key.clone()),
+            pool: server.database.clone(),
+            max_connections: server.max_connections,
+            otp: server.otp,
+            email: server.email.clone(),
+            identity: server.identity.clone(),
+            trusted_proxies: server.audit.trusted_proxies.clone(),
+        }
+    }
+
+    /// Checks that every migration bundled with this binary has been applied to the database.
+    #[tracing::instrument(name = "db.migrations_applied", skip_all)]
+    pub async fn migrations_applied(&self) -> sqlx::Result<bool> {
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(sqlx::migrate!()
+            .iter()
+            .all(|migration| applied.contains(&migration.version)))
+    }
+
+    /// Reason the email (OTP) backend cannot be used, if any.
+    pub fn email_backend(&self) -> Option<String> {
+        if cfg!(test) {
+            return None;
+        }
+
+        if self.email.sendgrid_key.is_empty() {
+            Some("SENDGRID_KEY is not configured".into())
+        } else {
+            None
         }
     }
 
@@ -34,14 +91,22 @@ impl BaseAuthenticator {
         hasher.finish().to_string()
     }
 
-    pub async fn prepare<T>(&self, email: &str, sec: &str, data: &T) -> HttpResponse
+    #[tracing::instrument(name = "db.prepare", skip_all)]
+    pub async fn prepare<T>(
+        &self,
+        email: &str,
+        sec: &str,
+        commitments: Option<&str>,
+        data: &T,
+    ) -> HttpResponse
     where
         T: serde::Serialize,
     {
         let res = sqlx::query!(
-            "INSERT INTO prepare (email, secret_component, data) VALUES ($1, $2, $3) RETURNING id",
+            "INSERT INTO prepare (email, secret_component, commitments, data) VALUES ($1, $2, $3, $4) RETURNING id",
             Self::hash(email),
             &sec,
+            commitments,
             serde_json::to_value(data).expect("Could not serialize data"),
         )
         .fetch_one(&self.pool)
@@ -64,6 +129,7 @@ impl BaseAuthenticator {
         }
     }
     #[cfg(not(test))]
+    #[tracing::instrument(name = "sendgrid.send", skip_all, fields(email = crate::telemetry::redact(email)))]
     async fn send_email(
         &self,
         email: &str,
@@ -71,8 +137,8 @@ impl BaseAuthenticator {
     ) -> sendgrid::SendgridResult<reqwest::Response> {
         let body = format!("Your OTP for CryptoPass: {}", otp);
         let message = sendgrid::Mail::new()
-            .add_from("benjcape@gmail.com")
-            .add_reply_to("benjcape@gmail.com")
+            .add_from(&self.email.from)
+            .add_reply_to(&self.email.from)
             .add_subject("OTP")
             .add_to(sendgrid::Destination {
                 address: email,
@@ -88,8 +154,24 @@ impl BaseAuthenticator {
         Ok(())
     }
 
-    pub fn verify(id: &str, otp: &str) -> bool {
-        let totp = TOTP::new(totp_rs::Algorithm::SHA1, 6, 1, 30, id);
+    fn totp<'a>(&self, id: &'a str) -> TOTP<&'a str> {
+        let OtpOptions { digits, skew, step } = self.otp;
+        TOTP::new(totp_rs::Algorithm::SHA1, digits, skew, step, id)
+    }
+
+    /// The OTP currently sent for `id`, as tests cannot read the email.
+    #[cfg(test)]
+    pub(crate) fn current_otp(&self, id: &str) -> String {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        self.totp(id).generate(time)
+    }
+
+    pub fn verify(&self, id: &str, otp: &str) -> bool {
+        let totp = self.totp(id);
         let time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
@@ -98,8 +180,9 @@ impl BaseAuthenticator {
         totp.check(otp, time)
     }
 
+    #[tracing::instrument(name = "otp.register", skip_all)]
     pub async fn register(&self, id: &str, email: &str) -> Option<actix_web::HttpResponse> {
-        let totp = TOTP::new(totp_rs::Algorithm::SHA1, 6, 1, 30, id);
+        let totp = self.totp(id);
         let time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
@@ -110,24 +193,29 @@ impl BaseAuthenticator {
         if cfg!(test) {
             Some(actix_web::HttpResponseBuilder::new(StatusCode::OK).json(otp))
         } else {
-            self.send_email(email, &otp)
-                .await
-                .map_err(|e| {
-                    actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json(e.to_string())
-                })
-                .err()
+            let start = Instant::now();
+            let sent = self.send_email(email, &otp).await;
+            metrics::otp_sent(start.elapsed(), sent.is_ok());
+
+            sent.map_err(|e| {
+                actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json(e.to_string())
+            })
+            .err()
         }
     }
 
+    #[tracing::instrument(name = "db.verify_register", skip_all)]
     pub async fn verify_register(
         &self,
         email: &str,
         secret_component: &str,
+        commitments: Option<String>,
         data: serde_json::Value,
     ) -> Option<HttpResponse> {
-        sqlx::query!("INSERT INTO authenticated (email, secret_component, status, data) VALUES ($1, $2, $3, $4) ON CONFLICT (email) DO UPDATE SET secret_component = EXCLUDED.secret_component, data = EXCLUDED.data;",
+        sqlx::query!("INSERT INTO authenticated (email, secret_component, commitments, status, data) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (email) DO UPDATE SET secret_component = EXCLUDED.secret_component, commitments = EXCLUDED.commitments, epoch = EXCLUDED.epoch, data = EXCLUDED.data;",
                         Self::hash(email),
                         secret_component,
+                        commitments,
                         VerificationStatus::Verified as VerificationStatus,
                         data
                     )
@@ -137,9 +225,10 @@ impl BaseAuthenticator {
                     .err()
     }
 
-    pub async fn get_prepared(&self, email: &str) -> Vec<(String, String, serde_json::Value)> {
+    #[tracing::instrument(name = "db.get_prepared", skip_all)]
+    pub async fn get_prepared(&self, email: &str) -> Vec<Prepared> {
         sqlx::query!(
-            "SELECT id, secret_component, data from prepare WHERE email=$1",
+            "SELECT id, secret_component, commitments, data from prepare WHERE email=$1",
             Self::hash(email)
         )
         .fetch_all(&self.pool)
@@ -150,14 +239,18 @@ impl BaseAuthenticator {
             (
                 rec.id as Option<Uuid>,
                 rec.secret_component.clone() as Option<String>,
+                rec.commitments.clone() as Option<String>,
                 rec.data.clone() as Option<serde_json::Value>,
             )
         })
-        .filter(|(a, b, c)| a.and(b.as_ref()).and(c.as_ref()).is_some())
-        .map(|(id, sec, data)| (id.unwrap().to_string(), sec.unwrap(), data.unwrap()))
+        .filter(|(a, b, _, c)| a.and(b.as_ref()).and(c.as_ref()).is_some())
+        .map(|(id, sec, commitments, data)| {
+            (id.unwrap().to_string(), sec.unwrap(), commitments, data.unwrap())
+        })
         .collect()
     }
 
+    #[tracing::instrument(name = "db.get_authenticated_id", skip_all)]
     pub async fn get_authenticated_id(&self, email: &str) -> Option<Uuid> {
         sqlx::query!(
             "SELECT id from authenticated WHERE email=$1",
@@ -168,4 +261,40 @@ impl BaseAuthenticator {
         .ok()
         .and_then(|rec| rec.id)
     }
+
+    /// Deletes everything stored about a user, and records the deletion in the audit log.
+    ///
+    /// Returns whether the user was registered.
+    #[tracing::instrument(name = "db.delete", skip_all)]
+    pub(crate) async fn delete(&self, server_ty: ServerType, email: &str) -> sqlx::Result<bool> {
+        let email_hash = Self::hash(email);
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM prepare WHERE email=$1", email_hash)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM refresh_updates WHERE email=$1", email_hash)
+            .execute(&mut tx)
+            .await?;
+        let deleted = sqlx::query!("DELETE FROM authenticated WHERE email=$1", email_hash)
+            .execute(&mut tx)
+            .await?
+            .rows_affected()
+            > 0;
+
+        tx.commit().await?;
+
+        if deleted {
+            audit::record(
+                &self.pool,
+                server_ty,
+                AuditEvent::Deleted,
+                email,
+                Origin::default(),
+            )
+            .await;
+        }
+
+        Ok(deleted)
+    }
 }
@@ -1,29 +1,503 @@
 use async_trait::async_trait;
 use derive::*;
-use sqlx::PgPool;
 
-use super::{base::BaseAuthenticator, AuthenticatorServer, VerificationStatus};
-use actix_web::{HttpResponse};
+use std::time::Duration;
+
+use super::device::{ChangeAction, ChangeRequest, DeviceApi, DeviceStatus, SignedApproval};
+use super::{base::BaseAuthenticator, now, ApiError, AuthenticatorServer, VerificationStatus};
+use crate::config::Server;
+use actix_web::{web, HttpRequest, HttpResponse};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+
+pub use simple_syrup_client::types::{
+    AddDeviceRequest, Device, DevicesRequest, RevokeDeviceRequest, SessionRequest, SessionResponse,
+};
+
+/// Devices a user may enroll, revoked ones included.
+pub const MAX_DEVICES: usize = 10;
 
+/// How long a session opened by `/devices/session` lets the user manage their devices.
+pub const SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a change to the devices waits for an approval, before another may replace it.
+pub const CHANGE_TTL: Duration = Duration::from_secs(10 * 60);
 
 #[PassServer(
-    data(String), 
-    store(Stored), 
-    ty(crate::config::ServerType::Biometric),
-    ignore_tests(true)
+    requests(simple_syrup_client::types::BiometricAuthenticator),
+    store(Stored),
+    routes(extra(device_approval, open_session, list_devices, add_device, revoke_device, verify_device_change))
 )]
 pub struct BiometricAuthenticator {
-    pub(crate) api_url: String,
+    pub(crate) device: DeviceApi,
+    /// Approvals pushed by the device API are refused without it.
+    pub(crate) webhook_key: Option<String>,
+}
+
+/// The devices in `authenticated.data`.
+///
+/// Registering stores the id of the device registered with, which becomes a list of devices once
+/// the user changes them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredDevices {
+    Registered(String),
+    Devices(Vec<Device>),
+}
+
+fn devices(data: Option<serde_json::Value>) -> Vec<Device> {
+    match data.and_then(|data| serde_json::from_value(data).ok()) {
+        Some(StoredDevices::Registered(device_id)) => vec![Device {
+            device_id,
+            label: String::new(),
+            revoked: false,
+        }],
+        Some(StoredDevices::Devices(devices)) => devices,
+        None => vec![],
+    }
+}
+
+fn active(devices: &[Device]) -> Vec<String> {
+    devices.iter()
+        .filter(|device| !device.revoked)
+        .map(|device| device.device_id.clone())
+        .collect()
+}
+
+/// A change to the devices of a user, waiting for one of their active devices to approve it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Change {
+    Add(Device),
+    Revoke(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct DeviceChange {
+    request_id: String,
+    change: Change,
+    /// When the devices were asked, changes stored without it having expired.
+    #[serde(default)]
+    requested_at: i64,
+}
+
+impl DeviceChange {
+    /// The devices asked to approve the change: a revoked device does not approve its own
+    /// revocation, as it may well be lost.
+    fn approvers(&self, devices: &[Device]) -> Vec<String> {
+        let mut approvers = active(devices);
+        if let Change::Revoke(device_id) = &self.change {
+            approvers.retain(|id| id != device_id);
+        }
+        approvers
+    }
+
+    fn expired(&self) -> bool {
+        now() >= self.requested_at + CHANGE_TTL.as_secs() as i64
+    }
+
+    /// What the devices are asked to approve, each of them with its own `device_id`.
+    fn request(&self, devices: &[Device]) -> ChangeRequest {
+        let (action, target_device_id, label) = match &self.change {
+            Change::Add(device) => (ChangeAction::Add, &device.device_id, device.label.clone()),
+            Change::Revoke(device_id) => {
+                let label = devices.iter()
+                    .find(|device| device.device_id == *device_id)
+                    .map(|device| device.label.clone());
+                (ChangeAction::Revoke, device_id, label.unwrap_or_default())
+            }
+        };
+
+        ChangeRequest {
+            device_id: String::new(),
+            request_id: self.request_id.clone(),
+            action,
+            target_device_id: target_device_id.clone(),
+            label,
+        }
+    }
+
+    fn apply(self, mut devices: Vec<Device>) -> Vec<Device> {
+        match self.change {
+            Change::Add(device) => devices.push(device),
+            Change::Revoke(device_id) => devices
+                .iter_mut()
+                .filter(|device| device.device_id == device_id)
+                .for_each(|device| device.revoked = true),
+        }
+        devices
+    }
+}
+
+/// Only the digest of a session is stored.
+fn session_digest(session: &str) -> String {
+    base64::encode(Sha256::digest(session.as_bytes()))
+}
+
+fn not_in_session() -> HttpResponse {
+    actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Open a session with /devices/session first.")
 }
 
 impl BiometricAuthenticator {
-    fn request_auth_url(&self) -> String {
-        format!("{}/requestAuth", self.api_url)
+    /// The devices of a registered user, with the change to them waiting for an approval if any,
+    /// when `session` is still open for them.
+    async fn user_devices(&self, email: &str, session: &str) -> Option<(Vec<Device>, Option<DeviceChange>)> {
+        sqlx::query!(
+            "SELECT data, device_change FROM authenticated WHERE email=$1 AND (status=$2 OR status=$3) AND device_session=$4 AND device_session_expires>$5;",
+            BaseAuthenticator::hash(email),
+            VerificationStatus::Verified as VerificationStatus,
+            VerificationStatus::RequestAuth as VerificationStatus,
+            session_digest(session),
+            now(),
+        )
+            .fetch_one(&self.base.pool)
+            .await
+            .ok()
+            .map(|record| (
+                devices(record.data),
+                record.device_change.and_then(|change| serde_json::from_value(change).ok()),
+            ))
     }
-    fn status_url(&self) -> String {
-        format!("{}/status", self.api_url)
+
+    /// Asks the devices to approve `change`, unless another change is still waiting for them.
+    async fn request_change(&self, email: &str, devices: &[Device], change: Change) -> HttpResponse {
+        let change = DeviceChange {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            change,
+            requested_at: now(),
+        };
+        let email_hash = BaseAuthenticator::hash(email);
+
+        match self.stage_change(&email_hash, &change).await {
+            Ok(true) => {}
+            Ok(false) => return actix_web::HttpResponseBuilder::new(StatusCode::CONFLICT).json("Another device change is waiting for an approval."),
+            Err(e) => return actix_web::HttpResponseBuilder::new(StatusCode::NOT_FOUND).json(e.to_string()),
+        }
+
+        let res = self.device.request_change_all(&change.approvers(devices), &change.request(devices))
+            .instrument(tracing::info_span!("biometric.request_change", url = %self.device.url()))
+            .await;
+        if let Err(e) = res {
+            // No device was asked, so the change is not left blocking the next one.
+            let _ = sqlx::query!(
+                "UPDATE authenticated SET device_change=NULL WHERE email=$1 AND device_change->>'request_id'=$2;",
+                email_hash,
+                change.request_id,
+            )
+                .execute(&self.base.pool)
+                .await;
+            return e.into();
+        }
+
+        HttpResponse::new(StatusCode::OK)
+    }
+
+    /// Stores `change` to wait for an approval, unless another change still is.
+    async fn stage_change(&self, email_hash: &str, change: &DeviceChange) -> Result<bool, sqlx::Error> {
+        let mut tx = self.base.pool.begin().await?;
+
+        let record = sqlx::query!(
+            "SELECT device_change FROM authenticated WHERE email=$1 FOR UPDATE;",
+            email_hash,
+        )
+            .fetch_one(&mut tx)
+            .await?;
+        let waiting: Option<DeviceChange> = record.device_change.and_then(|change| serde_json::from_value(change).ok());
+        if waiting.is_some_and(|waiting| !waiting.expired()) {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "UPDATE authenticated SET device_change=$2 WHERE email=$1;",
+            email_hash,
+            serde_json::to_value(change).expect("Could not serialize device change"),
+        )
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// The authentication request waiting for the user, once one of their active devices approved
+    /// it. When given, `device_id` must be one of them.
+    async fn approved_request(&self, email: &str, device_id: Option<&str>) -> Result<String, HttpResponse> {
+        let record = sqlx::query!(
+            r#"SELECT data, status as "status: VerificationStatus", auth_request from authenticated WHERE email=$1;"#,
+            BaseAuthenticator::hash(email)
+        )
+            .fetch_one(&self.base.pool)
+            .await;
+
+        let record = match record {
+            Ok(record) => record,
+            Err(_) => return Err(actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json("You were not authenticated.")),
+        };
+        let devices = active(&devices(record.data));
+
+        // Only a device of the user, that was not revoked, can verify them.
+        if device_id.is_some_and(|device_id| !devices.iter().any(|id| id == device_id)) {
+            return Err(HttpResponse::new(StatusCode::UNAUTHORIZED));
+        }
+        let request_id = match record.auth_request {
+            Some(id) => id,
+            None => return Err(actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Authentication was not requested.")),
+        };
+
+        // A device already pushed its approval, otherwise they are asked for it.
+        if !matches!(record.status, VerificationStatus::Verified) {
+            let status = self.device.status_all(&devices, &request_id)
+                .instrument(tracing::info_span!("biometric.status", url = %self.device.url()))
+                .await;
+
+            match status {
+                Ok(DeviceStatus::Approved) => {}
+                Ok(DeviceStatus::Pending) => return Err(actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("The devices did not answer yet.")),
+                Ok(DeviceStatus::Denied) => return Err(HttpResponse::new(StatusCode::UNAUTHORIZED)),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(request_id)
+    }
+
+    /// Applies the change answering `request_id`, if it is still waiting, and returns the devices
+    /// after it.
+    async fn apply_change(&self, email_hash: &str, request_id: &str) -> Result<Vec<Device>, sqlx::Error> {
+        let mut tx = self.base.pool.begin().await?;
+
+        let record = sqlx::query!(
+            "SELECT data, device_change FROM authenticated WHERE email=$1 AND device_change->>'request_id'=$2 FOR UPDATE;",
+            email_hash,
+            request_id,
+        )
+            .fetch_one(&mut tx)
+            .await?;
+
+        let change: DeviceChange = record.device_change
+            .and_then(|change| serde_json::from_value(change).ok())
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let devices = change.apply(devices(record.data));
+
+        sqlx::query!(
+            "UPDATE authenticated SET data=$2, device_change=NULL WHERE email=$1;",
+            email_hash,
+            serde_json::to_value(&devices).expect("Could not serialize devices"),
+        )
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(devices)
+    }
+}
+
+/// Opens a session to manage the devices of a user, once one of their active devices approved the
+/// authentication requested by `/authenticate`. The approval is taken, so it no longer verifies
+/// the user with `/authenticate/verify`.
+#[actix_web::post("/devices/session")]
+async fn open_session(req: HttpRequest, request: web::Json<SessionRequest>) -> HttpResponse {
+    let authenticator = req.app_data::<BiometricAuthenticator>().unwrap();
+
+    let request_id = match authenticator.approved_request(&request.email, None).await {
+        Ok(request_id) => request_id,
+        Err(res) => return res,
+    };
+
+    let session = base64::encode(rand::random::<[u8; 32]>());
+    sqlx::query!(
+        "UPDATE authenticated SET status=$3, auth_request=NULL, device_session=$5, device_session_expires=$6 WHERE email=$1 AND auth_request=$2 AND (status=$3 OR status=$4) RETURNING id;",
+        BaseAuthenticator::hash(&request.email),
+        request_id,
+        VerificationStatus::RequestAuth as VerificationStatus,
+        VerificationStatus::Verified as VerificationStatus,
+        session_digest(&session),
+        now() + SESSION_TTL.as_secs() as i64,
+    )
+        .fetch_one(&authenticator.base.pool)
+        .await
+        .map_or_else(
+            |e| actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json(e.to_string()),
+            |_| HttpResponse::Ok().json(SessionResponse { session }),
+        )
+}
+
+/// The devices of a user, revoked ones included.
+#[actix_web::post("/devices")]
+async fn list_devices(req: HttpRequest, request: web::Json<DevicesRequest>) -> HttpResponse {
+    let authenticator = req.app_data::<BiometricAuthenticator>().unwrap();
+
+    match authenticator.user_devices(&request.email, &request.session).await {
+        Some((devices, _)) => HttpResponse::Ok().json(devices),
+        None => not_in_session(),
+    }
+}
+
+/// Enrolls a device, once one of the user's active devices approved it.
+#[actix_web::post("/devices/add")]
+async fn add_device(req: HttpRequest, request: web::Json<AddDeviceRequest>) -> HttpResponse {
+    let authenticator = req.app_data::<BiometricAuthenticator>().unwrap();
+    let AddDeviceRequest { email, session, device_id, label } = request.0;
+
+    let devices = match authenticator.user_devices(&email, &session).await {
+        Some((devices, _)) => devices,
+        None => return not_in_session(),
+    };
+    let error = if device_id.is_empty() {
+        Some("device_id must not be empty".into())
+    } else if devices.iter().any(|device| device.device_id == device_id) {
+        Some(format!("{} is already enrolled, or was revoked", device_id))
+    } else if devices.len() >= MAX_DEVICES {
+        Some(format!("at most {} devices can be enrolled", MAX_DEVICES))
+    } else {
+        None
+    };
+    if let Some(e) = error {
+        return ApiError::Invalid(e).into();
+    }
+
+    let device = Device { device_id, label, revoked: false };
+    authenticator.request_change(&email, &devices, Change::Add(device)).await
+}
+
+/// Revokes a device, once another of the user's active devices approved it.
+#[actix_web::post("/devices/revoke")]
+async fn revoke_device(req: HttpRequest, request: web::Json<RevokeDeviceRequest>) -> HttpResponse {
+    let authenticator = req.app_data::<BiometricAuthenticator>().unwrap();
+    let RevokeDeviceRequest { email, session, device_id } = request.0;
+
+    let devices = match authenticator.user_devices(&email, &session).await {
+        Some((devices, _)) => devices,
+        None => return not_in_session(),
+    };
+    let active = active(&devices);
+    if !active.contains(&device_id) {
+        return ApiError::Invalid(format!("{} is not an active device", device_id)).into();
+    }
+    // Users who lost every device register again instead.
+    if active.len() == 1 {
+        return ApiError::Invalid("the last active device cannot be revoked".into()).into();
+    }
+
+    authenticator.request_change(&email, &devices, Change::Revoke(device_id)).await
+}
+
+/// Applies the change waiting for the user's devices, once one of them approved it.
+#[actix_web::post("/devices/verify")]
+async fn verify_device_change(req: HttpRequest, request: web::Json<DevicesRequest>) -> HttpResponse {
+    let authenticator = req.app_data::<BiometricAuthenticator>().unwrap();
+
+    let (devices, change) = match authenticator.user_devices(&request.email, &request.session).await {
+        Some((devices, Some(change))) => (devices, change),
+        None => return not_in_session(),
+        Some((_, None)) => return actix_web::HttpResponseBuilder::new(StatusCode::NOT_FOUND).json("No device change is waiting."),
+    };
+
+    let status = authenticator.device.status_all(&change.approvers(&devices), &change.request_id)
+        .instrument(tracing::info_span!("biometric.status", url = %authenticator.device.url()))
+        .await;
+    match status {
+        Ok(DeviceStatus::Approved) => {}
+        Ok(DeviceStatus::Pending) => return actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("The devices did not answer yet."),
+        Ok(DeviceStatus::Denied) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
+        Err(e) => return e.into(),
+    }
+
+    authenticator.apply_change(&BaseAuthenticator::hash(&request.email), &change.request_id)
+        .await
+        .map_or_else(
+            |_| HttpResponse::new(StatusCode::NOT_FOUND),
+            |devices| HttpResponse::Ok().json(devices),
+        )
+}
+
+/// Approvals pushed by the device API, so that clients poll `/status` until the user is verified
+/// instead of `/authenticate/verify` asking the devices. The first device to answer a request
+/// decides it.
+#[actix_web::post("/device/approval")]
+async fn device_approval(req: HttpRequest, signed: web::Json<SignedApproval>) -> HttpResponse {
+    let authenticator = req.app_data::<BiometricAuthenticator>().unwrap();
+
+    match &authenticator.webhook_key {
+        Some(key) if signed.verify(key) => {}
+        Some(_) => return actix_web::HttpResponseBuilder::new(StatusCode::FORBIDDEN).json("The approval signature is not valid."),
+        None => return HttpResponse::new(StatusCode::NOT_FOUND),
+    }
+
+    let approval = signed.0.approval;
+    let not_found = || actix_web::HttpResponseBuilder::new(StatusCode::NOT_FOUND).json("No such request is waiting for the device.");
+
+    // Either an authentication, or a change to the user's devices, waits for the approval.
+    let record = sqlx::query!(
+        "SELECT email, data, auth_request, device_change FROM authenticated WHERE (auth_request=$1 AND status=$2) OR device_change->>'request_id'=$1;",
+        approval.request_id,
+        VerificationStatus::RequestAuth as VerificationStatus,
+    )
+        .fetch_one(&authenticator.base.pool)
+        .await;
+    let record = match record {
+        Ok(record) => record,
+        Err(_) => return not_found(),
+    };
+    let devices = devices(record.data);
+
+    let res = if record.auth_request.as_ref() == Some(&approval.request_id) {
+        if !active(&devices).contains(&approval.device_id) {
+            return not_found();
+        }
+
+        // A denial ends the request, which can then no longer be approved.
+        if approval.approved {
+            sqlx::query!(
+                "UPDATE authenticated SET status=$3 WHERE email=$1 AND auth_request=$2 AND status=$4 RETURNING id;",
+                record.email,
+                approval.request_id,
+                VerificationStatus::Verified as VerificationStatus,
+                VerificationStatus::RequestAuth as VerificationStatus,
+            )
+            .fetch_one(&authenticator.base.pool)
+            .await
+            .map(|_| ())
+        } else {
+            sqlx::query!(
+                "UPDATE authenticated SET auth_request=NULL WHERE email=$1 AND auth_request=$2 AND status=$3 RETURNING id;",
+                record.email,
+                approval.request_id,
+                VerificationStatus::RequestAuth as VerificationStatus,
+            )
+            .fetch_one(&authenticator.base.pool)
+            .await
+            .map(|_| ())
+        }
+    } else {
+        let change: Option<DeviceChange> = record.device_change.and_then(|change| serde_json::from_value(change).ok());
+        if !change.is_some_and(|change| change.approvers(&devices).contains(&approval.device_id)) {
+            return not_found();
+        }
+
+        if approval.approved {
+            authenticator.apply_change(&record.email, &approval.request_id).await.map(|_| ())
+        } else {
+            sqlx::query!(
+                "UPDATE authenticated SET device_change=NULL WHERE email=$1 AND device_change->>'request_id'=$2 RETURNING id;",
+                record.email,
+                approval.request_id,
+            )
+            .fetch_one(&authenticator.base.pool)
+            .await
+            .map(|_| ())
+        }
+    };
+
+    match res {
+        Ok(()) => {
+            let event = if approval.approved { "device_approved" } else { "device_denied" };
+            crate::metrics::event(crate::config::ServerType::Biometric, event);
+            HttpResponse::new(StatusCode::OK)
+        }
+        Err(_) => not_found(),
     }
 }
 
@@ -33,63 +507,51 @@ impl AuthenticatorServer for BiometricAuthenticator {
 
     async fn authenticate(&self, email: &str) -> Option<HttpResponse> {
 
-        let client = reqwest::Client::new();
+        // Each request has its own id, so that a decision only answers the request it was made for.
+        // Until a device answers it, a user verified before is not anymore.
+        let request_id = uuid::Uuid::new_v4().to_string();
 
-        let device_id: Option<String> = sqlx::query!("SELECT data from authenticated WHERE email=$1;", BaseAuthenticator::hash(email))
+        let devices = sqlx::query!(
+            "UPDATE authenticated SET auth_request=$2, status=$4 WHERE email=$1 AND (status=$3 OR status=$4) RETURNING data;",
+            BaseAuthenticator::hash(email),
+            request_id,
+            VerificationStatus::Verified as VerificationStatus,
+            VerificationStatus::RequestAuth as VerificationStatus,
+        )
             .fetch_one(&self.base.pool)
             .await
-            .ok()
-            .and_then(|record| record.data)
-            .and_then(|data| serde_json::from_value(data).ok());
-
-        if let Some(id) = device_id {
-            client.post(&self.request_auth_url())
-                .json(&serde_json::json!({ "deviceId": id }))
-                .send()
-                .await
-                .err()
-                .map(|_| HttpResponse::new(StatusCode::BAD_REQUEST))
-        } else {
-            Some(actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json("You were not authenticated."))
+            .map(|record| active(&devices(record.data)));
+
+        match devices {
+            Ok(devices) if !devices.is_empty() => {
+                self.device.request_auth_all(&devices, &request_id)
+                    .instrument(tracing::info_span!("biometric.request_auth", url = %self.device.url()))
+                    .await
+                    .err()
+                    .map(HttpResponse::from)
+            }
+            _ => Some(actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json("You were not authenticated.")),
         }
     }
 
     async fn verify_authentication(
         &self,
         email: &str,
-        _data: &Self::Data,
+        data: &Self::Data,
     ) -> Option<HttpResponse> {
 
-        let device_id: Option<String> = sqlx::query!("SELECT data from authenticated WHERE email=$1;", BaseAuthenticator::hash(email))
-            .fetch_one(&self.base.pool)
-            .await
-            .ok()
-            .and_then(|record| record.data)
-            .and_then(|data| serde_json::from_value(data).ok());
-
-        if let Some(id) = device_id {
-            let client = reqwest::Client::new();
-            let res = client.get(&self.status_url())
-                .json(&serde_json::json!({ "deviceId": id }))
-                .send()
-                .await;
-
-            if let Ok(res) = res {
-                // This should not be an unwrap... but oh well for now
-                let status: bool = res.json().await.unwrap();
-                if !status {
-                    return Some(HttpResponse::new(StatusCode::UNAUTHORIZED));
-                }
-            }
-        } else {
-            return Some(actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json("You were not authenticated."))
-        }
+        let request_id = match self.approved_request(email, Some(data)).await {
+            Ok(request_id) => request_id,
+            Err(res) => return Some(res),
+        };
 
+        // Taking the request id makes an approval verify the user only once.
         sqlx::query!(
-            "UPDATE authenticated SET status=$3 WHERE email=$1 AND (status=$2 OR status=$3) RETURNING id;",
+            "UPDATE authenticated SET status=$3, auth_request=NULL WHERE email=$1 AND auth_request=$2 AND (status=$3 OR status=$4) RETURNING id;",
             BaseAuthenticator::hash(email),
-            VerificationStatus::Verified as VerificationStatus,
+            request_id,
             VerificationStatus::RequestAuth as VerificationStatus,
+            VerificationStatus::Verified as VerificationStatus,
         )
         .fetch_one(&self.base.pool)
         .await
@@ -99,15 +561,484 @@ impl AuthenticatorServer for BiometricAuthenticator {
         )
         .err()
     }
+
+    fn backend_ready(&self) -> Option<String> {
+        if self.device.url().is_empty() {
+            Some("BIOMETRIC_API_URL is not configured".into())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+impl super::Fixture for BiometricAuthenticator {
+    fn registration_data() -> String {
+        "device-1".into()
+    }
+
+    fn correct_data(_: serde_json::Value) -> String {
+        Self::registration_data()
+    }
+
+    /// A device the user did not register.
+    fn incorrect_data() -> String {
+        "device-2".into()
+    }
 }
 
-pub fn server_builder(pool: PgPool) -> BiometricAuthenticator {
-    let api_url = std::env::var("BIOMETRIC_API_URL").expect("need BIOMETRIC_API_URL to test");
+pub fn server_builder(server: &Server) -> BiometricAuthenticator {
     BiometricAuthenticator {
-        base: BaseAuthenticator::new(pool),
-        api_url
+        base: BaseAuthenticator::new(server),
+        device: DeviceApi::new(&server.biometric),
+        webhook_key: server.biometric.webhook_key.clone(),
     }
 }
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use crate::api::device::Approval;
+    use crate::config::{Config, ServerType};
+    use crate::identity::Identity;
+    use crate::test::biometric::{MockBiometric, Outcome};
+
+    use super::*;
+
+    /// Registers `device-1` with a server calling `mock`, then requests authentication.
+    async fn requested(mock: &MockBiometric) -> (Config, String) {
+        let mut config = Config::test(ServerType::Biometric).await;
+        config.servers[0].biometric.api_url = Some(mock.url());
+
+        let secret = crate::test::share();
+        let otp = config.register(&secret, &"device-1").await;
+        config.verify_register(&otp).await;
+        config.auth().await;
+
+        (config, secret)
+    }
+
+    async fn post<T: Serialize>(config: Config, uri: &str, body: T) -> actix_web::dev::ServiceResponse {
+        let app = crate::test::build_test_app!(config).await;
+        let req = actix_web::test::TestRequest::post()
+            .uri(uri)
+            .set_json(body)
+            .to_request();
+
+        actix_web::test::call_service(&app, req).await
+    }
+
+    async fn verify(config: Config) -> actix_web::dev::ServiceResponse {
+        let body = serde_json::json!({
+            "email": "benjcape@gmail.com",
+            "data": "device-1",
+        });
+
+        post(config, "/authenticate/verify", body).await
+    }
+
+    async fn push(config: Config, approval: SignedApproval) -> StatusCode {
+        post(config, "/device/approval", approval).await.status()
+    }
+
+    /// Opens a session with the authentication approved, or answers why it could not.
+    async fn session(config: Config) -> Result<String, StatusCode> {
+        let body = serde_json::json!({ "email": "benjcape@gmail.com" });
+        let res = post(config, "/devices/session", body).await;
+        if res.status() != StatusCode::OK {
+            return Err(res.status());
+        }
+
+        let res: SessionResponse = actix_web::test::read_body_json(res).await;
+        Ok(res.session)
+    }
+
+    async fn change(config: Config, session: &str, uri: &str, device_id: &str) -> StatusCode {
+        let body = serde_json::json!({
+            "email": "benjcape@gmail.com",
+            "session": session,
+            "device_id": device_id,
+            "label": "Tablet",
+        });
+
+        post(config, uri, body).await.status()
+    }
+
+    async fn verify_change(config: Config, session: &str) -> actix_web::dev::ServiceResponse {
+        let body = serde_json::json!({
+            "email": "benjcape@gmail.com",
+            "session": session,
+        });
+
+        post(config, "/devices/verify", body).await
+    }
+
+    async fn list(config: Config, session: &str) -> Vec<Device> {
+        let app = crate::test::build_test_app!(config).await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/devices")
+            .set_json(serde_json::json!({ "email": "benjcape@gmail.com", "session": session }))
+            .to_request();
+
+        actix_web::test::call_and_read_body_json(&app, req).await
+    }
+
+    /// The devices asked about the last request to authenticate.
+    fn last_requested(mock: &MockBiometric) -> Vec<String> {
+        let requested = mock.requested();
+        let last = &requested.last().unwrap().request_id;
+        requested
+            .iter()
+            .filter(|request| request.request_id == *last)
+            .map(|request| request.device_id.clone())
+            .collect()
+    }
+
+    /// The devices asked about the last change.
+    fn last_changed(mock: &MockBiometric) -> Vec<String> {
+        let changes = mock.changes();
+        let last = &changes.last().unwrap().request_id;
+        changes
+            .iter()
+            .filter(|change| change.request_id == *last)
+            .map(|change| change.device_id.clone())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn approved() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        let (config, secret) = requested(&mock).await;
+
+        assert_eq!(config.verify_auth(&"device-1").await, Some(secret));
+        assert_eq!(mock.requested().len(), 1);
+        assert_eq!(mock.requested()[0].device_id, "device-1");
+
+        // The approval answered that request only.
+        assert_eq!(verify(config).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(mock.status_calls(), 1);
+    }
+
+    #[actix_web::test]
+    async fn denied() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        mock.set("device-1", Outcome::Deny);
+        let (config, _) = requested(&mock).await;
+
+        assert_eq!(verify(config.clone()).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(config.get_status().await, VerificationStatus::RequestAuth);
+    }
+
+    #[actix_web::test]
+    async fn pending() {
+        let mock = MockBiometric::start(Outcome::Pending);
+        let (config, secret) = requested(&mock).await;
+
+        assert_eq!(verify(config.clone()).await.status(), StatusCode::UNAUTHORIZED);
+
+        // The request is still waiting for the user.
+        mock.set("device-1", Outcome::Approve);
+        assert_eq!(config.verify_auth(&"device-1").await, Some(secret));
+    }
+
+    #[actix_web::test]
+    async fn timed_out() {
+        let mock = MockBiometric::start(Outcome::Timeout);
+        let (config, _) = requested(&mock).await;
+
+        // Each attempt times out, then the user is not verified.
+        let start = std::time::Instant::now();
+        assert_eq!(verify(config.clone()).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(start.elapsed() >= crate::test::biometric::TIMEOUT * 2);
+        assert_eq!(mock.status_calls(), 2);
+        assert_eq!(config.get_status().await, VerificationStatus::RequestAuth);
+    }
+
+    #[actix_web::test]
+    async fn unavailable() {
+        let mock = MockBiometric::start(Outcome::Unavailable);
+        let (mut config, _) = requested(&mock).await;
+        config.servers[0].biometric.retries = 3;
+
+        assert_eq!(verify(config).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(mock.status_calls(), 4);
+    }
+
+    #[actix_web::test]
+    async fn not_requested() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        let mut config = Config::test(ServerType::Biometric).await;
+        config.servers[0].biometric.api_url = Some(mock.url());
+
+        let otp = config.register(&crate::test::share(), &"device-1").await;
+        config.verify_register(&otp).await;
+
+        // Never asked to authenticate, the device has nothing to approve.
+        assert_eq!(verify(config).await.status(), StatusCode::UNAUTHORIZED);
+        assert!(mock.requested().is_empty());
+        assert_eq!(mock.status_calls(), 0);
+    }
+
+    #[actix_web::test]
+    async fn pushed_approval() {
+        let mock = MockBiometric::start(Outcome::Pending);
+        let (mut config, secret) = requested(&mock).await;
+
+        let device_api = Identity::generate();
+        config.servers[0].biometric.webhook_key = Some(device_api.public_key());
+
+        let approval = |request_id: &str, approved| {
+            Approval {
+                device_id: "device-1".into(),
+                request_id: request_id.into(),
+                approved,
+            }
+        };
+        let request_id = mock.requested()[0].request_id.clone();
+
+        // Only the device API signs approvals, and only for the request waiting.
+        let forged = approval(&request_id, true).sign(&Identity::generate());
+        assert_eq!(push(config.clone(), forged).await, StatusCode::FORBIDDEN);
+        let other = approval("other-request", true).sign(&device_api);
+        assert_eq!(push(config.clone(), other).await, StatusCode::NOT_FOUND);
+        assert_eq!(config.get_status().await, VerificationStatus::RequestAuth);
+
+        let approved = approval(&request_id, true).sign(&device_api);
+        assert_eq!(push(config.clone(), approved.clone()).await, StatusCode::OK);
+        assert_eq!(config.get_status().await, VerificationStatus::Verified);
+
+        // The device is not asked again.
+        assert_eq!(config.verify_auth(&"device-1").await, Some(secret));
+        assert_eq!(mock.status_calls(), 0);
+
+        // Replaying the approval does not verify the next request.
+        config.auth().await;
+        assert_eq!(push(config.clone(), approved).await, StatusCode::NOT_FOUND);
+        assert_eq!(config.get_status().await, VerificationStatus::RequestAuth);
+    }
+
+    #[actix_web::test]
+    async fn pushed_denial() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        let (mut config, _) = requested(&mock).await;
+
+        let device_api = Identity::generate();
+        config.servers[0].biometric.webhook_key = Some(device_api.public_key());
+
+        let denied = Approval {
+            device_id: "device-1".into(),
+            request_id: mock.requested()[0].request_id.clone(),
+            approved: false,
+        };
+        assert_eq!(push(config.clone(), denied.sign(&device_api)).await, StatusCode::OK);
+
+        // The denial ended the request, which the device can no longer approve.
+        assert_eq!(verify(config).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(mock.status_calls(), 0);
+    }
+
+    #[actix_web::test]
+    async fn described_device_routes() {
+        let config = Config::test(ServerType::Biometric).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/openapi.json").to_request();
+        let spec: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+
+        for path in ["/device/approval", "/devices/session", "/devices", "/devices/add", "/devices/revoke", "/devices/verify"] {
+            assert!(spec["paths"][path].get("post").is_some(), "{}", path);
+
+            let req = actix_web::test::TestRequest::post().uri(path).to_request();
+            let res = actix_web::test::call_service(&app, req).await;
+            assert_ne!(res.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn approvals_refused_without_key() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        let (config, _) = requested(&mock).await;
+
+        let approval = Approval {
+            device_id: "device-1".into(),
+            request_id: mock.requested()[0].request_id.clone(),
+            approved: true,
+        };
+        assert_eq!(
+            push(config.clone(), approval.sign(&Identity::generate())).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(config.get_status().await, VerificationStatus::RequestAuth);
+    }
+
+    #[actix_web::test]
+    async fn added_device() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        let (config, secret) = requested(&mock).await;
+        let session = session(config.clone()).await.unwrap();
+
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-2").await, StatusCode::OK);
+        assert_eq!(last_changed(&mock), ["device-1"]);
+        assert_eq!(mock.changes()[0], ChangeRequest {
+            device_id: "device-1".into(),
+            request_id: mock.changes()[0].request_id.clone(),
+            action: ChangeAction::Add,
+            target_device_id: "device-2".into(),
+            label: "Tablet".into(),
+        });
+        // The devices are not asked to authenticate the user instead.
+        assert_eq!(mock.requested().len(), 1);
+
+        let devices: Vec<Device> = actix_web::test::read_body_json(verify_change(config.clone(), &session).await).await;
+        assert_eq!(devices, list(config.clone(), &session).await);
+        assert_eq!(devices[1], Device {
+            device_id: "device-2".into(),
+            label: "Tablet".into(),
+            revoked: false,
+        });
+
+        // Every device is asked, and the first approval wins.
+        config.auth().await;
+        assert_eq!(last_requested(&mock), ["device-1", "device-2"]);
+        mock.set("device-1", Outcome::Pending);
+        mock.set("device-2", Outcome::Deny);
+        assert_eq!(verify(config.clone()).await.status(), StatusCode::UNAUTHORIZED);
+        mock.set("device-2", Outcome::Approve);
+        assert_eq!(config.verify_auth(&"device-1").await, Some(secret));
+    }
+
+    #[actix_web::test]
+    async fn denied_device_change() {
+        let mock = MockBiometric::start(Outcome::Approve);
+        let (config, _) = requested(&mock).await;
+        let session = session(config.clone()).await.unwrap();
+        mock.set("device-1", Outcome::Deny);
+
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-1").await, StatusCode::BAD_REQUEST);
+        assert_eq!(change(config.clone(), &session, "/devices/revoke", "device-1").await, StatusCode::BAD_REQUEST);
+
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-2").await, StatusCode::OK);
+        assert_eq!(verify_change(config.clone(), &session).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(list(config, &session).await.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn devices_need_session() {
+        let mock = MockBiometric::start(Outcome::Pending);
+        let (config, _) = requested(&mock).await;
+
+        // The authentication was not approved yet.
+        assert_eq!(session(config.clone()).await, Err(StatusCode::UNAUTHORIZED));
+
+        // Without a session, enrolled users are not told apart from others, and no device is asked.
+        for email in ["benjcape@gmail.com", "unknown@gmail.com"] {
+            let body = serde_json::json!({
+                "email": email,
+                "session": "forged",
+                "device_id": "device-2",
+                "label": "Tablet",
+            });
+            for uri in ["/devices", "/devices/add", "/devices/revoke", "/devices/verify"] {
+                assert_eq!(post(config.clone(), uri, &body).await.status(), StatusCode::UNAUTHORIZED);
+            }
+        }
+        assert!(mock.changes().is_empty());
+
+        mock.set("device-1", Outcome::Approve);
+        let opened = session(config.clone()).await.unwrap();
+        assert_eq!(list(config.clone(), &opened).await.len(), 1);
+
+        // The approval opened the session, and no longer verifies the user.
+        assert_eq!(verify(config.clone()).await.status(), StatusCode::UNAUTHORIZED);
+
+        // Nor does it open another session.
+        assert_eq!(session(config.clone()).await, Err(StatusCode::UNAUTHORIZED));
+
+        sqlx::query("UPDATE authenticated SET device_session_expires=$1")
+            .bind(now())
+            .execute(&config.servers[0].database)
+            .await
+            .unwrap();
+        assert_eq!(change(config.clone(), &opened, "/devices/add", "device-2").await, StatusCode::UNAUTHORIZED);
+        assert!(mock.changes().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn waiting_change() {
+        let mock = MockBiometric::start(Outcome::Pending);
+        mock.set("device-1", Outcome::Approve);
+        let (config, _) = requested(&mock).await;
+        let session = session(config.clone()).await.unwrap();
+        mock.set("device-1", Outcome::Pending);
+
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-2").await, StatusCode::OK);
+        let waiting = mock.changes()[0].request_id.clone();
+
+        // Another change does not replace the one waiting.
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-3").await, StatusCode::CONFLICT);
+        assert_eq!(mock.changes().len(), 1);
+
+        mock.set("device-1", Outcome::Approve);
+        let devices: Vec<Device> = actix_web::test::read_body_json(verify_change(config.clone(), &session).await).await;
+        assert_eq!(devices[1].device_id, "device-2");
+
+        // Once the change waiting expired, another replaces it.
+        mock.set("device-1", Outcome::Pending);
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-3").await, StatusCode::OK);
+        sqlx::query("UPDATE authenticated SET device_change=jsonb_set(device_change, '{requested_at}', '0')")
+            .execute(&config.servers[0].database)
+            .await
+            .unwrap();
+        assert_eq!(change(config.clone(), &session, "/devices/revoke", "device-2").await, StatusCode::OK);
+        assert_eq!(last_changed(&mock), ["device-1"]);
+        assert_ne!(mock.changes().last().unwrap().request_id, waiting);
+        assert_eq!(mock.changes().last().unwrap().action, ChangeAction::Revoke);
+        assert_eq!(mock.changes().last().unwrap().label, "Tablet");
+    }
+
+    #[actix_web::test]
+    async fn revoked_device() {
+        let mock = MockBiometric::start(Outcome::Pending);
+        let (mut config, _) = requested(&mock).await;
+
+        let device_api = Identity::generate();
+        config.servers[0].biometric.webhook_key = Some(device_api.public_key());
+        let approval = |device_id: &str, request_id: String| {
+            Approval {
+                device_id: device_id.into(),
+                request_id,
+                approved: true,
+            }
+            .sign(&device_api)
+        };
+        let requested = || mock.requested().last().unwrap().request_id.clone();
+        let changed = || mock.changes().last().unwrap().request_id.clone();
+
+        // A pushed approval opens the session too.
+        assert_eq!(push(config.clone(), approval("device-1", requested())).await, StatusCode::OK);
+        let session = session(config.clone()).await.unwrap();
+
+        assert_eq!(change(config.clone(), &session, "/devices/add", "device-2").await, StatusCode::OK);
+        // The device enrolled cannot approve itself.
+        assert_eq!(push(config.clone(), approval("device-2", changed())).await, StatusCode::NOT_FOUND);
+        assert_eq!(push(config.clone(), approval("device-1", changed())).await, StatusCode::OK);
+        assert_eq!(list(config.clone(), &session).await.len(), 2);
+
+        // Nor can the device revoked, which is not asked.
+        assert_eq!(change(config.clone(), &session, "/devices/revoke", "device-1").await, StatusCode::OK);
+        assert_eq!(last_changed(&mock), ["device-2"]);
+        assert_eq!(push(config.clone(), approval("device-1", changed())).await, StatusCode::NOT_FOUND);
+        assert_eq!(push(config.clone(), approval("device-2", changed())).await, StatusCode::OK);
+        assert!(list(config.clone(), &session).await[0].revoked);
+
+        config.auth().await;
+        assert_eq!(last_requested(&mock), ["device-2"]);
+        assert_eq!(push(config.clone(), approval("device-1", requested())).await, StatusCode::NOT_FOUND);
+        assert_eq!(verify(config.clone()).await.status(), StatusCode::UNAUTHORIZED);
+
+        assert_eq!(change(config.clone(), &session, "/devices/revoke", "device-2").await, StatusCode::BAD_REQUEST);
+        assert_eq!(change(config, &session, "/devices/add", "device-1").await, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,270 @@
+//! Client of the device API biometric servers authenticate users with, at `BIOMETRIC_API_URL`.
+//!
+//! `POST /requestAuth` asks a device to authenticate its user for a request, and `GET /status`
+//! reports what they decided for it. `POST /requestChange` asks a device to approve a change to
+//! the devices of its user instead, which `/status` reports on the same way. Each request has its
+//! own id, so a decision only ever answers the request it was made for, and users with several
+//! devices ask all of them at once. Instead of being polled, the device API may also push
+//! decisions to the server as a [`SignedApproval`].
+//!
+//! Any error talking to the device API fails closed: the user is not verified.
+
+use std::time::Duration;
+
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use super::ApiError;
+use crate::config::BiometricOptions;
+use crate::identity;
+
+/// Waited before the first retry, and once more before each of the next ones.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Asks a device to authenticate its user, also the query of `/status`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthRequest {
+    pub device_id: String,
+    pub request_id: String,
+}
+
+/// What a [`ChangeRequest`] asks a device to approve.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Add,
+    Revoke,
+}
+
+/// Asks a device to approve a change to the devices of its user, so that it is not mistaken for
+/// an authentication.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeRequest {
+    pub device_id: String,
+    pub request_id: String,
+    pub action: ChangeAction,
+    /// The device added or revoked, and its label.
+    pub target_device_id: String,
+    pub label: String,
+}
+
+/// What the user decided on their device.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    /// The user did not answer yet.
+    Pending,
+    Approved,
+    Denied,
+}
+
+/// The body of `/status`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub status: DeviceStatus,
+}
+
+/// A decision pushed by the device API.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Approval {
+    pub device_id: String,
+    pub request_id: String,
+    pub approved: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedApproval {
+    pub approval: Approval,
+    /// Signature of the JSON encoded `approval`, by the key of the device API.
+    pub signature: String,
+}
+
+impl Approval {
+    /// Signs as the device API does.
+    #[cfg(test)]
+    pub fn sign(self, identity: &identity::Identity) -> SignedApproval {
+        let signature = identity.sign(&self.payload());
+
+        SignedApproval {
+            approval: self,
+            signature,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Could not serialize approval")
+    }
+}
+
+impl SignedApproval {
+    /// Whether the approval was signed by `public_key`, such as `BIOMETRIC_WEBHOOK_KEY`.
+    pub fn verify(&self, public_key: &str) -> bool {
+        identity::verify(public_key, &self.approval.payload(), &self.signature)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceApi {
+    url: String,
+    client: reqwest::Client,
+    retries: u32,
+}
+
+impl DeviceApi {
+    pub fn new(options: &BiometricOptions) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(options.timeout)
+            .build()
+            .expect("Could not build the device API client");
+
+        Self {
+            url: options.api_url.clone().unwrap_or_default(),
+            client,
+            retries: options.retries,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub async fn request_auth(&self, request: &AuthRequest) -> Result<(), ApiError> {
+        let url = format!("{}/requestAuth", self.url);
+
+        self.send(|| self.client.post(&url).json(request))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn request_change(&self, request: &ChangeRequest) -> Result<(), ApiError> {
+        let url = format!("{}/requestChange", self.url);
+
+        self.send(|| self.client.post(&url).json(request))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn status(&self, request: &AuthRequest) -> Result<DeviceStatus, ApiError> {
+        let url = format!("{}/status", self.url);
+
+        let res = self.send(|| self.client.get(&url).query(request)).await?;
+        res.json::<StatusResponse>()
+            .await
+            .map(|res| res.status)
+            .map_err(|e| ApiError::Unavailable(format!("Unexpected device API response: {}", e)))
+    }
+
+    /// Asks every device in `device_ids` to authenticate the user for the same request, which
+    /// fails only when no device could be asked.
+    pub async fn request_auth_all(
+        &self,
+        device_ids: &[String],
+        request_id: &str,
+    ) -> Result<(), ApiError> {
+        let results = join_all(
+            requests(device_ids, request_id)
+                .map(|request| async move { self.request_auth(&request).await }),
+        )
+        .await;
+
+        any_asked(results)
+    }
+
+    /// Asks every device in `device_ids` to approve the same change, described by `change` whose
+    /// `device_id` is replaced by each of them, which fails only when no device could be asked.
+    pub async fn request_change_all(
+        &self,
+        device_ids: &[String],
+        change: &ChangeRequest,
+    ) -> Result<(), ApiError> {
+        let results = join_all(device_ids.iter().map(|device_id| async move {
+            let request = ChangeRequest {
+                device_id: device_id.clone(),
+                ..change.clone()
+            };
+            self.request_change(&request).await
+        }))
+        .await;
+
+        any_asked(results)
+    }
+
+    /// What the devices in `device_ids` decided for a request: the first approval wins, whatever
+    /// the other devices answered. It is denied once every device denied it.
+    pub async fn status_all(
+        &self,
+        device_ids: &[String],
+        request_id: &str,
+    ) -> Result<DeviceStatus, ApiError> {
+        let statuses = join_all(
+            requests(device_ids, request_id)
+                .map(|request| async move { self.status(&request).await }),
+        )
+        .await;
+
+        for status in [DeviceStatus::Approved, DeviceStatus::Pending] {
+            if statuses.contains(&Ok(status)) {
+                return Ok(status);
+            }
+        }
+        match statuses.into_iter().find_map(Result::err) {
+            Some(e) => Err(e),
+            None => Ok(DeviceStatus::Denied),
+        }
+    }
+
+    /// Sends the request built by `request`, again on errors that may not last: a timeout, a
+    /// failed connection, or an error of the device API itself.
+    async fn send(
+        &self,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, ApiError> {
+        let mut attempt = 0;
+        loop {
+            let error = match request().send().await {
+                Ok(res) if res.status().is_server_error() => res.status().to_string(),
+                Ok(res) if res.status().is_client_error() => {
+                    return Err(ApiError::Invalid(format!(
+                        "The device API refused the request: {}",
+                        res.status()
+                    )))
+                }
+                Ok(res) => return Ok(res),
+                Err(e) => e.to_string(),
+            };
+
+            if attempt == self.retries {
+                return Err(ApiError::Unavailable(format!(
+                    "Could not reach the device API: {}",
+                    error
+                )));
+            }
+            attempt += 1;
+            tracing::warn!(attempt, %error, "retrying the device API");
+            actix_web::rt::time::sleep(RETRY_DELAY * attempt).await;
+        }
+    }
+}
+
+fn any_asked(results: Vec<Result<(), ApiError>>) -> Result<(), ApiError> {
+    if results.iter().any(Result::is_ok) {
+        return Ok(());
+    }
+    results
+        .into_iter()
+        .find_map(Result::err)
+        .map_or(Ok(()), Err)
+}
+
+fn requests<'a>(
+    device_ids: &'a [String],
+    request_id: &'a str,
+) -> impl Iterator<Item = AuthRequest> + 'a {
+    device_ids.iter().map(move |device_id| AuthRequest {
+        device_id: device_id.clone(),
+        request_id: request_id.into(),
+    })
+}
@@ -1,13 +1,12 @@
 use async_trait::async_trait;
 use derive::PassServer;
-use sqlx::PgPool;
 
 use super::{base::BaseAuthenticator, AuthenticatorServer, VerificationStatus};
+use crate::config::Server;
 use actix_web::HttpResponse;
 use hyper::StatusCode;
-use serde::{Deserialize, Serialize};
 
-#[PassServer(data(String), store(Ignored), ty(crate::config::ServerType::Email))]
+#[PassServer(requests(simple_syrup_client::types::EmailAuthenticator), store(Ignored))]
 pub struct EmailAuthenticator {}
 
 #[async_trait]
@@ -31,7 +30,7 @@ impl AuthenticatorServer for EmailAuthenticator {
             }
         };
 
-        if BaseAuthenticator::verify(&id.to_string(), data) {
+        if self.base.verify(&id.to_string(), data) {
             None
         } else {
             Some(actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).finish())
@@ -39,8 +38,24 @@ impl AuthenticatorServer for EmailAuthenticator {
     }
 }
 
-pub fn server_builder(pool: PgPool) -> EmailAuthenticator {
+#[cfg(test)]
+impl super::Fixture for EmailAuthenticator {
+    fn registration_data() -> String {
+        String::new()
+    }
+
+    /// The OTP sent by `/authenticate`.
+    fn correct_data(authenticated: serde_json::Value) -> String {
+        serde_json::from_value(authenticated).unwrap()
+    }
+
+    fn incorrect_data() -> String {
+        "Bad data".into()
+    }
+}
+
+pub fn server_builder(server: &Server) -> EmailAuthenticator {
     EmailAuthenticator {
-        base: BaseAuthenticator::new(pool),
+        base: BaseAuthenticator::new(server),
     }
 }
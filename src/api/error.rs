@@ -0,0 +1,31 @@
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use hyper::StatusCode;
+
+/// Why a server refused a request, answered with a JSON message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApiError {
+    /// The data is not acceptable, for example a password the policy rejects.
+    Invalid(String),
+    /// A service the server depends on could not be reached, so the request may be retried.
+    Unavailable(String),
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::Unavailable(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ApiError> for HttpResponse {
+    fn from(e: ApiError) -> Self {
+        let status = match e {
+            ApiError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+
+        HttpResponseBuilder::new(status).json(e.to_string())
+    }
+}
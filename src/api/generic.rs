@@ -0,0 +1,243 @@
+//! A server generic over how it checks the data, with requests of its own, a qualified `data` type
+//! and its own routes, to test the structs and arguments `#[PassServer]` accepts.
+
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use derive::{PassRequests, PassServer};
+
+use super::{base::BaseAuthenticator, AuthenticatorServer, VerificationStatus};
+use crate::config::Server;
+use actix_web::HttpResponse;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// How the data sent to authenticate is checked.
+pub trait Check {
+    fn check(data: &[u8]) -> bool;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NonEmpty;
+
+impl Check for NonEmpty {
+    fn check(data: &[u8]) -> bool {
+        !data.is_empty()
+    }
+}
+
+/// The requests of the generic server, which no client sends.
+#[PassRequests(
+    data(std::vec::Vec<u8>),
+    store(Stored),
+    ty(crate::config::ServerType::Email),
+    extra(CheckName)
+)]
+pub struct GenericRequests;
+
+/// `GET /check`, served by `check_name`.
+pub struct CheckName;
+
+impl simple_syrup_client::openapi::Route for CheckName {
+    const PATH: &'static str = "/check";
+
+    fn item(components: &mut simple_syrup_client::openapi::Components) -> serde_json::Value {
+        serde_json::json!({
+            "get": {
+                "summary": "Name of the check",
+                "responses": {
+                    "200": {
+                        "description": "Name of the check",
+                        "content": { "application/json": { "schema": components.reference::<String>() } },
+                    },
+                },
+            },
+        })
+    }
+}
+
+#[PassServer(
+    requests(GenericRequests),
+    store(Stored),
+    prefix("/generic"),
+    routes(exclude(status, refresh), extra(check_name)),
+    ignore_tests(true)
+)]
+#[derive(Clone, Debug)]
+pub struct GenericAuthenticator<C: Check = NonEmpty> {
+    check: PhantomData<C>,
+}
+
+/// Name of the check, registered next to the generated routes.
+#[actix_web::get("/check")]
+async fn check_name() -> impl actix_web::Responder {
+    actix_web::web::Json(std::any::type_name::<NonEmpty>())
+}
+
+#[async_trait]
+impl<C: Check + Send + Sync> AuthenticatorServer for GenericAuthenticator<C> {
+    type Data = Vec<u8>;
+
+    async fn verify_authentication(&self, email: &str, data: &Self::Data) -> Option<HttpResponse> {
+        match self.base.get_authenticated_id(email).await {
+            Some(_) if C::check(data) => None,
+            _ => Some(actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).finish()),
+        }
+    }
+}
+
+pub fn server_builder(server: &Server) -> GenericAuthenticator {
+    GenericAuthenticator {
+        base: BaseAuthenticator::new(server),
+        check: PhantomData,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::{Config, ServerType};
+
+    #[actix_web::test]
+    async fn generic_server() {
+        let config = Config::test(ServerType::Email).await;
+        let authenticator = server_builder(&config.servers[0]);
+
+        // Derives on the struct are kept, and the API key is not printed.
+        let _ = authenticator.clone();
+        assert!(format!("{:?}", authenticator).starts_with("GenericAuthenticator"));
+
+        let app = test::init_service(App::new().app_data(authenticator).configure(configure)).await;
+
+        let email = format!("generic-{}@test", rand::random::<u64>());
+        let secret_component = crate::test::share();
+
+        let req = test::TestRequest::post()
+            .uri("/generic/register")
+            .set_json(json!({
+                "email": email,
+                "secret_component": secret_component,
+                "data": [1, 2, 3],
+            }))
+            .to_request();
+        let otp: String = test::call_and_read_body_json(&app, req).await;
+
+        let req = test::TestRequest::post()
+            .uri("/generic/register/verify")
+            .set_json(json!({ "email": email, "otp": otp }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let verify_auth = |data: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/generic/authenticate/verify")
+                .set_json(json!({ "email": email, "data": data }))
+                .to_request()
+        };
+
+        let req = test::TestRequest::post()
+            .uri("/generic/authenticate")
+            .set_json(json!({ "email": email }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let res = test::call_service(&app, verify_auth(json!([]))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let released: crate::api::Released =
+            test::call_and_read_body_json(&app, verify_auth(json!([4, 5]))).await;
+        assert_eq!(released.secret_component, Some(secret_component));
+    }
+
+    #[actix_web::test]
+    async fn configured_routes() {
+        let config = Config::test(ServerType::Email).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(server_builder(&config.servers[0]))
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/generic/check").to_request();
+        let name: String = test::call_and_read_body_json(&app, req).await;
+        assert!(name.ends_with("NonEmpty"));
+
+        for (method, uri) in [
+            ("GET", "/ty"),
+            ("POST", "/generic/status"),
+            ("POST", "/generic/refresh"),
+        ] {
+            let req = test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(uri)
+                .set_json(json!({ "email": "generic@test" }))
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::NOT_FOUND,
+                "{}",
+                uri
+            );
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/generic/openapi.json")
+            .to_request();
+        let spec: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let paths = spec["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/generic/register"));
+        assert!(paths.contains_key("/generic/openapi.json"));
+        assert!(!paths.contains_key("/generic/status"));
+        assert!(!paths.contains_key("/register"));
+        assert_eq!(spec["info"]["title"], "GenericAuthenticator");
+
+        assert!(paths.contains_key("/generic/check"));
+
+        // Every route described is served.
+        for (path, item) in paths {
+            let req = match item.get("get") {
+                Some(_) => test::TestRequest::get(),
+                None => test::TestRequest::post(),
+            }
+            .uri(path)
+            .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_ne!(res.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+
+        // Every route served is described, out of the generated and extra ones.
+        for (method, path) in [
+            ("POST", "/register"),
+            ("POST", "/register/verify"),
+            ("POST", "/authenticate"),
+            ("POST", "/authenticate/verify"),
+            ("POST", "/status"),
+            ("POST", "/refresh/share"),
+            ("POST", "/refresh"),
+            ("GET", "/ty"),
+            ("GET", "/identity"),
+            ("GET", "/openapi.json"),
+            ("GET", "/readyz"),
+            ("GET", "/version"),
+            ("GET", "/metrics"),
+            ("GET", "/check"),
+        ] {
+            let path = format!("/generic{}", path);
+            let req = test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(&path)
+                .to_request();
+            let served = test::call_service(&app, req).await.status() != StatusCode::NOT_FOUND;
+            let described = paths
+                .get(&path)
+                .and_then(|item| item.get(method.to_lowercase()))
+                .is_some();
+            assert_eq!(served, described, "{} {}", method, path);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::base::BaseAuthenticator;
+use super::AuthenticatorServer;
+use crate::config::{Server, ServerType};
+
+/// Build information reported by `/version`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionInfo {
+    pub version: String,
+    pub git_sha: String,
+    pub features: Vec<String>,
+    pub server_ty: ServerType,
+}
+
+impl VersionInfo {
+    pub fn new(server_ty: ServerType) -> Self {
+        let features = [
+            ("email", cfg!(feature = "email")),
+            ("qa", cfg!(feature = "qa")),
+            ("password", cfg!(feature = "password")),
+            ("biometric", cfg!(feature = "biometric")),
+        ]
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature.to_string())
+        .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            git_sha: env!("GIT_SHA").into(),
+            features,
+            server_ty,
+        }
+    }
+}
+
+/// The result of each readiness check performed by `/readyz`.
+///
+/// Any failing check is described in `errors`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Readiness {
+    pub database: bool,
+    pub migrations: bool,
+    pub backend: bool,
+    pub errors: Vec<String>,
+}
+
+impl Readiness {
+    pub async fn check(base: &BaseAuthenticator, backend: Option<String>) -> Self {
+        let mut readiness = Self::default();
+
+        match sqlx::query("SELECT 1").execute(&base.pool).await {
+            Ok(_) => readiness.database = true,
+            Err(e) => readiness.errors.push(e.to_string()),
+        }
+
+        if readiness.database {
+            match base.migrations_applied().await {
+                Ok(true) => readiness.migrations = true,
+                Ok(false) => readiness
+                    .errors
+                    .push("Database migrations have not been applied".into()),
+                Err(e) => readiness.errors.push(e.to_string()),
+            }
+        }
+
+        let backend_errors: Vec<String> = base.email_backend().into_iter().chain(backend).collect();
+        readiness.backend = backend_errors.is_empty();
+        readiness.errors.extend(backend_errors);
+
+        readiness
+    }
+
+    /// The checks of `/readyz`, for a server outside of a request.
+    pub async fn of(server: &Server) -> Self {
+        let backend = match server.server_ty {
+            #[cfg(feature = "email")]
+            ServerType::Email => super::email::server_builder(server).backend_ready(),
+            #[cfg(feature = "qa")]
+            ServerType::QA => super::qa::server_builder(server).backend_ready(),
+            #[cfg(feature = "password")]
+            ServerType::Password => super::password::server_builder(server).backend_ready(),
+            #[cfg(feature = "biometric")]
+            ServerType::Biometric => super::biometric::server_builder(server).backend_ready(),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("settings only accept the server types built in"),
+        };
+
+        Self::check(&BaseAuthenticator::new(server), backend).await
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.database && self.migrations && self.backend
+    }
+}
+
+impl From<Readiness> for HttpResponse {
+    fn from(readiness: Readiness) -> Self {
+        let status = if readiness.is_ready() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        HttpResponseBuilder::new(status).json(readiness)
+    }
+}
+
+/// Liveness probe, this only confirms that the process is up and serving requests.
+#[get("/healthz")]
+pub async fn healthz(_req: HttpRequest) -> impl Responder {
+    web::Json("OK")
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[actix_web::test]
+    async fn healthz() {
+        let config = Config::test(ServerType::Email).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn readyz() {
+        let config = Config::test(ServerType::Email).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let readiness: Readiness = test::call_and_read_body_json(&app, req).await;
+
+        assert!(readiness.is_ready(), "{:?}", readiness.errors);
+    }
+
+    /// Announcements report the backend of a server, like its `/readyz`.
+    #[cfg(feature = "biometric")]
+    #[actix_web::test]
+    async fn backend_outside_of_requests() {
+        let mut config = Config::test(ServerType::Biometric).await;
+        assert!(Readiness::of(&config.servers[0]).await.is_ready());
+
+        config.servers[0].biometric.api_url = None;
+        let readiness = Readiness::of(&config.servers[0]).await;
+        assert!(!readiness.backend);
+        assert_eq!(readiness.errors, ["BIOMETRIC_API_URL is not configured"]);
+    }
+
+    #[actix_web::test]
+    async fn version() {
+        let config = Config::test(ServerType::Email).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let version: VersionInfo = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(version.server_ty, ServerType::Email);
+    }
+}
@@ -1,17 +1,11 @@
-use std::fmt::Debug;
 
 use actix_web::{get, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
 use async_trait::async_trait;
 use hyper::StatusCode;
-use serde::{Deserialize, Serialize};
-
-// Need to figure out how to get this working...
-#[derive(sqlx::Type, Debug, Serialize, Deserialize, PartialEq, Eq)]
-pub enum VerificationStatus {
-    Requested,
-    Verified,
-    RequestAuth,
-}
+
+#[cfg(any(feature = "qa", feature = "password", feature = "biometric"))]
+pub use error::ApiError;
+pub use simple_syrup_client::{Released, VerificationStatus};
 
 pub(crate) trait TestDefault<F, T> {
     fn or_test_default_else(self, default: F) -> Self;
@@ -45,7 +39,7 @@ where
 
 #[async_trait]
 pub trait AuthenticatorServer {
-    type Data;
+    type Data: Clone + Send + Sync;
     /*
     The Options here are a reason for failure. If there is no reason for failure, that means we did not fail.
     - None => Good! :)
@@ -68,16 +62,45 @@ pub trait AuthenticatorServer {
     ///
     /// Any API call to a 3rd party would happen here (faceID, etc.)
     async fn verify_authentication(&self, email: &str, data: &Self::Data) -> Option<HttpResponse>;
+
+    /// Validates the data a user registers with, returning what is stored.
+    ///
+    /// Called by `/register` before anything is stored, so servers can enforce a policy on the
+    /// data, such as a password strength, or normalize it. Servers ignoring the data, like Email
+    /// servers, are not sent any to validate.
+    #[cfg(any(feature = "qa", feature = "password", feature = "biometric"))]
+    async fn validate_registration(&self, data: &Self::Data) -> Result<Self::Data, ApiError> {
+        Ok(data.clone())
+    }
+
+    /// Checks that any external backend this server depends on is configured.
+    ///
+    /// Returns the reason the server is not ready, if any. Reported by `/readyz`.
+    fn backend_ready(&self) -> Option<String> {
+        None
+    }
 }
 
-pub trait ServerData: Default + Serialize {
-    fn bad_data() -> Self;
+/// The data the generated tests of a server register and authenticate with.
+#[cfg(test)]
+pub(crate) trait Fixture: AuthenticatorServer {
+    /// What the user registers.
+    fn registration_data() -> Self::Data;
+
+    /// What verifies the user, given the body `/authenticate` answered, such as the OTP sent.
+    fn correct_data(authenticated: serde_json::Value) -> Self::Data;
+
+    /// What does not verify the user.
+    fn incorrect_data() -> Self::Data;
 }
 
-impl ServerData for String {
-    fn bad_data() -> String {
-        "Bad data".to_string()
-    }
+/// Seconds since the Unix epoch, as expiries are stored.
+#[cfg(any(feature = "qa", feature = "biometric"))]
+pub(crate) fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
 }
 
 #[get("/")]
@@ -87,9 +110,20 @@ pub async fn index(_req: HttpRequest) -> impl Responder {
 
 pub mod base;
 
+#[cfg(any(feature = "qa", feature = "password", feature = "biometric"))]
+mod error;
+
+pub mod health;
+
+#[cfg(feature = "password")]
+pub mod policy;
+
 #[cfg(feature = "email")]
 pub mod email;
 
+#[cfg(test)]
+mod generic;
+
 #[cfg(feature = "qa")]
 pub mod qa;
 
@@ -98,3 +132,6 @@ pub mod password;
 
 #[cfg(feature = "biometric")]
 pub mod biometric;
+
+#[cfg(feature = "biometric")]
+pub mod device;
@@ -1,31 +1,41 @@
 use async_trait::async_trait;
 use derive::*;
-use sqlx::PgPool;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
-use super::ServerData;
+use super::policy::PasswordPolicy;
+use super::ApiError;
 use super::{base::BaseAuthenticator, AuthenticatorServer, VerificationStatus};
+use crate::config::Server;
 use actix_web::HttpResponse;
 use hyper::StatusCode;
-use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Default)]
-pub struct Pass {
-    pub password: String
-}
+pub use simple_syrup_client::Pass;
+
 
 #[PassServer(
-    data(Pass), 
-    store(Hashed), 
-    ty(crate::config::ServerType::Password)
+    requests(simple_syrup_client::types::PasswordAuthenticator),
+    store(Hashed)
 )]
-pub struct PasswordAuthenticator {}
+pub struct PasswordAuthenticator {
+    pub(crate) policy: PasswordPolicy,
+}
+
+#[cfg(test)]
+impl super::Fixture for PasswordAuthenticator {
+    fn registration_data() -> Pass {
+        Pass {
+            password: "correct horse battery staple".into(),
+        }
+    }
+
+    fn correct_data(_: serde_json::Value) -> Pass {
+        Self::registration_data()
+    }
 
-impl ServerData for Pass {
-    fn bad_data() -> Self {
-        Self {
-            password: "Bad data".to_string()
+    fn incorrect_data() -> Pass {
+        Pass {
+            password: "Bad data".into(),
         }
     }
 }
@@ -35,6 +45,12 @@ impl ServerData for Pass {
 impl AuthenticatorServer for PasswordAuthenticator {
     type Data = Pass;
 
+    async fn validate_registration(&self, data: &Self::Data) -> Result<Self::Data, ApiError> {
+        self.policy.check(&data.password)?;
+
+        Ok(data.clone())
+    }
+
     async fn verify_authentication(
         &self,
         email: &str,
@@ -62,8 +78,43 @@ impl AuthenticatorServer for PasswordAuthenticator {
     }
 }
 
-pub fn server_builder(pool: PgPool) -> PasswordAuthenticator {
+pub fn server_builder(server: &Server) -> PasswordAuthenticator {
     PasswordAuthenticator {
-        base: BaseAuthenticator::new(pool),
+        base: BaseAuthenticator::new(server),
+        policy: server.password.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, ServerType};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn password_policy() {
+        let mut config = Config::test(ServerType::Password).await;
+        config.servers[0].password = PasswordPolicy::new(8);
+
+        let app = crate::test::build_test_app!(config).await;
+
+        let register = |password: &str| {
+            actix_web::test::TestRequest::post()
+                .uri("/register")
+                .set_json(serde_json::json!({
+                    "email": "benjcape@gmail.com",
+                    "secret_component": crate::test::share(),
+                    "data": { "password": password },
+                }))
+                .to_request()
+        };
+
+        let res = actix_web::test::call_service(&app, register("short")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let message: String = actix_web::test::read_body_json(res).await;
+        assert_eq!(message, "password must be at least 8 characters");
+
+        let res = actix_web::test::call_service(&app, register("long enough")).await;
+        assert_eq!(res.status(), StatusCode::OK);
     }
 }
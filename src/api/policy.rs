@@ -0,0 +1,113 @@
+//! Policy for the passwords users register with the Password server.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::ApiError;
+
+/// Minimum length of passwords, unless configured otherwise.
+pub const MIN_LENGTH: usize = 8;
+
+/// Rejects passwords that are too short, or known from breaches.
+///
+/// The default policy accepts any password.
+#[derive(Clone, Debug, Default)]
+pub struct PasswordPolicy {
+    /// In characters, not bytes.
+    pub min_length: usize,
+    breached: Arc<HashSet<String>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize) -> Self {
+        Self {
+            min_length,
+            breached: Arc::default(),
+        }
+    }
+
+    /// Also rejects the passwords listed in `path`, one per line.
+    ///
+    /// Lines are taken as they are, except for the line ending, as breached passwords may start or
+    /// end with spaces. Empty lines are skipped.
+    pub fn with_breached_list(self, path: &Path) -> Result<Self, String> {
+        let contents =
+            std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+        // Breach dumps are not always valid UTF-8, such lines could not be registered anyway.
+        let breached = String::from_utf8_lossy(&contents)
+            .lines()
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+
+        Ok(Self {
+            breached: Arc::new(breached),
+            ..self
+        })
+    }
+
+    pub fn check(&self, password: &str) -> Result<(), ApiError> {
+        if password.chars().count() < self.min_length {
+            return Err(ApiError::Invalid(format!(
+                "password must be at least {} characters",
+                self.min_length
+            )));
+        }
+
+        if self.breached.contains(password) {
+            return Err(ApiError::Invalid(
+                "password is known from a data breach, choose another one".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn password_policy() {
+        let mut list = tempfile();
+        write!(list.1, "password\r\n123456789\n\n correct horse \n").unwrap();
+
+        let policy = PasswordPolicy::new(MIN_LENGTH)
+            .with_breached_list(&list.0)
+            .unwrap();
+        assert_eq!(policy.breached.len(), 3);
+
+        assert!(matches!(policy.check("short"), Err(ApiError::Invalid(_))));
+        assert!(matches!(
+            policy.check("123456789"),
+            Err(ApiError::Invalid(_))
+        ));
+        assert!(matches!(
+            policy.check(" correct horse "),
+            Err(ApiError::Invalid(_))
+        ));
+        assert_eq!(policy.check("correct horse"), Ok(()));
+        // Characters are counted, not bytes.
+        assert!(policy.check("pässwör").is_err());
+        assert_eq!(policy.check("pässwörd"), Ok(()));
+
+        assert_eq!(PasswordPolicy::default().check(""), Ok(()));
+        assert!(PasswordPolicy::default()
+            .with_breached_list(Path::new("/does/not/exist"))
+            .is_err());
+
+        std::fs::remove_file(list.0).unwrap();
+    }
+
+    fn tempfile() -> (std::path::PathBuf, std::fs::File) {
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", rand::random::<u64>()));
+        let file = std::fs::File::create(&path).unwrap();
+        (path, file)
+    }
+}
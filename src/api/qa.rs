@@ -1,39 +1,326 @@
+use argon2::{Algorithm, Argon2, Params, Version};
 use async_trait::async_trait;
 use derive::*;
-use sqlx::PgPool;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use unicode_normalization::UnicodeNormalization;
 
-use super::ServerData;
-use super::{base::BaseAuthenticator, AuthenticatorServer, VerificationStatus};
+use super::{base::BaseAuthenticator, now, ApiError, AuthenticatorServer, VerificationStatus};
+use crate::config::Server;
+use crate::metrics;
 use actix_web::HttpResponse;
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Default)]
-pub struct QuestionAnswer {
-    question: String,
-    answer: String,
-}
+pub use simple_syrup_client::{QaChallenge, QuestionAnswer};
+
+/// Questions asked to authenticate, and the least a user registers.
+pub const ASKED: usize = 3;
+
+/// Questions asked that must be answered correctly.
+pub const REQUIRED: usize = 2;
+
+/// Questions a user may register.
+pub const MAX_QUESTIONS: usize = 10;
+
+/// Time the questions asked may be answered in, after which other questions are asked.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Wrong answers in a row that lock a user out.
+pub const MAX_FAILURES: i32 = 5;
+
+/// Time a user stays locked out.
+pub const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Standard questions clients may offer users, served by `/questions`.
+pub const CATALOG: &[&str] = &[
+    "What was the name of your first pet?",
+    "In what city were you born?",
+    "What is the name of the street you grew up on?",
+    "What was the name of your first school?",
+    "What was the make of your first car?",
+    "What is your mother's maiden name?",
+    "What was the name of your childhood best friend?",
+    "In what city did your parents meet?",
+    "What was your first job?",
+    "What is the title of your favourite book?",
+];
+
 #[PassServer(
-    data(QuestionAnswer), 
-    store(Hashed), 
-    ty(crate::config::ServerType::QA)
+    requests(simple_syrup_client::types::QAAuthenticator),
+    store(Stored),
+    routes(extra(catalog))
 )]
 pub struct QAAuthenticator {}
 
-impl ServerData for QuestionAnswer {
-    fn bad_data() -> Self {
-        Self {
-            question: "Bad data".to_string(),
-            answer: "Bad data".to_string()
+/// The standard questions.
+#[actix_web::get("/questions")]
+async fn catalog() -> HttpResponse {
+    HttpResponse::Ok().json(CATALOG)
+}
+
+/// Compares answers regardless of case, spacing, and how the same characters are encoded.
+fn normalize(text: &str) -> String {
+    text.nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Hashes an answer with Argon2id, 19 MiB and 2 passes, as answers such as a city or a pet name
+/// are quick to guess from a fast hash.
+///
+/// `None` when the salt is too short, which only a tampered row holds.
+fn digest(salt: &[u8], answer: &str) -> Option<String> {
+    let params = Params::new(19 * 1024, 2, 1, None).expect("Argon2 parameters are valid");
+    let mut hash = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(normalize(answer).as_bytes(), salt, &mut hash)
+        .ok()?;
+
+    Some(base64::encode(hash))
+}
+
+/// The registered question, kept so it can be asked, and its answer hashed: `<salt>:<hash>` in
+/// base64.
+fn hashed(qa: &QuestionAnswer) -> QuestionAnswer {
+    let salt: [u8; 16] = rand::random();
+
+    QuestionAnswer {
+        question: qa.question.trim().into(),
+        answer: format!(
+            "{}:{}",
+            base64::encode(salt),
+            digest(&salt, &qa.answer).expect("16 bytes of salt are enough"),
+        ),
+    }
+}
+
+/// The questions asked, by their position in the list registered.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Challenge {
+    questions: Vec<usize>,
+    required: usize,
+    /// Challenges stored without it have expired.
+    #[serde(default)]
+    issued_at: i64,
+}
+
+impl Challenge {
+    fn expired(&self) -> bool {
+        now() >= self.issued_at + CHALLENGE_TTL.as_secs() as i64
+    }
+
+    /// Whether the challenge can still be answered. One that requires no answer never can.
+    fn open(&self) -> bool {
+        self.required > 0 && !self.expired()
+    }
+}
+
+/// The questions in `authenticated.data`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAnswers {
+    /// The hash of the one question users registered before they could register several.
+    Legacy(u64),
+    Hashed(Vec<QuestionAnswer>),
+}
+
+impl StoredAnswers {
+    /// `None` when no question is stored, so there is nothing to ask.
+    fn new(data: Option<serde_json::Value>) -> Option<Self> {
+        data.and_then(|data| serde_json::from_value(data).ok())
+            .filter(|answers: &StoredAnswers| answers.len() > 0)
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            StoredAnswers::Legacy(_) => 1,
+            StoredAnswers::Hashed(answers) => answers.len(),
+        }
+    }
+
+    /// Whether `qa` answers the question registered at `index`.
+    fn answers(&self, index: usize, qa: &QuestionAnswer) -> bool {
+        let stored = match self {
+            StoredAnswers::Legacy(hash) => {
+                let mut hasher = DefaultHasher::new();
+                qa.hash(&mut hasher);
+                return index == 0 && hasher.finish() == *hash;
+            }
+            StoredAnswers::Hashed(answers) => match answers.get(index) {
+                Some(stored) => stored,
+                None => return false,
+            },
+        };
+
+        if normalize(&stored.question) != normalize(&qa.question) {
+            return false;
+        }
+
+        stored.answer
+            .split_once(':')
+            .and_then(|(salt, hash)| Some((base64::decode(salt).ok()?, hash)))
+            .is_some_and(|(salt, hash)| digest(&salt, &qa.answer).as_deref() == Some(hash))
+    }
+
+    /// A random subset of the questions to ask, at least one, or `None` without any.
+    fn challenge(&self) -> Option<Challenge> {
+        let len = self.len();
+        let asked = len.min(ASKED);
+        if asked == 0 {
+            return None;
+        }
+
+        let mut questions = rand::seq::index::sample(&mut rand::thread_rng(), len, asked).into_vec();
+        questions.sort_unstable();
+
+        Some(Challenge {
+            questions,
+            required: asked.min(REQUIRED),
+            issued_at: now(),
+        })
+    }
+
+    /// The questions of `challenge`, as the user registered them. The legacy answer kept no
+    /// question, which is empty.
+    fn asked(&self, challenge: &Challenge) -> QaChallenge {
+        let question = |index: usize| match self {
+            StoredAnswers::Legacy(_) => String::new(),
+            StoredAnswers::Hashed(answers) => answers
+                .get(index)
+                .map(|stored| stored.question.clone())
+                .unwrap_or_default(),
+        };
+
+        QaChallenge {
+            questions: challenge.questions.iter().copied().map(question).collect(),
+            required: challenge.required,
         }
     }
 }
 
+#[cfg(test)]
+impl super::Fixture for QAAuthenticator {
+    fn registration_data() -> Vec<QuestionAnswer> {
+        [
+            ("What was the name of your first pet?", "Rex"),
+            ("In what city were you born?", "Lyon"),
+            ("What is your favourite book?", "Dune"),
+            ("What was your first car?", "A bike"),
+        ]
+        .iter()
+        .map(|(question, answer)| QuestionAnswer {
+            question: question.to_string(),
+            answer: answer.to_string(),
+        })
+        .collect()
+    }
+
+    /// The answers to the questions `/authenticate` asked.
+    fn correct_data(authenticated: serde_json::Value) -> Vec<QuestionAnswer> {
+        let challenge: QaChallenge = serde_json::from_value(authenticated).unwrap();
+
+        Self::registration_data()
+            .into_iter()
+            .filter(|qa| challenge.questions.contains(&qa.question))
+            .collect()
+    }
+
+    fn incorrect_data() -> Vec<QuestionAnswer> {
+        Self::registration_data()
+            .into_iter()
+            .map(|qa| QuestionAnswer {
+                answer: "Bad data".into(),
+                ..qa
+            })
+            .collect()
+    }
+}
+
 #[async_trait]
 impl AuthenticatorServer for QAAuthenticator {
-    type Data = QuestionAnswer;
+    type Data = Vec<QuestionAnswer>;
+
+    async fn validate_registration(&self, data: &Self::Data) -> Result<Self::Data, ApiError> {
+        if data.len() < ASKED || data.len() > MAX_QUESTIONS {
+            return Err(ApiError::Invalid(format!(
+                "between {} and {} questions must be registered",
+                ASKED, MAX_QUESTIONS
+            )));
+        }
+
+        let mut questions = HashSet::new();
+        for qa in data {
+            let question = normalize(&qa.question);
+            if question.is_empty() || normalize(&qa.answer).is_empty() {
+                return Err(ApiError::Invalid("questions and answers must not be empty".into()));
+            }
+            if !questions.insert(question) {
+                return Err(ApiError::Invalid("questions must be different".into()));
+            }
+        }
+
+        // Hashing is deliberately slow, so it does not hold up the other requests of the worker.
+        let data = data.clone();
+        actix_web::web::block(move || data.iter().map(hashed).collect())
+            .await
+            .map_err(|e| ApiError::Unavailable(e.to_string()))
+    }
+
+    /// Asks a random subset of the questions registered, answered by `/authenticate/verify`.
+    ///
+    /// The questions stay the same until they are answered or expire, so that asking again
+    /// cannot be used to pick the easiest ones.
+    async fn authenticate(&self, email: &str) -> Option<HttpResponse> {
+
+        let record = sqlx::query!(
+            "SELECT data, challenge, locked_until FROM authenticated WHERE email=$1 AND (status=$2 OR status=$3);",
+            BaseAuthenticator::hash(email),
+            VerificationStatus::Verified as VerificationStatus,
+            VerificationStatus::RequestAuth as VerificationStatus,
+        )
+            .fetch_one(&self.base.pool)
+            .await;
+
+        let record = match record {
+            Ok(record) => record,
+            Err(_) => return Some(actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json("You were not authenticated.")),
+        };
+        if record.locked_until.is_some_and(|until| until > now()) {
+            return Some(locked_out());
+        }
+        let answers = match StoredAnswers::new(record.data) {
+            Some(answers) => answers,
+            None => return Some(no_answers()),
+        };
+
+        let current = record.challenge.and_then(|challenge| serde_json::from_value::<Challenge>(challenge).ok());
+        if let Some(challenge) = current.filter(Challenge::open) {
+            return Some(actix_web::HttpResponseBuilder::new(StatusCode::OK).json(answers.asked(&challenge)));
+        }
+
+        let challenge = match answers.challenge() {
+            Some(challenge) => challenge,
+            None => return Some(no_answers()),
+        };
+        sqlx::query!(
+            "UPDATE authenticated SET challenge=$2 WHERE email=$1 RETURNING id;",
+            BaseAuthenticator::hash(email),
+            serde_json::to_value(&challenge).expect("Could not serialize challenge"),
+        )
+            .fetch_one(&self.base.pool)
+            .await
+            .map_or_else(
+                |e| actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json(e.to_string()),
+                |_| actix_web::HttpResponseBuilder::new(StatusCode::OK).json(answers.asked(&challenge)),
+            )
+            .into()
+    }
 
     async fn verify_authentication(
         &self,
@@ -41,20 +328,57 @@ impl AuthenticatorServer for QAAuthenticator {
         data: &Self::Data,
     ) -> Option<HttpResponse> {
 
-        let mut hasher = DefaultHasher::new();
-        data.hash(&mut hasher);
-        let qa = serde_json::to_value(hasher.finish()).unwrap();
+        let record = sqlx::query!(
+            "SELECT data, challenge, locked_until FROM authenticated WHERE email=$1 AND (status=$2 OR status=$3);",
+            BaseAuthenticator::hash(email),
+            VerificationStatus::Verified as VerificationStatus,
+            VerificationStatus::RequestAuth as VerificationStatus,
+        )
+            .fetch_one(&self.base.pool)
+            .await;
+
+        let record = match record {
+            Ok(record) => record,
+            Err(_) => return Some(actix_web::HttpResponseBuilder::new(StatusCode::BAD_REQUEST).json("You were not authenticated.")),
+        };
+        if record.locked_until.is_some_and(|until| until > now()) {
+            return Some(locked_out());
+        }
+        let challenge = match record.challenge.clone().and_then(|challenge| serde_json::from_value::<Challenge>(challenge).ok()) {
+            Some(challenge) if challenge.open() => challenge,
+            _ => return Some(actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Authentication was not requested.")),
+        };
+        let answers = match StoredAnswers::new(record.data) {
+            Some(answers) => answers,
+            None => return Some(no_answers()),
+        };
 
+        // Answers may be given in any order, and each question asked counts once.
+        let asked = challenge.questions.clone();
+        let data = data.clone();
+        let correct = actix_web::web::block(move || {
+            asked
+                .iter()
+                .filter(|&&index| data.iter().any(|qa| answers.answers(index, qa)))
+                .count()
+        })
+            .await
+            .unwrap_or(0);
+        if correct < challenge.required {
+            return Some(self.failed(email).await);
+        }
+
+        // Taking the challenge makes its answers verify the user only once.
         sqlx::query!(
-            "UPDATE authenticated SET status=$3 WHERE email=$1 AND (status=$2 OR status=$3) AND data = $4 RETURNING id;",
+            "UPDATE authenticated SET status=$3, challenge=NULL, failed_attempts=0 WHERE email=$1 AND challenge=$4 AND (status=$2 OR status=$3) RETURNING id;",
             BaseAuthenticator::hash(email),
             VerificationStatus::Verified as VerificationStatus,
             VerificationStatus::RequestAuth as VerificationStatus,
-            qa
+            record.challenge,
         )
         .fetch_one(&self.base.pool)
         .await
-        .map_err(|e| 
+        .map_err(|e|
             actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED)
                 .json(e.to_string()),
         )
@@ -62,11 +386,307 @@ impl AuthenticatorServer for QAAuthenticator {
     }
 }
 
-pub fn server_builder(pool: PgPool) -> QAAuthenticator {
+impl QAAuthenticator {
+    /// Counts a wrong answer, locking the user out and dropping their challenge after
+    /// [`MAX_FAILURES`] in a row.
+    async fn failed(&self, email: &str) -> HttpResponse {
+        let failed = sqlx::query!(
+            "UPDATE authenticated SET failed_attempts=failed_attempts + 1 WHERE email=$1 RETURNING failed_attempts;",
+            BaseAuthenticator::hash(email),
+        )
+            .fetch_one(&self.base.pool)
+            .await;
+
+        if failed.is_ok_and(|record| record.failed_attempts >= MAX_FAILURES) {
+            tracing::warn!("Locking out a user after {} wrong answers", MAX_FAILURES);
+            metrics::locked_out(crate::config::ServerType::QA);
+
+            if let Err(e) = sqlx::query!(
+                "UPDATE authenticated SET failed_attempts=0, challenge=NULL, locked_until=$2 WHERE email=$1;",
+                BaseAuthenticator::hash(email),
+                now() + LOCKOUT.as_secs() as i64,
+            )
+                .execute(&self.base.pool)
+                .await
+            {
+                tracing::error!("Could not lock out a user: {}", e);
+            }
+        }
+
+        HttpResponse::new(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// The stored answers are missing or unreadable, which must never let a user through.
+fn no_answers() -> HttpResponse {
+    actix_web::HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("No answers are registered.")
+}
+
+fn locked_out() -> HttpResponse {
+    actix_web::HttpResponseBuilder::new(StatusCode::TOO_MANY_REQUESTS).json("Too many wrong answers, try again later.")
+}
+
+pub fn server_builder(server: &Server) -> QAAuthenticator {
     QAAuthenticator {
-        base: BaseAuthenticator::new(pool),
+        base: BaseAuthenticator::new(server),
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, ServerType};
+
+    use super::*;
+
+    fn qa(question: &str, answer: &str) -> QuestionAnswer {
+        QuestionAnswer {
+            question: question.into(),
+            answer: answer.into(),
+        }
+    }
+
+    async fn verify(config: &Config, data: &[QuestionAnswer]) -> StatusCode {
+        let app = crate::test::build_test_app!(config).await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/authenticate/verify")
+            .set_json(serde_json::json!({
+                "email": "benjcape@gmail.com",
+                "data": data,
+            }))
+            .to_request();
+
+        actix_web::test::call_service(&app, req).await.status()
+    }
+
+    async fn authenticate(config: &Config) -> StatusCode {
+        let app = crate::test::build_test_app!(config).await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/authenticate")
+            .set_json(serde_json::json!({ "email": "benjcape@gmail.com" }))
+            .to_request();
+
+        actix_web::test::call_service(&app, req).await.status()
+    }
+
+    #[test]
+    fn normalized_answers() {
+        assert_eq!(normalize("  Ｒｅｘ \t the\u{a0}DOG "), "rex the dog");
+        assert_eq!(normalize("Cafe\u{301}"), normalize("Café"));
+
+        let stored = StoredAnswers::Hashed(vec![hashed(&qa("First pet?", "Rex the dog"))]);
+        assert!(stored.answers(0, &qa("first  pet?", "REX the Dog")));
+        assert!(!stored.answers(0, &qa("First pet?", "Rex")));
+        assert!(!stored.answers(0, &qa("First car?", "Rex the dog")));
+        assert!(!stored.answers(1, &qa("First pet?", "Rex the dog")));
+    }
+
+    #[test]
+    fn challenges() {
+        let stored = StoredAnswers::Hashed(vec![hashed(&qa("Q", "A")); 5]);
+        for _ in 0..10 {
+            let challenge = stored.challenge().unwrap();
+            assert_eq!(challenge.required, REQUIRED);
+            assert_eq!(challenge.questions.len(), ASKED);
+            assert!(challenge.questions.windows(2).all(|w| w[0] < w[1] && w[1] < 5));
+        }
+
+        let mut hasher = DefaultHasher::new();
+        qa("Q", "A").hash(&mut hasher);
+        let legacy = StoredAnswers::Legacy(hasher.finish());
+        let challenge = legacy.challenge().unwrap();
+        assert_eq!(challenge.questions, [0]);
+        assert_eq!(challenge.required, 1);
+        assert!(!challenge.expired());
+        assert!(legacy.answers(0, &qa("Q", "A")));
+        assert!(!legacy.answers(0, &qa("Q", "a")));
+    }
 
+    #[test]
+    fn no_stored_answers() {
+        assert!(StoredAnswers::new(None).is_none());
+        assert!(StoredAnswers::new(Some(serde_json::json!("not answers"))).is_none());
+        assert!(StoredAnswers::new(Some(serde_json::json!([]))).is_none());
+        assert!(StoredAnswers::Hashed(vec![]).challenge().is_none());
 
+        let stored = StoredAnswers::new(Some(serde_json::json!([{ "question": "Q", "answer": "c2FsdA==:x" }])));
+        assert!(!stored.unwrap().answers(0, &qa("Q", "A")));
+    }
+
+    /// Missing answers are not taken for a challenge asking nothing.
+    #[actix_web::test]
+    async fn missing_answers() {
+        let config = Config::test(ServerType::QA).await;
+        let otp = config.register(&crate::test::share(), &<QAAuthenticator as crate::api::Fixture>::registration_data()).await;
+        config.verify_register(&otp).await;
+        sqlx::query!("UPDATE authenticated SET data=NULL")
+            .execute(&config.servers[0].database)
+            .await
+            .unwrap();
+
+        assert_eq!(authenticate(&config).await, StatusCode::UNAUTHORIZED);
+
+        let empty = Challenge { questions: vec![], required: 0, issued_at: now() };
+        sqlx::query!("UPDATE authenticated SET challenge=$1", serde_json::to_value(&empty).unwrap())
+            .execute(&config.servers[0].database)
+            .await
+            .unwrap();
+        assert_eq!(verify(&config, &[]).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn registered_questions() {
+        let config = Config::test(ServerType::QA).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let register = |data: Vec<QuestionAnswer>| {
+            actix_web::test::TestRequest::post()
+                .uri("/register")
+                .set_json(serde_json::json!({
+                    "email": "benjcape@gmail.com",
+                    "secret_component": crate::test::share(),
+                    "data": data,
+                }))
+                .to_request()
+        };
+
+        let cases = [
+            (vec![qa("A?", "a"), qa("B?", "b")], "between 3 and 10 questions must be registered"),
+            (vec![qa("A?", "a"), qa("B?", " "), qa("C?", "c")], "questions and answers must not be empty"),
+            (vec![qa("A?", "a"), qa("B?", "b"), qa(" a? ", "c")], "questions must be different"),
+        ];
+        for (data, expected) in cases {
+            let res = actix_web::test::call_service(&app, register(data)).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let message: String = actix_web::test::read_body_json(res).await;
+            assert_eq!(message, expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn n_of_m_answers() {
+        use crate::api::Fixture;
+
+        let config = Config::test(ServerType::QA).await;
+        let secret = crate::test::share();
+        let otp = config.register(&secret, &QAAuthenticator::registration_data()).await;
+        config.verify_register(&otp).await;
+
+        let registered = QAAuthenticator::registration_data();
+        let challenge: QaChallenge = serde_json::from_value(config.auth().await).unwrap();
+        assert_eq!(challenge.questions.len(), ASKED);
+        assert_eq!(challenge.required, REQUIRED);
+
+        // The questions asked are among those registered.
+        let (asked, not_asked): (Vec<_>, Vec<_>) = registered
+            .iter()
+            .cloned()
+            .partition(|qa| challenge.questions.contains(&qa.question));
+        assert_eq!(asked.len(), ASKED);
+
+        // Only questions asked count, and answering one of them twice counts once.
+        let data = vec![asked[0].clone(), asked[0].clone(), not_asked[0].clone()];
+        assert_eq!(verify(&config, &data).await, StatusCode::UNAUTHORIZED);
+
+        // Answers given to the wrong questions do not count.
+        let data: Vec<_> = asked
+            .iter()
+            .zip(asked.iter().cycle().skip(1))
+            .map(|(qa, other)| QuestionAnswer {
+                answer: other.answer.clone(),
+                ..qa.clone()
+            })
+            .collect();
+        assert_eq!(verify(&config, &data).await, StatusCode::UNAUTHORIZED);
+
+        // Two correct answers, written differently, and a wrong one.
+        let mut data: Vec<_> = asked
+            .iter()
+            .map(|qa| QuestionAnswer {
+                answer: format!("  {} ", qa.answer.to_uppercase()),
+                ..qa.clone()
+            })
+            .collect();
+        data[1].answer = "wrong".into();
+        assert_eq!(config.verify_auth(&data).await, Some(secret));
+
+        // The challenge is taken by the answers verifying the user.
+        assert_eq!(verify(&config, &data).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn kept_challenge() {
+        use crate::api::Fixture;
+
+        let config = Config::test(ServerType::QA).await;
+        let otp = config.register(&crate::test::share(), &<QAAuthenticator as crate::api::Fixture>::registration_data()).await;
+        config.verify_register(&otp).await;
+
+        // Asking again, or answering wrong, asks the same questions.
+        let challenge = config.auth().await;
+        assert_eq!(config.auth().await, challenge);
+        assert_eq!(verify(&config, &QAAuthenticator::incorrect_data()).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(config.auth().await, challenge);
+
+        // Until they expire.
+        let expired = Challenge {
+            questions: vec![0, 1, 2],
+            required: REQUIRED,
+            issued_at: now() - CHALLENGE_TTL.as_secs() as i64,
+        };
+        sqlx::query("UPDATE authenticated SET challenge=$1")
+            .bind(serde_json::to_value(&expired).unwrap())
+            .execute(&config.servers[0].database)
+            .await
+            .unwrap();
+        let data = QAAuthenticator::correct_data(challenge.clone());
+        assert_eq!(verify(&config, &data).await, StatusCode::UNAUTHORIZED);
+        let renewed: QaChallenge = serde_json::from_value(config.auth().await).unwrap();
+        assert_eq!(renewed.questions.len(), ASKED);
+    }
+
+    #[actix_web::test]
+    async fn locked_out_user() {
+        use crate::api::Fixture;
+
+        let config = Config::test(ServerType::QA).await;
+        let otp = config.register(&crate::test::share(), &<QAAuthenticator as crate::api::Fixture>::registration_data()).await;
+        config.verify_register(&otp).await;
+
+        let data = QAAuthenticator::correct_data(config.auth().await);
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(verify(&config, &QAAuthenticator::incorrect_data()).await, StatusCode::UNAUTHORIZED);
+        }
+
+        // Correct answers are refused, and no questions are asked, until the lockout ends.
+        assert_eq!(verify(&config, &data).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(authenticate(&config).await, StatusCode::TOO_MANY_REQUESTS);
+
+        sqlx::query("UPDATE authenticated SET locked_until=$1")
+            .bind(now())
+            .execute(&config.servers[0].database)
+            .await
+            .unwrap();
+        let data = QAAuthenticator::correct_data(config.auth().await);
+        assert!(config.verify_auth(&data).await.is_some());
+    }
+
+    #[actix_web::test]
+    async fn question_catalog() {
+        let config = Config::test(ServerType::QA).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/questions").to_request();
+        let questions: Vec<String> = actix_web::test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(questions, CATALOG);
+
+        // The questions, and the challenge `/authenticate` answers with, are described.
+        let req = actix_web::test::TestRequest::get().uri("/openapi.json").to_request();
+        let spec: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert!(spec["paths"].get("/questions").is_some());
+        assert_eq!(
+            spec["paths"]["/authenticate"]["post"]["responses"]["200"]["content"]["application/json"]["schema"],
+            serde_json::json!({ "$ref": "#/components/schemas/QaChallenge" })
+        );
+    }
+}
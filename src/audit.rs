@@ -0,0 +1,464 @@
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use actix_web::HttpRequest;
+use hyper::header::USER_AGENT;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::api::base::BaseAuthenticator;
+use crate::config::{ServerType, Settings};
+use crate::db;
+
+/// `prev_hash` of the first row in the chain.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Security relevant events recorded in the audit log.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AuditEvent {
+    RegisterRequested,
+    RegisterVerified,
+    /// A verified user registered again, replacing their secret component.
+    Rotated,
+    AuthRequested,
+    AuthVerified,
+    AuthFailed,
+    /// An operator deleted the user, see [`crate::config::Command::DeleteUser`].
+    Deleted,
+    /// Peers refreshed the secret component, see [`crate::refresh`].
+    Refreshed,
+}
+
+/// Where an event came from, unknown for the commands an operator runs on the server itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Origin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Origin {
+    /// The peer that sent a request, or the client it was forwarded for by a trusted proxy.
+    ///
+    /// Forwarding headers are set by the client unless a proxy overwrites them, so they are
+    /// ignored for any other peer.
+    pub fn of(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> Self {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+
+        let ip = match peer {
+            Some(peer) if trusted_proxies.contains(&peer) => {
+                req.connection_info().realip_remote_addr().map(String::from)
+            }
+            peer => peer.map(|peer| peer.to_string()),
+        };
+
+        Self {
+            ip,
+            user_agent: req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|agent| agent.to_str().ok())
+                .map(String::from),
+        }
+    }
+}
+
+/// A single row of the `audit_log` table.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: i64,
+    pub server_ty: String,
+    pub event: String,
+    pub identity: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// The hash a row must carry, given the hash of the row before it.
+    ///
+    /// Every column except the database assigned `id` is covered.
+    pub fn compute_hash(&self) -> String {
+        let content = serde_json::to_string(&(
+            &self.prev_hash,
+            self.created_at,
+            &self.server_ty,
+            &self.event,
+            &self.identity,
+            &self.ip,
+            &self.user_agent,
+        ))
+        .expect("Could not serialize audit entry");
+
+        format!("{:x}", Sha256::digest(content.as_bytes()))
+    }
+}
+
+/// Why an audit log failed verification, and at which row.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChainError {
+    /// The row does not point to the hash of the row before it.
+    BrokenLink { id: i64 },
+    /// The row's contents do not match its hash.
+    Tampered { id: i64 },
+}
+
+impl std::fmt::Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BrokenLink { id } => write!(f, "audit log chain is broken at row {}", id),
+            Self::Tampered { id } => write!(f, "audit log row {} has been tampered with", id),
+        }
+    }
+}
+
+/// Checks the integrity of a chain of entries, ordered by `id`.
+///
+/// Returns the number of verified entries.
+pub fn verify_chain(entries: &[AuditEntry]) -> Result<usize, ChainError> {
+    let mut prev_hash = GENESIS_HASH;
+
+    for entry in entries {
+        if entry.prev_hash != prev_hash {
+            return Err(ChainError::BrokenLink { id: entry.id });
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(ChainError::Tampered { id: entry.id });
+        }
+        prev_hash = &entry.hash;
+    }
+
+    Ok(entries.len())
+}
+
+/// The first of `heads` no entry carries, meaning rows were removed from the end of a log.
+///
+/// The hash of the last entry is the head of a log, which an operator keeps from one verification
+/// to the next, since a log cut short is still a valid chain.
+pub fn missing_head<'a>(entries: &[AuditEntry], heads: &'a [String]) -> Option<&'a str> {
+    heads
+        .iter()
+        .find(|head| !entries.iter().any(|entry| &entry.hash == *head))
+        .map(String::as_str)
+}
+
+/// Loads the complete audit log, in chain order.
+pub async fn entries(pool: &PgPool) -> sqlx::Result<Vec<AuditEntry>> {
+    sqlx::query_as!(
+        AuditEntry,
+        "SELECT id, created_at, server_ty, event, identity, ip, user_agent, prev_hash, hash FROM audit_log ORDER BY id;"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn append(
+    pool: &PgPool,
+    server_ty: ServerType,
+    event: AuditEvent,
+    email: &str,
+    origin: Origin,
+) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    append_to(
+        &mut tx,
+        server_ty,
+        event,
+        BaseAuthenticator::hash(email),
+        origin,
+    )
+    .await?;
+
+    tx.commit().await
+}
+
+/// Appends an event about the user whose email hashes to `identity`, as part of `tx`.
+///
+/// For changes that must not happen without being recorded, as failing to record the event fails
+/// the transaction.
+pub async fn append_to(
+    tx: &mut Transaction<'_, Postgres>,
+    server_ty: ServerType,
+    event: AuditEvent,
+    identity: String,
+    origin: Origin,
+) -> sqlx::Result<()> {
+    // Appends must be serialized, otherwise two rows could share the same predecessor.
+    sqlx::query!("LOCK TABLE audit_log IN SHARE ROW EXCLUSIVE MODE;")
+        .execute(&mut *tx)
+        .await?;
+
+    let prev_hash = sqlx::query!("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1;")
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|rec| rec.hash)
+        .unwrap_or_else(|| GENESIS_HASH.into());
+
+    let mut entry = AuditEntry {
+        id: 0,
+        created_at: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64,
+        server_ty: format!("{:?}", server_ty),
+        event: format!("{:?}", event),
+        identity,
+        ip: origin.ip,
+        user_agent: origin.user_agent,
+        prev_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry.compute_hash();
+
+    sqlx::query!(
+        "INSERT INTO audit_log (created_at, server_ty, event, identity, ip, user_agent, prev_hash, hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8);",
+        entry.created_at,
+        entry.server_ty,
+        entry.event,
+        entry.identity,
+        entry.ip,
+        entry.user_agent,
+        entry.prev_hash,
+        entry.hash,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Appends an event to the audit log.
+///
+/// Failing to record an event does not fail the request it belongs to.
+pub async fn record(
+    pool: &PgPool,
+    server_ty: ServerType,
+    event: AuditEvent,
+    email: &str,
+    origin: Origin,
+) {
+    if let Err(e) = append(pool, server_ty, event, email, origin).await {
+        tracing::error!("Could not record {:?} in the audit log: {}", event, e);
+    }
+}
+
+/// The audit log of one server, once verified.
+#[derive(Debug)]
+pub struct VerifiedLog {
+    pub server_ty: ServerType,
+    /// Number of entries verified.
+    pub count: usize,
+    /// Hash of the last entry, to pass as a `--head` of a later verification.
+    pub head: String,
+}
+
+/// Verifies the audit log of every configured server, without starting a server.
+///
+/// Run with `simple-syrup verify-audit-log`, every one of `heads` must still be in one of the logs.
+pub async fn verify_offline(
+    settings: &Settings,
+    heads: &[String],
+) -> Result<Vec<VerifiedLog>, String> {
+    let mut verified = vec![];
+    let mut all = vec![];
+
+    for server in &settings.servers {
+        let pool = db::new_pool(&server.database)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let entries = entries(&pool).await.map_err(|e| e.to_string())?;
+
+        let count =
+            verify_chain(&entries).map_err(|e| format!("{:?} server: {}", server.server_ty, e))?;
+        verified.push(VerifiedLog {
+            server_ty: server.server_ty,
+            count,
+            head: entries
+                .last()
+                .map_or_else(|| GENESIS_HASH.into(), |entry| entry.hash.clone()),
+        });
+        all.extend(entries);
+    }
+
+    match missing_head(&all, heads) {
+        Some(head) => Err(format!(
+            "audit log head {} is missing, rows were removed from the end of a log",
+            head
+        )),
+        None => Ok(verified),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(len: usize) -> Vec<AuditEntry> {
+        let mut prev_hash = GENESIS_HASH.to_string();
+
+        (0..len)
+            .map(|i| {
+                let mut entry = AuditEntry {
+                    id: i as i64 + 1,
+                    created_at: 1_650_000_000 + i as i64,
+                    server_ty: "Email".into(),
+                    event: format!("{:?}", AuditEvent::AuthRequested),
+                    identity: BaseAuthenticator::hash("benjcape@gmail.com"),
+                    ip: Some("127.0.0.1".into()),
+                    user_agent: None,
+                    prev_hash: prev_hash.clone(),
+                    hash: String::new(),
+                };
+                entry.hash = entry.compute_hash();
+                prev_hash = entry.hash.clone();
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_chain() {
+        assert_eq!(verify_chain(&chain(3)), Ok(3));
+        assert_eq!(verify_chain(&[]), Ok(0));
+    }
+
+    #[test]
+    fn tampered_entry() {
+        let mut entries = chain(3);
+        entries[1].event = format!("{:?}", AuditEvent::AuthVerified);
+
+        assert_eq!(verify_chain(&entries), Err(ChainError::Tampered { id: 2 }));
+    }
+
+    #[actix_web::test]
+    async fn recorded_events() {
+        use crate::api::{email::EmailAuthenticator, Fixture};
+        use crate::config::{Config, ServerType};
+
+        let config = Config::test(ServerType::Email).await;
+        let otp = config
+            .register(
+                &crate::test::share(),
+                &EmailAuthenticator::registration_data(),
+            )
+            .await;
+        config.verify_register(&otp).await;
+
+        let entries = entries(&config.servers[0].database).await.unwrap();
+        assert!(verify_chain(&entries).is_ok());
+
+        let last = entries.last().unwrap();
+        assert_eq!(last.event, format!("{:?}", AuditEvent::RegisterVerified));
+        assert_eq!(last.identity, BaseAuthenticator::hash("benjcape@gmail.com"));
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+        let req = |peer: IpAddr| {
+            actix_web::test::TestRequest::default()
+                .peer_addr((peer, 4000).into())
+                .insert_header(("X-Forwarded-For", "203.0.113.7"))
+                .insert_header((USER_AGENT, "cpass"))
+                .to_http_request()
+        };
+
+        let direct = Origin::of(&req(IpAddr::from([192, 0, 2, 1])), &[proxy]);
+        assert_eq!(direct.ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(direct.user_agent.as_deref(), Some("cpass"));
+
+        let forwarded = Origin::of(&req(proxy), &[proxy]);
+        assert_eq!(forwarded.ip.as_deref(), Some("203.0.113.7"));
+
+        let untrusted = Origin::of(&req(proxy), &[]);
+        assert_eq!(untrusted.ip.as_deref(), Some("10.0.0.1"));
+    }
+
+    #[actix_web::test]
+    async fn deleted_user() {
+        use crate::api::{base::BaseAuthenticator, email::EmailAuthenticator, Fixture};
+        use crate::config::{Config, ServerType};
+
+        let config = Config::test(ServerType::Email).await;
+        let otp = config
+            .register(
+                &crate::test::share(),
+                &EmailAuthenticator::registration_data(),
+            )
+            .await;
+        config.verify_register(&otp).await;
+
+        let base = BaseAuthenticator::new(&config.servers[0]);
+        assert!(base
+            .delete(ServerType::Email, "benjcape@gmail.com")
+            .await
+            .unwrap());
+        assert!(base
+            .get_authenticated_id("benjcape@gmail.com")
+            .await
+            .is_none());
+        assert!(!base
+            .delete(ServerType::Email, "benjcape@gmail.com")
+            .await
+            .unwrap());
+
+        let entries = entries(&config.servers[0].database).await.unwrap();
+        assert!(verify_chain(&entries).is_ok());
+
+        let last = entries.last().unwrap();
+        assert_eq!(last.event, format!("{:?}", AuditEvent::Deleted));
+        assert_eq!(last.ip, None);
+    }
+
+    #[test]
+    fn truncated_chain() {
+        let mut entries = chain(3);
+        let heads = [entries[1].hash.clone(), entries[2].hash.clone()];
+        assert_eq!(missing_head(&entries, &heads), None);
+
+        entries.pop();
+        assert_eq!(verify_chain(&entries), Ok(2));
+        assert_eq!(missing_head(&entries, &heads), Some(heads[1].as_str()));
+    }
+
+    #[actix_web::test]
+    async fn append_only() {
+        use crate::config::{Config, ServerType};
+
+        let config = Config::test(ServerType::Email).await;
+        let pool = &config.servers[0].database;
+        record(
+            pool,
+            ServerType::Email,
+            AuditEvent::AuthRequested,
+            "benjcape@gmail.com",
+            Origin::default(),
+        )
+        .await;
+
+        for statement in [
+            "UPDATE audit_log SET event = 'Deleted'",
+            "DELETE FROM audit_log",
+            "TRUNCATE audit_log",
+        ] {
+            let e = sqlx::query(statement).execute(pool).await.unwrap_err();
+            assert!(e.to_string().contains("append-only"), "{}", statement);
+        }
+        assert!(!entries(pool).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn removed_entry() {
+        let mut entries = chain(3);
+        entries.remove(1);
+
+        assert_eq!(
+            verify_chain(&entries),
+            Err(ChainError::BrokenLink { id: 3 })
+        );
+    }
+}
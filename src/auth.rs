@@ -4,16 +4,8 @@ use actix_web_httpauth::extractors::bearer::{BearerAuth, Config};
 use actix_web_httpauth::extractors::AuthenticationError;
 use alcoholic_jwt::{token_kid, validate, Validation, JWKS};
 use hyper::StatusCode;
-use serde::{Deserialize, Serialize};
 use std::error::Error;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    company: String,
-    exp: usize,
-}
-
 pub async fn validate_token(token: &str) -> Result<bool, HttpResponse> {
     let authority = std::env::var("AUTHORITY").expect("AUTHORITY must be set");
     let jwks = fetch_jwks(&format!(
@@ -0,0 +1,321 @@
+#[cfg(feature = "password")]
+use crate::api::policy::PasswordPolicy;
+use crate::db;
+use crate::identity::Identity;
+use crate::registry::Registry;
+use actix_web::{web, HttpRequest, Responder};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pub mod settings;
+
+#[cfg(feature = "biometric")]
+pub use settings::BiometricOptions;
+pub use settings::{
+    AuditOptions, Cli, Command, CorsOptions, EmailOptions, OtpOptions, RefreshOptions, RegistryOptions,
+    ServerSettings, Settings,
+};
+
+/// The kinds of servers, of which settings only accept those built in, see [`settings::built_in`].
+pub(crate) use simple_syrup_client::ServerType;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DBOptions {
+    pub(crate) uri: String,
+    pub(crate) max_connections: u32,
+}
+
+#[derive(Clone)]
+pub struct Server {
+    pub(crate) _dev_port: u32,
+    /// Path the server is mounted under, empty when it is the only one in the process.
+    pub(crate) prefix: String,
+    pub(crate) database: PgPool,
+    pub(crate) max_connections: u32,
+    pub(crate) server_ty: ServerType,
+    pub(crate) otp: OtpOptions,
+    pub(crate) email: EmailOptions,
+    #[cfg(feature = "biometric")]
+    pub(crate) biometric: BiometricOptions,
+    #[cfg(feature = "password")]
+    pub(crate) password: PasswordPolicy,
+    pub(crate) identity: Identity,
+    pub(crate) audit: AuditOptions,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub(crate) servers: Vec<Server>,
+    pub(crate) host: String,
+    pub(crate) port: u32,
+    pub(crate) public_url: Option<String>,
+    pub(crate) active_servers: Vec<ServerPublicData>,
+    pub(crate) cors: CorsOptions,
+    pub(crate) identity: Identity,
+    pub(crate) registry: RegistryOptions,
+    pub(crate) refresh: RefreshOptions,
+}
+
+impl Config {
+    #[cfg(test)]
+    async fn test_server(server_ty: ServerType, prefix: &str, uri: String) -> Server {
+        let database = db::new_pool(&DBOptions {
+            uri,
+            max_connections: db::MAX_CONNECTIONS,
+        })
+        .await
+        .expect("Could not connect to test pool");
+
+        sqlx::migrate!()
+            .run(&database)
+            .await
+            .expect("Could not perform db migrations");
+
+        sqlx::query!("DELETE FROM authenticated")
+            .execute(&database)
+            .await
+            .expect("Error clearing database");
+        sqlx::query!("DELETE FROM refresh_updates")
+            .execute(&database)
+            .await
+            .expect("Error clearing database");
+
+        // Devices approve every request, tests of other outcomes start their own mock.
+        #[cfg(feature = "biometric")]
+        let biometric = BiometricOptions {
+            api_url: matches!(server_ty, ServerType::Biometric).then(|| {
+                crate::test::biometric::MockBiometric::start(
+                    crate::test::biometric::Outcome::Approve,
+                )
+                .url()
+            }),
+            timeout: crate::test::biometric::TIMEOUT,
+            retries: 1,
+            webhook_key: None,
+        };
+
+        Server {
+            _dev_port: 0000,
+            prefix: prefix.into(),
+            database,
+            max_connections: db::MAX_CONNECTIONS,
+            server_ty,
+            otp: OtpOptions::default(),
+            email: EmailOptions::default(),
+            #[cfg(feature = "biometric")]
+            biometric,
+            #[cfg(feature = "password")]
+            password: PasswordPolicy::default(),
+            identity: Identity::generate(),
+            audit: AuditOptions::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn test(server_ty: ServerType) -> Self {
+        let active_server = ServerPublicData {
+            server_ty,
+            url: "https://server.test:8080".into(),
+            public_key: None,
+            ready: None,
+        };
+
+        let db = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let server = Self::test_server(server_ty, "", db).await;
+
+        // A single server signs the root listing with its own key.
+        let identity = server.identity.clone();
+
+        Self {
+            servers: vec![server],
+            host: "".into(),
+            port: 0,
+            public_url: None,
+            active_servers: vec![active_server],
+            cors: CorsOptions::default(),
+            identity,
+            registry: RegistryOptions::default(),
+            refresh: RefreshOptions::default(),
+        }
+    }
+
+    /// Mounts each server under `/<server_ty>`, with its own database next to the test database.
+    #[cfg(test)]
+    pub(crate) async fn test_mounted(server_tys: &[ServerType]) -> Self {
+        let db = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let (base, _) = db
+            .rsplit_once('/')
+            .expect("DATABASE_URL has no database name");
+
+        let admin = sqlx::PgPool::connect(&db)
+            .await
+            .expect("Could not connect to test pool");
+
+        let mut servers = vec![];
+        for server_ty in server_tys {
+            let name = format!("{:?}", server_ty).to_lowercase();
+            let database = format!("simple_syrup_test_{}", name);
+
+            let exists = sqlx::query("SELECT 1 FROM pg_database WHERE datname = $1")
+                .bind(&database)
+                .fetch_optional(&admin)
+                .await
+                .expect("Could not list databases")
+                .is_some();
+            if !exists {
+                sqlx::query(&format!("CREATE DATABASE {}", database))
+                    .execute(&admin)
+                    .await
+                    .expect("Could not create test database");
+            }
+
+            let uri = format!("{}/{}", base, database);
+            servers.push(Self::test_server(*server_ty, &format!("/{}", name), uri).await);
+        }
+
+        Self {
+            servers,
+            host: "".into(),
+            port: 0,
+            public_url: Some("http://localhost:8080".into()),
+            active_servers: vec![],
+            cors: CorsOptions::default(),
+            identity: Identity::generate(),
+            registry: RegistryOptions::default(),
+            refresh: RefreshOptions::default(),
+        }
+    }
+
+    pub async fn new(settings: Settings) -> Self {
+        let Settings {
+            host,
+            port,
+            public_url,
+            servers,
+            active_servers,
+            otp,
+            email,
+            cors,
+            #[cfg(feature = "biometric")]
+            biometric,
+            #[cfg(feature = "password")]
+            password,
+            identity,
+            registry,
+            refresh,
+            audit,
+        } = settings;
+
+        let mut mounted = vec![];
+        for ServerSettings {
+            server_ty,
+            prefix,
+            database,
+            identity,
+        } in servers
+        {
+            let max_connections = database.max_connections;
+            let database = db::new_pool(&database)
+                .await
+                .expect("Could not connect to db");
+
+            mounted.push(Server {
+                database,
+                max_connections,
+                server_ty,
+                prefix,
+                otp,
+                email: email.clone(),
+                #[cfg(feature = "biometric")]
+                biometric: biometric.clone(),
+                #[cfg(feature = "password")]
+                password: password.clone(),
+                identity,
+                audit: audit.clone(),
+                _dev_port: port,
+            });
+        }
+
+        Self {
+            host,
+            servers: mounted,
+            port,
+            public_url,
+            active_servers,
+            cors,
+            identity,
+            registry,
+            refresh,
+        }
+    }
+
+    /// The url, type and public key of every server mounted under a prefix, at the `public_url`
+    /// of this process.
+    pub(crate) fn mounts(&self) -> Mounts {
+        let public_url = match &self.public_url {
+            Some(url) => url.trim_end_matches('/'),
+            None => return Mounts::default(),
+        };
+
+        Mounts(
+            self.servers
+                .iter()
+                .filter(|server| !server.prefix.is_empty())
+                .map(|server| ServerPublicData {
+                    url: format!("{}{}", public_url, server.prefix),
+                    server_ty: server.server_ty,
+                    public_key: Some(server.identity.public_key()),
+                    ready: None,
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Urls of the servers mounted under a path prefix of this process, listed by [`root`] next to
+/// the `active_servers`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Mounts(pub(crate) Vec<ServerPublicData>);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerPublicData {
+    pub(crate) url: String,
+    pub(crate) server_ty: ServerType,
+    /// Known for servers mounted in this process, and those that announced themselves to the
+    /// registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) public_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ready: Option<bool>,
+}
+
+/// Lists the live servers known to the registry, or else the static `active_servers` and the
+/// servers mounted in this process.
+///
+/// The listing is signed by the identity of this process.
+#[actix_web::get("/")]
+pub async fn root(req: HttpRequest) -> impl Responder {
+    let live = req
+        .app_data::<web::Data<Registry>>()
+        .map(|registry| registry.live())
+        .unwrap_or_default();
+    let identity = req.app_data::<Identity>().unwrap();
+
+    if !live.is_empty() {
+        return identity.signed_json(StatusCode::OK, &live);
+    }
+
+    let mut servers = req.app_data::<Vec<ServerPublicData>>().unwrap().clone();
+
+    // Urls come from the configuration, as the Host of the request is set by the client.
+    if let Some(Mounts(mounts)) = req.app_data::<Mounts>() {
+        for mounted in mounts {
+            if !servers.iter().any(|server| server.url == mounted.url) {
+                servers.push(mounted.clone());
+            }
+        }
+    }
+
+    identity.signed_json(StatusCode::OK, &servers)
+}
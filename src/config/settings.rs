@@ -0,0 +1,1270 @@
+//! Layered configuration.
+//!
+//! Settings are read from a TOML or YAML file, then overridden by environment variables, then by
+//! command line flags. Everything is validated before the server starts, and all problems are
+//! reported together.
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use super::{DBOptions, ServerPublicData, ServerType};
+#[cfg(feature = "password")]
+use crate::api::policy::{self, PasswordPolicy};
+use crate::db;
+use crate::identity::Identity;
+
+#[derive(Debug, Default, Parser)]
+#[clap(version, about = "CryptoPass authenticator server")]
+pub struct Cli {
+    /// Path to a TOML or YAML configuration file. Defaults to `CONFIG_FILE`
+    #[clap(long, short)]
+    pub config: Option<PathBuf>,
+
+    /// Overrides `HOST`
+    #[clap(long)]
+    pub host: Option<String>,
+
+    /// Overrides `PORT`
+    #[clap(long)]
+    pub port: Option<u32>,
+
+    /// Overrides `DATABASE_URL`
+    #[clap(long)]
+    pub database_url: Option<String>,
+
+    /// Overrides `SERVER_TY`
+    #[clap(long)]
+    pub server_ty: Option<String>,
+
+    /// Validate the configuration and exit
+    #[clap(long)]
+    pub check_config: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Verify the integrity of the audit log of every configured server
+    VerifyAuditLog {
+        /// Head printed by an earlier verification, which must still be in the logs, so rows
+        /// removed from their end are detected
+        #[clap(long = "head")]
+        heads: Vec<String>,
+    },
+    /// Print a new random `IDENTITY_SECRET_KEY`
+    GenerateIdentity,
+    /// Delete a user from every configured server, recording it in their audit logs
+    DeleteUser {
+        /// Email the user registered with
+        email: String,
+    },
+}
+
+/// Parameters of the TOTP used for one time passwords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OtpOptions {
+    pub digits: usize,
+    pub skew: u8,
+    pub step: u64,
+}
+
+impl Default for OtpOptions {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            skew: 1,
+            step: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EmailOptions {
+    pub sendgrid_key: String,
+    pub from: String,
+}
+
+/// Origins allowed to make cross origin requests. Empty allows any origin.
+#[derive(Clone, Debug, Default)]
+pub struct CorsOptions {
+    pub allowed_origins: Vec<String>,
+}
+
+impl CorsOptions {
+    pub fn middleware(&self) -> actix_cors::Cors {
+        let cors = actix_cors::Cors::default()
+            .allow_any_header()
+            .allow_any_method();
+
+        if self.allowed_origins.is_empty() {
+            cors.allow_any_origin()
+        } else {
+            self.allowed_origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin))
+        }
+    }
+}
+
+/// The device API of Biometric servers.
+#[cfg(feature = "biometric")]
+#[derive(Clone, Debug)]
+pub struct BiometricOptions {
+    pub api_url: Option<String>,
+    /// Of each request to the device API.
+    pub timeout: Duration,
+    /// Times a request failing with an error that may not last is sent again.
+    pub retries: u32,
+    /// Key the device API signs the approvals it pushes with, which are refused when unset.
+    pub webhook_key: Option<String>,
+}
+
+#[cfg(feature = "biometric")]
+impl Default for BiometricOptions {
+    fn default() -> Self {
+        Self {
+            api_url: None,
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            webhook_key: None,
+        }
+    }
+}
+
+/// Service discovery, see [`crate::registry`].
+#[derive(Clone, Debug)]
+pub struct RegistryOptions {
+    /// Registry node this process announces its servers to, at the `public_url` of the process.
+    pub url: Option<String>,
+    /// Keys accepted in announcements, this process is a registry node when any are set.
+    pub trusted_keys: Vec<String>,
+    pub ttl: Duration,
+    pub heartbeat: Duration,
+}
+
+impl Default for RegistryOptions {
+    fn default() -> Self {
+        Self {
+            url: None,
+            trusted_keys: vec![],
+            ttl: Duration::from_secs(30),
+            heartbeat: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Proactive share refresh, see [`crate::refresh`].
+#[derive(Clone, Debug, Default)]
+pub struct RefreshOptions {
+    /// Urls of every server holding a share of the same users, this process' servers included.
+    pub peers: Vec<String>,
+    /// Keys of the peers, a refresh is applied once each of them dealt its shares of zero.
+    pub trusted_keys: Vec<String>,
+    /// Time between the refresh rounds dealt by this process, which deals none when unset.
+    pub interval: Option<Duration>,
+}
+
+/// The audit log, see [`crate::audit`].
+#[derive(Clone, Debug, Default)]
+pub struct AuditOptions {
+    /// Proxies trusted to forward the client address, which is otherwise the peer address.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// An authenticator served by this process.
+#[derive(Clone, Debug)]
+pub struct ServerSettings {
+    pub(crate) server_ty: ServerType,
+    /// Empty for a single server, otherwise the path it is mounted under, such as `/email`.
+    pub(crate) prefix: String,
+    pub(crate) database: DBOptions,
+    /// Signs the responses of this server.
+    pub(crate) identity: Identity,
+}
+
+/// A complete and validated configuration.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub(crate) host: String,
+    pub(crate) port: u32,
+    /// Base url this process is reachable at, under which its servers are listed and announced.
+    pub(crate) public_url: Option<String>,
+    pub(crate) servers: Vec<ServerSettings>,
+    pub(crate) active_servers: Vec<ServerPublicData>,
+    pub(crate) otp: OtpOptions,
+    pub(crate) email: EmailOptions,
+    pub(crate) cors: CorsOptions,
+    #[cfg(feature = "biometric")]
+    pub(crate) biometric: BiometricOptions,
+    #[cfg(feature = "password")]
+    pub(crate) password: PasswordPolicy,
+    /// Signs the root listing, and is the identity of servers without their own key.
+    pub(crate) identity: Identity,
+    pub(crate) registry: RegistryOptions,
+    pub(crate) refresh: RefreshOptions,
+    pub(crate) audit: AuditOptions,
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError(pub Vec<String>);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawDatabase {
+    pub uri: Option<String>,
+    pub max_connections: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawOtp {
+    pub digits: Option<usize>,
+    pub skew: Option<u8>,
+    pub step: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawEmail {
+    pub sendgrid_key: Option<String>,
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawCors {
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawBiometric {
+    pub api_url: Option<String>,
+    pub timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub webhook_key: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawPassword {
+    pub min_length: Option<usize>,
+    pub breached_list: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawServer {
+    pub url: String,
+    pub server_ty: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawIdentity {
+    pub secret_key: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawRegistry {
+    pub url: Option<String>,
+    pub trusted_keys: Option<Vec<String>>,
+    pub ttl: Option<u64>,
+    pub heartbeat: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawRefresh {
+    pub peers: Option<Vec<String>>,
+    pub trusted_keys: Option<Vec<String>>,
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawAudit {
+    pub trusted_proxies: Option<Vec<String>>,
+}
+
+/// An entry of `servers`, mounting an authenticator under a path prefix.
+///
+/// Missing database values are taken from the top level `database`. As every server creates the
+/// same tables, at most one of them may use the top level database uri.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawMount {
+    pub server_ty: String,
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub database: RawDatabase,
+    pub secret_key: Option<String>,
+}
+
+/// A single configuration layer, where anything may be missing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawSettings {
+    pub host: Option<String>,
+    pub port: Option<u32>,
+    pub public_url: Option<String>,
+    pub server_ty: Option<String>,
+    pub active_servers: Option<Vec<RawServer>>,
+    pub servers: Option<Vec<RawMount>>,
+    pub database: RawDatabase,
+    pub otp: RawOtp,
+    pub email: RawEmail,
+    pub cors: RawCors,
+    pub biometric: RawBiometric,
+    pub password: RawPassword,
+    pub identity: RawIdentity,
+    pub registry: RawRegistry,
+    pub refresh: RawRefresh,
+    pub audit: RawAudit,
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok()
+}
+
+fn env_parsed<T: std::str::FromStr>(key: &str, errors: &mut Vec<String>) -> Option<T> {
+    env(key).and_then(|v| match v.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            errors.push(format!("{} is not valid: {:?}", key, v));
+            None
+        }
+    })
+}
+
+impl RawSettings {
+    pub fn from_file(path: &Path, errors: &mut Vec<String>) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                errors.push(format!("Could not read {}: {}", path.display(), e));
+                return Self::default();
+            }
+        };
+
+        let is_yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml") | Some("yml")
+        );
+
+        let parsed = if is_yaml {
+            serde_yaml::from_str(&contents).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&contents).map_err(|e| e.to_string())
+        };
+
+        parsed.unwrap_or_else(|e| {
+            errors.push(format!("Could not parse {}: {}", path.display(), e));
+            Self::default()
+        })
+    }
+
+    pub fn from_env(errors: &mut Vec<String>) -> Self {
+        let active_servers = env("ACTIVE_SERVERS").and_then(|v| match serde_json::from_str(&v) {
+            Ok(servers) => Some(servers),
+            Err(e) => {
+                errors.push(format!("ACTIVE_SERVERS is not correctly formatted: {}", e));
+                None
+            }
+        });
+
+        let servers = env("SERVERS").and_then(|v| match serde_json::from_str(&v) {
+            Ok(servers) => Some(servers),
+            Err(e) => {
+                errors.push(format!("SERVERS is not correctly formatted: {}", e));
+                None
+            }
+        });
+
+        let allowed_origins = env("CORS_ALLOWED_ORIGINS").map(|v| split_list(&v));
+
+        Self {
+            host: env("HOST"),
+            port: env_parsed("PORT", errors),
+            public_url: env("PUBLIC_URL"),
+            server_ty: env("SERVER_TY"),
+            active_servers,
+            servers,
+            database: RawDatabase {
+                uri: env("DATABASE_URL"),
+                max_connections: env_parsed("DATABASE_MAX_CONNECTIONS", errors),
+            },
+            otp: RawOtp {
+                digits: env_parsed("OTP_DIGITS", errors),
+                skew: env_parsed("OTP_SKEW", errors),
+                step: env_parsed("OTP_STEP", errors),
+            },
+            email: RawEmail {
+                sendgrid_key: env("SENDGRID_KEY"),
+                from: env("EMAIL_FROM"),
+            },
+            cors: RawCors { allowed_origins },
+            biometric: RawBiometric {
+                api_url: env("BIOMETRIC_API_URL"),
+                timeout: env_parsed("BIOMETRIC_TIMEOUT", errors),
+                retries: env_parsed("BIOMETRIC_RETRIES", errors),
+                webhook_key: env("BIOMETRIC_WEBHOOK_KEY"),
+            },
+            password: RawPassword {
+                min_length: env_parsed("PASSWORD_MIN_LENGTH", errors),
+                breached_list: env("PASSWORD_BREACHED_LIST").map(PathBuf::from),
+            },
+            identity: RawIdentity {
+                secret_key: env("IDENTITY_SECRET_KEY"),
+            },
+            registry: RawRegistry {
+                url: env("REGISTRY_URL"),
+                trusted_keys: env("REGISTRY_TRUSTED_KEYS").map(|v| split_list(&v)),
+                ttl: env_parsed("REGISTRY_TTL", errors),
+                heartbeat: env_parsed("REGISTRY_HEARTBEAT", errors),
+            },
+            refresh: RawRefresh {
+                peers: env("REFRESH_PEERS").map(|v| split_list(&v)),
+                trusted_keys: env("REFRESH_TRUSTED_KEYS").map(|v| split_list(&v)),
+                interval: env_parsed("REFRESH_INTERVAL", errors),
+            },
+            audit: RawAudit {
+                trusted_proxies: env("AUDIT_TRUSTED_PROXIES").map(|v| split_list(&v)),
+            },
+        }
+    }
+
+    pub fn from_cli(cli: &Cli) -> Self {
+        Self {
+            host: cli.host.clone(),
+            port: cli.port,
+            server_ty: cli.server_ty.clone(),
+            database: RawDatabase {
+                uri: cli.database_url.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Overrides any value of `self` that is also set in `other`.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            host: other.host.or(self.host),
+            port: other.port.or(self.port),
+            public_url: other.public_url.or(self.public_url),
+            server_ty: other.server_ty.or(self.server_ty),
+            active_servers: other.active_servers.or(self.active_servers),
+            servers: other.servers.or(self.servers),
+            database: RawDatabase {
+                uri: other.database.uri.or(self.database.uri),
+                max_connections: other
+                    .database
+                    .max_connections
+                    .or(self.database.max_connections),
+            },
+            otp: RawOtp {
+                digits: other.otp.digits.or(self.otp.digits),
+                skew: other.otp.skew.or(self.otp.skew),
+                step: other.otp.step.or(self.otp.step),
+            },
+            email: RawEmail {
+                sendgrid_key: other.email.sendgrid_key.or(self.email.sendgrid_key),
+                from: other.email.from.or(self.email.from),
+            },
+            cors: RawCors {
+                allowed_origins: other.cors.allowed_origins.or(self.cors.allowed_origins),
+            },
+            biometric: RawBiometric {
+                api_url: other.biometric.api_url.or(self.biometric.api_url),
+                timeout: other.biometric.timeout.or(self.biometric.timeout),
+                retries: other.biometric.retries.or(self.biometric.retries),
+                webhook_key: other.biometric.webhook_key.or(self.biometric.webhook_key),
+            },
+            password: RawPassword {
+                min_length: other.password.min_length.or(self.password.min_length),
+                breached_list: other.password.breached_list.or(self.password.breached_list),
+            },
+            identity: RawIdentity {
+                secret_key: other.identity.secret_key.or(self.identity.secret_key),
+            },
+            registry: RawRegistry {
+                url: other.registry.url.or(self.registry.url),
+                trusted_keys: other.registry.trusted_keys.or(self.registry.trusted_keys),
+                ttl: other.registry.ttl.or(self.registry.ttl),
+                heartbeat: other.registry.heartbeat.or(self.registry.heartbeat),
+            },
+            refresh: RawRefresh {
+                peers: other.refresh.peers.or(self.refresh.peers),
+                trusted_keys: other.refresh.trusted_keys.or(self.refresh.trusted_keys),
+                interval: other.refresh.interval.or(self.refresh.interval),
+            },
+            audit: RawAudit {
+                trusted_proxies: other.audit.trusted_proxies.or(self.audit.trusted_proxies),
+            },
+        }
+    }
+
+    /// Checks that every required value is present and well formed.
+    pub fn validate(self) -> Result<Settings, ConfigError> {
+        let mut errors = vec![];
+
+        let host = self.host.filter(|host| !host.is_empty());
+        if host.is_none() {
+            errors.push("Must supply HOST".into());
+        }
+
+        let port = match self.port {
+            Some(0) => {
+                errors.push("PORT must be a positive integer".into());
+                None
+            }
+            Some(port) => Some(port),
+            None => {
+                errors.push("Must supply PORT".into());
+                None
+            }
+        };
+
+        let public_url = self.public_url.filter(|url| !url.is_empty());
+        if let Some(url) = public_url.as_ref().filter(|url| !is_http_url(url)) {
+            errors.push(format!("PUBLIC_URL is not an http(s) url: {}", url));
+        }
+
+        let identity = validate_identity(self.identity.secret_key, None, "", &mut errors);
+
+        let multiple = self
+            .servers
+            .as_ref()
+            .is_some_and(|servers| !servers.is_empty());
+
+        let servers = if multiple {
+            if self.server_ty.is_some() {
+                errors.push("SERVER_TY cannot be combined with servers".into());
+            }
+            if public_url.is_none() {
+                errors.push("Must supply PUBLIC_URL to list the mounted servers".into());
+            }
+            validate_mounts(
+                self.servers.unwrap_or_default(),
+                &self.database,
+                &identity,
+                &mut errors,
+            )
+        } else {
+            let server_ty = match self.server_ty {
+                Some(ty) => parse_server_ty(&ty, "SERVER_TY", &mut errors),
+                None => {
+                    errors.push("Must supply SERVER_TY".into());
+                    None
+                }
+            };
+            let database = validate_database(self.database, "", &mut errors);
+
+            server_ty
+                .map(|server_ty| {
+                    vec![ServerSettings {
+                        server_ty,
+                        prefix: "".into(),
+                        database,
+                        identity: identity.clone(),
+                    }]
+                })
+                .unwrap_or_default()
+        };
+
+        let active_servers = match self.active_servers {
+            Some(servers) => servers
+                .into_iter()
+                .filter_map(|RawServer { url, server_ty }| {
+                    if !is_http_url(&url) {
+                        errors.push(format!("ACTIVE_SERVERS url is not an http(s) url: {}", url));
+                    }
+                    parse_server_ty(&server_ty, "ACTIVE_SERVERS server_ty", &mut errors).map(
+                        |server_ty| ServerPublicData {
+                            url,
+                            server_ty,
+                            public_key: None,
+                            ready: None,
+                        },
+                    )
+                })
+                .collect(),
+            // Mounted servers are listed by the root, so other servers are optional.
+            None if multiple => vec![],
+            None => {
+                errors.push("Must supply ACTIVE_SERVERS".into());
+                vec![]
+            }
+        };
+
+        let default_otp = OtpOptions::default();
+        let otp = OtpOptions {
+            digits: self.otp.digits.unwrap_or(default_otp.digits),
+            skew: self.otp.skew.unwrap_or(default_otp.skew),
+            step: self.otp.step.unwrap_or(default_otp.step),
+        };
+        if !(6..=8).contains(&otp.digits) {
+            errors.push("OTP_DIGITS must be between 6 and 8".into());
+        }
+        if otp.step == 0 {
+            errors.push("OTP_STEP must be at least 1 second".into());
+        }
+
+        let email = EmailOptions {
+            sendgrid_key: self.email.sendgrid_key.unwrap_or_default(),
+            from: self
+                .email
+                .from
+                .unwrap_or_else(|| "benjcape@gmail.com".into()),
+        };
+        if email.sendgrid_key.is_empty() {
+            errors.push("Must supply SENDGRID_KEY".into());
+        }
+        if !email.from.contains('@') {
+            errors.push(format!(
+                "EMAIL_FROM is not an email address: {}",
+                email.from
+            ));
+        }
+
+        let cors = CorsOptions {
+            allowed_origins: self.cors.allowed_origins.unwrap_or_default(),
+        };
+        cors.allowed_origins
+            .iter()
+            .filter(|origin| !is_http_url(origin))
+            .for_each(|origin| {
+                errors.push(format!(
+                    "CORS_ALLOWED_ORIGINS entry is not an http(s) origin: {}",
+                    origin
+                ))
+            });
+
+        #[cfg(feature = "biometric")]
+        let biometric = {
+            let default_biometric = BiometricOptions::default();
+            let biometric = BiometricOptions {
+                api_url: self.biometric.api_url.filter(|url| !url.is_empty()),
+                timeout: self
+                    .biometric
+                    .timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(default_biometric.timeout),
+                retries: self.biometric.retries.unwrap_or(default_biometric.retries),
+                webhook_key: self.biometric.webhook_key.filter(|key| !key.is_empty()),
+            };
+            if servers.iter().any(|server| matches!(server.server_ty, ServerType::Biometric))
+                && biometric.api_url.is_none()
+            {
+                errors.push("Must supply BIOMETRIC_API_URL for a Biometric server".into());
+            }
+            if biometric.timeout.as_secs() == 0 {
+                errors.push("BIOMETRIC_TIMEOUT must be at least 1 second".into());
+            }
+            if let Some(key) = &biometric.webhook_key {
+                if !base64::decode(key).is_ok_and(|key| key.len() == 32) {
+                    errors.push("BIOMETRIC_WEBHOOK_KEY is not a base64 Ed25519 public key".into());
+                }
+            }
+
+            biometric
+        };
+
+        #[cfg(feature = "password")]
+        let password = PasswordPolicy::new(self.password.min_length.unwrap_or(policy::MIN_LENGTH));
+        #[cfg(feature = "password")]
+        let password = match self
+            .password
+            .breached_list
+            .filter(|path| !path.as_os_str().is_empty())
+        {
+            Some(path) => password.with_breached_list(&path).unwrap_or_else(|e| {
+                errors.push(format!("PASSWORD_BREACHED_LIST: {}", e));
+                PasswordPolicy::default()
+            }),
+            None => password,
+        };
+
+        let default_registry = RegistryOptions::default();
+        let registry = RegistryOptions {
+            url: self.registry.url.filter(|url| !url.is_empty()),
+            trusted_keys: self.registry.trusted_keys.unwrap_or_default(),
+            ttl: self
+                .registry
+                .ttl
+                .map(Duration::from_secs)
+                .unwrap_or(default_registry.ttl),
+            heartbeat: self
+                .registry
+                .heartbeat
+                .map(Duration::from_secs)
+                .unwrap_or(default_registry.heartbeat),
+        };
+        if let Some(url) = &registry.url {
+            if !is_http_url(url) {
+                errors.push(format!("REGISTRY_URL is not an http(s) url: {}", url));
+            }
+            if public_url.is_none() {
+                errors.push("Must supply PUBLIC_URL to announce to a registry".into());
+            }
+        }
+        if registry.heartbeat.as_secs() == 0 || registry.heartbeat >= registry.ttl {
+            errors.push(
+                "REGISTRY_HEARTBEAT must be at least 1 second, and shorter than REGISTRY_TTL"
+                    .into(),
+            );
+        }
+
+        let refresh = RefreshOptions {
+            peers: self.refresh.peers.unwrap_or_default(),
+            trusted_keys: self.refresh.trusted_keys.unwrap_or_default(),
+            interval: self.refresh.interval.map(Duration::from_secs),
+        };
+        for peer in refresh.peers.iter().filter(|peer| !is_http_url(peer)) {
+            errors.push(format!("REFRESH_PEERS entry is not an http(s) url: {}", peer));
+        }
+        if let Some(interval) = refresh.interval {
+            if interval.as_secs() == 0 {
+                errors.push("REFRESH_INTERVAL must be at least 1 second".into());
+            }
+            if refresh.peers.is_empty() || refresh.trusted_keys.is_empty() {
+                errors.push(
+                    "Must supply REFRESH_PEERS and REFRESH_TRUSTED_KEYS to refresh shares".into(),
+                );
+            }
+        }
+
+        let audit = AuditOptions {
+            trusted_proxies: self
+                .audit
+                .trusted_proxies
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|proxy| match proxy.parse() {
+                    Ok(ip) => Some(ip),
+                    Err(_) => {
+                        errors.push(format!(
+                            "AUDIT_TRUSTED_PROXIES entry is not an ip address: {}",
+                            proxy
+                        ));
+                        None
+                    }
+                })
+                .collect(),
+        };
+
+        match (host, port) {
+            (Some(host), Some(port)) if errors.is_empty() => Ok(Settings {
+                host,
+                port,
+                public_url,
+                servers,
+                active_servers,
+                otp,
+                email,
+                cors,
+                #[cfg(feature = "biometric")]
+                biometric,
+                #[cfg(feature = "password")]
+                password,
+                identity,
+                registry,
+                refresh,
+                audit,
+            }),
+            _ => Err(ConfigError(errors)),
+        }
+    }
+}
+
+/// Validates the database of a server, `suffix` names the server in error messages.
+fn validate_database(raw: RawDatabase, suffix: &str, errors: &mut Vec<String>) -> DBOptions {
+    let uri = raw.uri.unwrap_or_default();
+    if uri.is_empty() {
+        errors.push(format!("Must supply DATABASE_URL{}", suffix));
+    } else if !(uri.starts_with("postgres://") || uri.starts_with("postgresql://")) {
+        errors.push(format!("DATABASE_URL{} must be a postgres:// url", suffix));
+    }
+
+    let max_connections = raw.max_connections.unwrap_or(db::MAX_CONNECTIONS);
+    if max_connections == 0 {
+        errors.push(format!(
+            "DATABASE_MAX_CONNECTIONS{} must be at least 1",
+            suffix
+        ));
+    }
+
+    DBOptions {
+        uri,
+        max_connections,
+    }
+}
+
+/// Loads a secret key, or else uses `default`.
+///
+/// Keys are never generated, as others trust them: registries, refresh peers and clients.
+fn validate_identity(
+    secret_key: Option<String>,
+    default: Option<&Identity>,
+    suffix: &str,
+    errors: &mut Vec<String>,
+) -> Identity {
+    match secret_key.filter(|key| !key.is_empty()) {
+        Some(key) => Identity::from_base64(&key).unwrap_or_else(|| {
+            errors.push(format!(
+                "IDENTITY_SECRET_KEY{} must be a base64 encoded 32 byte key",
+                suffix
+            ));
+            Identity::generate()
+        }),
+        None => default.cloned().unwrap_or_else(|| {
+            errors.push(format!(
+                "Must supply IDENTITY_SECRET_KEY{}, create one with `simple-syrup generate-identity`",
+                suffix
+            ));
+            Identity::generate()
+        }),
+    }
+}
+
+/// Validates `servers`, each of which needs its own prefix and its own database.
+///
+/// Servers without a `secret_key` share the identity of the process.
+fn validate_mounts(
+    mounts: Vec<RawMount>,
+    defaults: &RawDatabase,
+    identity: &Identity,
+    errors: &mut Vec<String>,
+) -> Vec<ServerSettings> {
+    let mut prefixes: Vec<String> = vec![];
+    let mut servers: Vec<ServerSettings> = vec![];
+
+    for RawMount {
+        server_ty,
+        prefix,
+        database,
+        secret_key,
+    } in mounts
+    {
+        let server_ty = parse_server_ty(&server_ty, "servers server_ty", errors);
+
+        let prefix = match (prefix, server_ty) {
+            (Some(prefix), _) => prefix,
+            (None, Some(server_ty)) => format!("/{:?}", server_ty).to_lowercase(),
+            (None, None) => continue,
+        };
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            errors.push(format!(
+                "servers prefix must start, and not end, with '/': {:?}",
+                prefix
+            ));
+        }
+        if prefixes.contains(&prefix) {
+            errors.push(format!("servers prefix is used twice: {:?}", prefix));
+        }
+        prefixes.push(prefix.clone());
+
+        let database = validate_database(
+            RawDatabase {
+                uri: database.uri.or_else(|| defaults.uri.clone()),
+                max_connections: database.max_connections.or(defaults.max_connections),
+            },
+            &format!(" for server {}", prefix),
+            errors,
+        );
+
+        if let Some(other) = servers
+            .iter()
+            .find(|other| other.database.uri == database.uri)
+        {
+            errors.push(format!(
+                "servers {} and {} share a database, give each its own database uri",
+                other.prefix, prefix
+            ));
+        }
+
+        let identity = validate_identity(
+            secret_key,
+            Some(identity),
+            &format!(" for server {}", prefix),
+            errors,
+        );
+
+        if let Some(server_ty) = server_ty {
+            servers.push(ServerSettings {
+                server_ty,
+                prefix,
+                database,
+                identity,
+            });
+        }
+    }
+
+    servers
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Whether the servers of a type are compiled into this build, by its feature.
+pub(crate) fn built_in(server_ty: ServerType) -> bool {
+    match server_ty {
+        ServerType::Email => cfg!(feature = "email"),
+        ServerType::QA => cfg!(feature = "qa"),
+        ServerType::Password => cfg!(feature = "password"),
+        ServerType::Biometric => cfg!(feature = "biometric"),
+    }
+}
+
+/// Accepts both `Email` and the JSON encoded `"Email"` used by older deployments.
+fn parse_server_ty(value: &str, key: &str, errors: &mut Vec<String>) -> Option<ServerType> {
+    let value = value.trim().trim_matches('"');
+
+    match serde_json::from_value(serde_json::Value::String(value.into())) {
+        Ok(server_ty) if built_in(server_ty) => Some(server_ty),
+        _ => {
+            errors.push(format!(
+                "{} is not a server type enabled in this build: {:?}",
+                key, value
+            ));
+            None
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the configuration file, environment and command line, in increasing priority.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut errors = vec![];
+
+        let file = cli
+            .config
+            .clone()
+            .or_else(|| env("CONFIG_FILE").map(PathBuf::from))
+            .map(|path| RawSettings::from_file(&path, &mut errors))
+            .unwrap_or_default();
+
+        let env = RawSettings::from_env(&mut errors);
+
+        let settings = file.merge(env).merge(RawSettings::from_cli(cli)).validate();
+
+        match settings {
+            Ok(settings) if errors.is_empty() => Ok(settings),
+            Ok(_) => Err(ConfigError(errors)),
+            Err(ConfigError(more)) => {
+                errors.extend(more);
+                Err(ConfigError(errors))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete() -> RawSettings {
+        toml::from_str(
+            r#"
+            host = "localhost"
+            port = 8080
+            server_ty = "Email"
+            active_servers = [{ url = "http://127.0.0.1:8080", server_ty = "Email" }]
+
+            [database]
+            uri = "postgres://localhost:5432/cpass"
+
+            [email]
+            sendgrid_key = "foobar"
+
+            [identity]
+            secret_key = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc="
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_file() {
+        let settings = complete().validate().unwrap();
+
+        assert_eq!(settings.port, 8080);
+        assert_eq!(settings.servers.len(), 1);
+        assert_eq!(settings.servers[0].prefix, "");
+        assert_eq!(
+            settings.servers[0].database.max_connections,
+            db::MAX_CONNECTIONS
+        );
+        assert_eq!(settings.otp, OtpOptions::default());
+        assert_eq!(settings.active_servers.len(), 1);
+        #[cfg(feature = "password")]
+        assert_eq!(settings.password.min_length, policy::MIN_LENGTH);
+    }
+
+    #[cfg(feature = "password")]
+    #[test]
+    fn password_policy() {
+        let mut settings = complete();
+        settings.password.min_length = Some(12);
+        settings.password.breached_list = Some("/does/not/exist.txt".into());
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("PASSWORD_BREACHED_LIST: Could not read /does/not/exist.txt"));
+
+        let mut settings = complete();
+        settings.password.min_length = Some(12);
+        assert_eq!(settings.validate().unwrap().password.min_length, 12);
+    }
+
+    #[test]
+    fn yaml_file() {
+        let settings: RawSettings = serde_yaml::from_str(
+            r#"
+            host: localhost
+            port: 8080
+            otp:
+              digits: 8
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.port, Some(8080));
+        assert_eq!(settings.otp.digits, Some(8));
+    }
+
+    #[test]
+    fn later_layers_override() {
+        let cli = Cli {
+            port: Some(9090),
+            server_ty: Some("\"Email\"".into()),
+            ..Default::default()
+        };
+
+        let settings = complete()
+            .merge(RawSettings::from_cli(&cli))
+            .validate()
+            .unwrap();
+
+        assert_eq!(settings.port, 9090);
+        assert_eq!(settings.host, "localhost");
+    }
+
+    #[test]
+    fn errors_are_aggregated() {
+        let mut settings = complete();
+        settings.host = None;
+        settings.database.uri = Some("mysql://localhost".into());
+        settings.server_ty = Some("Fingerprint".into());
+        settings.otp.digits = Some(4);
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "Must supply HOST",
+                "SERVER_TY is not a server type enabled in this build: \"Fingerprint\"",
+                "DATABASE_URL must be a postgres:// url",
+                "OTP_DIGITS must be between 6 and 8",
+            ]
+        );
+    }
+
+    #[test]
+    fn mounted_servers() {
+        let mut settings = complete();
+        settings.server_ty = None;
+        settings.active_servers = None;
+        settings.servers = toml::from_str::<RawSettings>(
+            r#"
+            [[servers]]
+            server_ty = "Email"
+
+            [[servers]]
+            server_ty = "QA"
+            prefix = "/questions"
+            database = { uri = "postgres://localhost:5432/cpass2" }
+            "#,
+        )
+        .unwrap()
+        .servers;
+        settings.public_url = Some("https://cpass.test".into());
+
+        let settings = settings.validate().unwrap();
+
+        assert_eq!(settings.servers[0].prefix, "/email");
+        assert_eq!(
+            settings.servers[0].database.uri,
+            "postgres://localhost:5432/cpass"
+        );
+        assert_eq!(
+            settings.servers[1].database.uri,
+            "postgres://localhost:5432/cpass2"
+        );
+        assert_eq!(settings.servers[1].prefix, "/questions");
+        assert!(settings.active_servers.is_empty());
+    }
+
+    #[test]
+    fn mounted_servers_are_isolated() {
+        let mut settings = complete();
+        settings.servers = serde_json::from_str(
+            r#"[
+                { "server_ty": "Email", "prefix": "/email" },
+                { "server_ty": "QA", "prefix": "/email" },
+                { "server_ty": "Password", "prefix": "password/", "database": { "uri": "mysql://localhost" } }
+            ]"#,
+        )
+        .unwrap();
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "SERVER_TY cannot be combined with servers",
+                "Must supply PUBLIC_URL to list the mounted servers",
+                "servers prefix is used twice: \"/email\"",
+                "servers /email and /email share a database, give each its own database uri",
+                "servers prefix must start, and not end, with '/': \"password/\"",
+                "DATABASE_URL for server password/ must be a postgres:// url",
+            ]
+        );
+    }
+
+    #[test]
+    fn registry_options() {
+        let mut settings = complete();
+        settings.registry.url = Some("http://registry.test".into());
+        settings.registry.heartbeat = Some(60);
+        settings.identity.secret_key = Some("not a key".into());
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "IDENTITY_SECRET_KEY must be a base64 encoded 32 byte key",
+                "Must supply PUBLIC_URL to announce to a registry",
+                "REGISTRY_HEARTBEAT must be at least 1 second, and shorter than REGISTRY_TTL",
+            ]
+        );
+    }
+
+    #[cfg(feature = "biometric")]
+    #[test]
+    fn biometric_options() {
+        let settings = complete().validate().unwrap();
+        assert_eq!(settings.biometric.timeout, Duration::from_secs(5));
+        assert_eq!(settings.biometric.retries, 2);
+
+        let mut settings = complete();
+        settings.biometric.timeout = Some(0);
+        settings.biometric.retries = Some(0);
+        settings.biometric.webhook_key = Some(base64::encode([7u8; 16]));
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "BIOMETRIC_TIMEOUT must be at least 1 second",
+                "BIOMETRIC_WEBHOOK_KEY is not a base64 Ed25519 public key",
+            ]
+        );
+
+        let mut settings = complete();
+        settings.biometric.webhook_key = Some(Identity::generate().public_key());
+        assert!(settings.validate().unwrap().biometric.webhook_key.is_some());
+    }
+
+    #[test]
+    fn refresh_options() {
+        let mut settings = complete();
+        settings.refresh.peers = Some(vec!["peer.test".into()]);
+        settings.refresh.interval = Some(0);
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                "REFRESH_PEERS entry is not an http(s) url: peer.test",
+                "REFRESH_INTERVAL must be at least 1 second",
+                "Must supply REFRESH_PEERS and REFRESH_TRUSTED_KEYS to refresh shares",
+            ]
+        );
+    }
+
+    #[test]
+    fn audit_options() {
+        let mut settings = complete();
+        settings.audit.trusted_proxies = Some(vec!["10.0.0.1".into(), "proxy.test".into()]);
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["AUDIT_TRUSTED_PROXIES entry is not an ip address: proxy.test"]
+        );
+
+        let mut settings = complete();
+        settings.audit.trusted_proxies = Some(vec!["10.0.0.1".into()]);
+        assert_eq!(
+            settings.validate().unwrap().audit.trusted_proxies,
+            vec![IpAddr::from([10, 0, 0, 1])]
+        );
+    }
+
+    #[test]
+    fn persistent_identity() {
+        let settings = complete().validate().unwrap();
+        assert_eq!(
+            settings.identity.public_key(),
+            complete().validate().unwrap().identity.public_key()
+        );
+
+        let mut settings = complete();
+        settings.identity.secret_key = None;
+
+        let ConfigError(errors) = settings.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["Must supply IDENTITY_SECRET_KEY, create one with `simple-syrup generate-identity`"]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<RawSettings>("servers_config = []").is_err());
+    }
+}
@@ -2,7 +2,19 @@ use sqlx::postgres::{PgPool, PgPoolOptions};
 
 use crate::config::DBOptions;
 
+/// Default upper bound on connections held by a pool created with [`new_pool`].
+pub const MAX_CONNECTIONS: u32 = 5;
+
 pub async fn new_pool(db_options: &DBOptions) -> sqlx::Result<PgPool> {
-    let DBOptions { uri } = db_options;
-    PgPoolOptions::new().max_connections(5).connect(uri).await
+    let DBOptions {
+        uri,
+        max_connections,
+    } = db_options;
+    let options = PgPoolOptions::new().max_connections(*max_connections);
+    // Idle connections keep their pool alive, so pools that tests drop without closing would hold
+    // on to them until the process exits, and exhaust the database's connections.
+    #[cfg(test)]
+    let options = options.after_release(|_| false);
+
+    options.connect(uri).await
 }
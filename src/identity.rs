@@ -0,0 +1,180 @@
+use std::convert::TryFrom;
+
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ServerType;
+
+/// Header carrying the signature of a response body.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+/// Header carrying the public key a response body was signed with.
+pub const PUBLIC_KEY_HEADER: &str = "x-public-key";
+
+/// The Ed25519 keypair a server signs its messages with.
+///
+/// Keys and signatures are exchanged as base64.
+#[derive(Clone)]
+pub struct Identity {
+    secret: [u8; 32],
+}
+
+impl Identity {
+    /// A new random identity, which only lasts as long as the process unless its
+    /// [`Identity::to_base64`] is configured.
+    pub fn generate() -> Self {
+        Self {
+            secret: rand::random(),
+        }
+    }
+
+    /// Loads the base64 encoded 32 byte secret key, such as `IDENTITY_SECRET_KEY`.
+    pub fn from_base64(secret: &str) -> Option<Self> {
+        let bytes = base64::decode(secret.trim()).ok()?;
+
+        Some(Self {
+            secret: <[u8; 32]>::try_from(bytes.as_slice()).ok()?,
+        })
+    }
+
+    /// The base64 encoded secret key, as loaded by [`Identity::from_base64`].
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.secret)
+    }
+
+    fn keypair(&self) -> Keypair {
+        let secret = SecretKey::from_bytes(&self.secret).expect("Secret keys are 32 bytes");
+        let public = PublicKey::from(&secret);
+
+        Keypair { secret, public }
+    }
+
+    pub fn public_key(&self) -> String {
+        base64::encode(self.keypair().public.as_bytes())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> String {
+        base64::encode(self.keypair().sign(message).to_bytes())
+    }
+
+    /// A JSON response, signed over the exact bytes of its body.
+    ///
+    /// The signature and public key are sent in the `x-signature` and `x-public-key` headers, so
+    /// the body stays what clients already expect.
+    pub fn signed_json<T: Serialize>(&self, status: StatusCode, body: &T) -> HttpResponse {
+        let body = serde_json::to_vec(body).expect("Could not serialize response");
+
+        HttpResponseBuilder::new(status)
+            .content_type("application/json")
+            .insert_header((SIGNATURE_HEADER, self.sign(&body)))
+            .insert_header((PUBLIC_KEY_HEADER, self.public_key()))
+            .body(body)
+    }
+}
+
+/// The public identity of a server, reported by `/identity`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityInfo {
+    pub server_ty: ServerType,
+    pub public_key: String,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+/// Checks a base64 `signature` of `message` against a base64 `public_key`.
+pub fn verify(public_key: &str, message: &[u8], signature: &str) -> bool {
+    let public_key = base64::decode(public_key)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok());
+    let signature = base64::decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok());
+
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => public_key.verify(message, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let identity = Identity::generate();
+        let signature = identity.sign(b"secret component");
+
+        assert!(verify(
+            &identity.public_key(),
+            b"secret component",
+            &signature
+        ));
+        assert!(!verify(
+            &identity.public_key(),
+            b"other component",
+            &signature
+        ));
+        assert!(!verify(
+            &Identity::generate().public_key(),
+            b"secret component",
+            &signature
+        ));
+        assert!(!verify("not a key", b"secret component", "not a signature"));
+    }
+
+    #[actix_web::test]
+    async fn served_identity() {
+        use actix_web::test;
+
+        use crate::config::{Config, ServerType};
+
+        let config = Config::test(ServerType::Email).await;
+        let public_key = config.identity.public_key();
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = test::TestRequest::get().uri("/identity").to_request();
+        let identity: IdentityInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(identity.public_key, public_key);
+
+        // The root listing is signed over its exact body.
+        let req = test::TestRequest::get().uri("/").to_request();
+        let res = test::call_service(&app, req).await;
+
+        let header = |name: &str| {
+            res.headers()
+                .get(name)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let signature = header(SIGNATURE_HEADER);
+        assert_eq!(header(PUBLIC_KEY_HEADER), public_key);
+
+        let body = test::read_body(res).await;
+        assert!(verify(&public_key, &body, &signature));
+        assert!(!verify(&public_key, b"[]", &signature));
+    }
+
+    #[test]
+    fn secret_key_encoding() {
+        let secret = base64::encode([7u8; 32]);
+        let identity = Identity::from_base64(&secret).unwrap();
+        assert_eq!(identity.to_base64(), secret);
+
+        assert_eq!(
+            identity.public_key(),
+            Identity::from_base64(&secret).unwrap().public_key()
+        );
+        assert!(Identity::from_base64(&base64::encode([7u8; 16])).is_none());
+        assert!(Identity::from_base64("not base64!").is_none());
+    }
+}
@@ -1,56 +1,99 @@
-use actix_cors::Cors;
-use actix_web::{middleware, App, HttpServer};
+use actix_web::{web, App, HttpServer, Scope};
 use actix_web_httpauth::middleware::HttpAuthentication;
-use config::{Config, Server};
-use env_logger::Env;
+use clap::Parser;
+use config::{Cli, Command, Config, Server, Settings};
 
 mod api;
+mod audit;
 mod auth;
 mod config;
 mod db;
+mod identity;
+mod metrics;
+mod refresh;
+mod registry;
+mod telemetry;
 
 macro_rules! build_app_ty {
-    ($app:ident, $mod:ident, $pool:ident) => {
-        $app.app_data(crate::api::$mod::server_builder($pool.clone()))
+    ($app:ident, $mod:ident, $server:ident) => {
+        $app.app_data(crate::api::$mod::server_builder(&$server))
             .service(crate::api::index)
-            .service(crate::api::$mod::server_ty)
-            .service(crate::api::$mod::register)
-            .service(crate::api::$mod::register_check)
-            .service(crate::api::$mod::auth)
-            .service(crate::api::$mod::auth_check)
-            .service(crate::api::$mod::status_check)
+            .service(crate::api::health::healthz)
+            .configure(crate::api::$mod::configure)
     };
 }
-pub(crate) use build_app_ty;
+
+/// The routes of `server`, under its prefix.
+pub(crate) fn mount(server: &Server) -> Scope {
+    let scope = web::scope(&server.prefix).app_data(server.server_ty);
+
+    match server.server_ty {
+        #[cfg(feature = "email")]
+        config::ServerType::Email => build_app_ty!(scope, email, server),
+        #[cfg(feature = "qa")]
+        config::ServerType::QA => build_app_ty!(scope, qa, server),
+        #[cfg(feature = "password")]
+        config::ServerType::Password => build_app_ty!(scope, password, server),
+        #[cfg(feature = "biometric")]
+        config::ServerType::Biometric => build_app_ty!(scope, biometric, server),
+        #[allow(unreachable_patterns)]
+        _ => scope,
+    }
+}
 
 async fn root_server(root: Config) -> std::io::Result<()> {
+    let mounts = root.mounts();
+
     let Config {
         host,
         port,
-        server,
+        public_url,
+        servers,
         active_servers,
+        cors,
+        identity,
+        registry,
+        refresh,
     } = root;
 
-    println!("[root]: {}:{}", host, port);
+    tracing::info!("[root]: {}:{}", host, port);
+
+    for server in &servers {
+        tracing::info!(
+            "[mount]: {:?} at {}/, identity {}",
+            server.server_ty,
+            server.prefix,
+            server.identity.public_key()
+        );
 
-    sqlx::migrate!()
-        .run(&server.database)
-        .await
-        .expect("Could not perform db migrations");
+        sqlx::migrate!()
+            .run(&server.database)
+            .await
+            .expect("Could not perform db migrations");
+    }
 
     let _host = host.clone();
 
-    let Server {
-        database,
-        server_ty,
-        ..
-    } = server;
+    tracing::info!("[identity]: {}", identity.public_key());
+
+    if let (Some(_), Some(public_url)) = (&registry.url, &public_url) {
+        actix_web::rt::spawn(registry::heartbeat(
+            registry.clone(),
+            public_url.clone(),
+            servers.clone(),
+        ));
+    }
+
+    if refresh.interval.is_some() {
+        actix_web::rt::spawn(refresh::run(refresh.clone(), servers.clone()));
+    }
+
+    // Shared by every worker, so announcements received by one are listed by all.
+    let registry = web::Data::new(registry::Registry::new(&registry));
+    let refresh = web::Data::new(refresh);
 
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_header()
-            .allow_any_origin()
-            .allow_any_method();
+        let cors = cors.middleware();
 
         let _auth_middleware = HttpAuthentication::bearer(auth::validator);
 
@@ -58,22 +101,20 @@ async fn root_server(root: Config) -> std::io::Result<()> {
             // Reset this when we are ready to implement JWT requirements
             // .wrap(auth_middleware)
             .wrap(cors)
-            .wrap(middleware::Logger::default())
+            .wrap(telemetry::RequestTracing)
+            .wrap(metrics::RequestMetrics)
             .app_data(active_servers.clone())
-            .service(config::root);
-
-        match server_ty {
-            #[cfg(feature = "email")]
-            config::ServerType::Email => build_app_ty!(app, email, database),
-            #[cfg(feature = "qa")]
-            config::ServerType::QA => build_app_ty!(app, qa, database),
-            #[cfg(feature = "password")]
-            config::ServerType::Password => build_app_ty!(app, password, database),
-            #[cfg(feature = "biometric")]
-            config::ServerType::Biometric => build_app_ty!(app, biometric, database),
-            #[allow(unreachable_patterns)]
-            _ => app,
-        }
+            .app_data(mounts.clone())
+            .app_data(registry.clone())
+            .app_data(refresh.clone())
+            .app_data(identity.clone())
+            .service(config::root)
+            .service(registry::announce)
+            .service(api::health::healthz);
+
+        servers
+            .iter()
+            .fold(app, |app, server| app.service(mount(server)))
     })
     .bind(format!("0.0.0.0:{}", port))?
     .run()
@@ -82,14 +123,73 @@ async fn root_server(root: Config) -> std::io::Result<()> {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
+    telemetry::init();
     if cfg!(debug_assertions) {
-        dotenv::dotenv().expect("Cannot initiate server without env variables.");
+        // Configuration may also come from a file, so a .env is not required.
+        dotenv::dotenv().ok();
     }
 
-    let config = config::Config::new().await;
+    let cli = Cli::parse();
+
+    if let Some(Command::GenerateIdentity) = cli.command {
+        let identity = identity::Identity::generate();
+        println!("IDENTITY_SECRET_KEY={}", identity.to_base64());
+        println!("[identity]: {}", identity.public_key());
+        return Ok(());
+    }
+
+    let settings = Settings::load(&cli).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+
+    match &cli.command {
+        Some(Command::VerifyAuditLog { heads }) => {
+            return match audit::verify_offline(&settings, heads).await {
+                Ok(verified) => {
+                    for log in verified {
+                        println!(
+                            "[audit]: {:?} server: {} entries verified, head {}",
+                            log.server_ty, log.count, log.head
+                        );
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(std::io::Error::other(e)),
+            };
+        }
+        Some(Command::DeleteUser { email }) => {
+            let config = config::Config::new(settings).await;
+
+            for server in &config.servers {
+                let deleted = api::base::BaseAuthenticator::new(server)
+                    .delete(server.server_ty, email)
+                    .await
+                    .map_err(std::io::Error::other)?;
+
+                println!(
+                    "[delete]: {:?} server: {}",
+                    server.server_ty,
+                    if deleted { "deleted" } else { "not registered" }
+                );
+            }
+            return Ok(());
+        }
+        Some(Command::GenerateIdentity) | None => {}
+    }
+
+    if cli.check_config {
+        println!("[config]: OK");
+        return Ok(());
+    }
+
+    let config = config::Config::new(settings).await;
+
+    let res = root_server(config).await;
+
+    telemetry::shutdown();
 
-    root_server(config).await
+    res
 }
 
 #[cfg(test)]
@@ -0,0 +1,267 @@
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, Histogram, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use sqlx::PgPool;
+
+use crate::api::VerificationStatus;
+use crate::config::ServerType;
+
+lazy_static! {
+    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "simple_syrup_requests_total",
+        "HTTP requests handled, by server type, endpoint and response status",
+        &["server_ty", "endpoint", "status"]
+    )
+    .unwrap();
+    static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "simple_syrup_request_duration_seconds",
+        "HTTP request latency, by server type and endpoint",
+        &["server_ty", "endpoint"]
+    )
+    .unwrap();
+    static ref EVENTS: IntCounterVec = register_int_counter_vec!(
+        "simple_syrup_events_total",
+        "Authenticator flow events (registrations, verifications), by server type",
+        &["server_ty", "event"]
+    )
+    .unwrap();
+    static ref TRANSITIONS: IntCounterVec = register_int_counter_vec!(
+        "simple_syrup_status_transitions_total",
+        "Transitions of a user's VerificationStatus, by server type and new status",
+        &["server_ty", "status"]
+    )
+    .unwrap();
+    static ref LOCKOUTS: IntCounterVec = register_int_counter_vec!(
+        "simple_syrup_lockouts_total",
+        "Users locked out after repeated failed verifications, by server type",
+        &["server_ty"]
+    )
+    .unwrap();
+    static ref OTP_SENDS: IntCounterVec = register_int_counter_vec!(
+        "simple_syrup_otp_sends_total",
+        "OTPs sent through the email backend, by result",
+        &["result"]
+    )
+    .unwrap();
+    static ref OTP_SEND_DURATION: Histogram = register_histogram!(
+        "simple_syrup_otp_send_duration_seconds",
+        "Latency of sending an OTP through the email backend"
+    )
+    .unwrap();
+    static ref DB_POOL: IntGaugeVec = register_int_gauge_vec!(
+        "simple_syrup_db_pool_connections",
+        "Connections of the database pool of each server, by state (idle, in_use, max)",
+        &["server_ty", "state"]
+    )
+    .unwrap();
+}
+
+fn label(server_ty: ServerType) -> String {
+    format!("{:?}", server_ty)
+}
+
+/// Records a step of an authenticator flow, such as `register_verified` or `auth_failed`.
+pub fn event(server_ty: ServerType, event: &str) {
+    EVENTS.with_label_values(&[&label(server_ty), event]).inc();
+}
+
+/// Records that a user's row moved into `status`.
+pub fn transition(server_ty: ServerType, status: &VerificationStatus) {
+    TRANSITIONS
+        .with_label_values(&[&label(server_ty), &format!("{:?}", status)])
+        .inc();
+}
+
+/// Records that failed verifications locked a user out.
+#[cfg(feature = "qa")]
+pub fn locked_out(server_ty: ServerType) {
+    LOCKOUTS.with_label_values(&[&label(server_ty)]).inc();
+}
+
+/// Records a single OTP send, and how long it took.
+pub fn otp_sent(elapsed: Duration, success: bool) {
+    OTP_SEND_DURATION.observe(elapsed.as_secs_f64());
+    OTP_SENDS
+        .with_label_values(&[if success { "success" } else { "failure" }])
+        .inc();
+}
+
+/// Renders every registered metric in the Prometheus text format.
+///
+/// Pool gauges are sampled at scrape time, and labeled by the server owning the pool.
+pub fn render(server_ty: ServerType, pool: &PgPool, max_connections: u32) -> HttpResponse {
+    let size = pool.size() as i64;
+    let idle = pool.num_idle() as i64;
+    let server_ty = label(server_ty);
+
+    DB_POOL.with_label_values(&[&server_ty, "idle"]).set(idle);
+    DB_POOL
+        .with_label_values(&[&server_ty, "in_use"])
+        .set(size - idle);
+    DB_POOL
+        .with_label_values(&[&server_ty, "max"])
+        .set(max_connections as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(_) => HttpResponseBuilder::new(StatusCode::OK)
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(e) => HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR).json(e.to_string()),
+    }
+}
+
+/// Middleware counting requests and their latency per endpoint.
+///
+/// Endpoints are labeled by their route pattern, so `/register/verify` is a single series, and by
+/// the `ServerType` of the server that handled them. Requests outside of any server, such as `/`
+/// when several are mounted, are labeled `root`.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+
+            // Only known once routing reached the scope of a server.
+            let server_ty = res
+                .request()
+                .app_data::<ServerType>()
+                .map(|server_ty| label(*server_ty))
+                .unwrap_or_else(|| "root".into());
+            let endpoint = res
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".into());
+
+            REQUEST_DURATION
+                .with_label_values(&[&server_ty, &endpoint])
+                .observe(start.elapsed().as_secs_f64());
+            REQUESTS
+                .with_label_values(&[&server_ty, &endpoint, res.status().as_str()])
+                .inc();
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use crate::api::{email::EmailAuthenticator, Fixture};
+    use crate::config::{Config, ServerType};
+
+    #[actix_web::test]
+    async fn metrics() {
+        let config = Config::test(ServerType::Email).await;
+        let otp = config
+            .register(
+                &crate::test::share(),
+                &EmailAuthenticator::registration_data(),
+            )
+            .await;
+        config.verify_register(&otp).await;
+
+        let app = crate::test::build_test_app!(config).await;
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let label = "server_ty=\"Email\"";
+        assert!(body
+            .lines()
+            .any(|line| line.starts_with("simple_syrup_requests_total")
+                && line.contains(label)
+                && line.contains("endpoint=\"/register/verify\"")));
+        assert!(body
+            .lines()
+            .any(|line| line.starts_with("simple_syrup_events_total")
+                && line.contains(label)
+                && line.contains("event=\"register_verified\"")));
+        assert!(
+            body.contains("simple_syrup_db_pool_connections{server_ty=\"Email\",state=\"max\"}")
+        );
+    }
+
+    #[actix_web::test]
+    async fn lockouts() {
+        use crate::api::qa::{QAAuthenticator, MAX_FAILURES};
+
+        let config = Config::test(ServerType::QA).await;
+        let otp = config
+            .register(&crate::test::share(), &QAAuthenticator::registration_data())
+            .await;
+        config.verify_register(&otp).await;
+        config.auth().await;
+
+        let locked_out = || super::LOCKOUTS.with_label_values(&["QA"]).get();
+        let before = locked_out();
+
+        let app = crate::test::build_test_app!(config).await;
+        for _ in 0..MAX_FAILURES {
+            let req = test::TestRequest::post()
+                .uri("/authenticate/verify")
+                .set_json(serde_json::json!({
+                    "email": "benjcape@gmail.com",
+                    "data": QAAuthenticator::incorrect_data(),
+                }))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        // Other tests may lock out users at the same time.
+        assert!(locked_out() > before);
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("simple_syrup_lockouts_total{server_ty=\"QA\"}"));
+    }
+}
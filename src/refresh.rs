@@ -0,0 +1,468 @@
+//! Proactive refresh of the shares held by the authenticator servers.
+//!
+//! Every `refresh.interval`, each server deals a refresh round: for every user it holds a share
+//! of, it asks every peer for the index of its share of that user, splits zero at those indices
+//! with [`sharing::refresh::zero_shares`], and sends each peer its share of zero. Peers stage the
+//! shares of zero they receive, and once every trusted key dealt its own for the next epoch, add
+//! them all to the user's share in a single transaction. The secret is never reconstructed, and
+//! shares from before the refresh no longer combine with shares after it. Applying a refresh is
+//! recorded in the audit log, in the same transaction.
+//!
+//! A peer that missed a share of zero stays at the previous epoch while the others move on. Dealers
+//! then deal the shares of zero of the latest epoch again to the peers behind, from the same seed,
+//! so the peer catches up on the next round.
+//!
+//! Messages between servers are signed with their [`Identity`], and only accepted from the keys
+//! in `refresh.trusted_keys`. Shares of zero for any epoch but the next are rejected as stale.
+//! Verifiable shares of zero must match the commitments dealt with them, which must commit to
+//! zero, so a dealer cannot change the secret or corrupt the share.
+
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use hyper::StatusCode;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sharing::{Commitments, Share};
+
+use crate::api::base::BaseAuthenticator;
+use crate::audit::{self, AuditEvent, Origin};
+use crate::config::{RefreshOptions, Server, ServerType};
+use crate::identity::{self, Identity};
+
+/// A message from one server to another, signed by the sender.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Signed<T> {
+    pub message: T,
+    pub public_key: String,
+    /// Signature of the JSON encoded `message`, by `public_key`.
+    pub signature: String,
+}
+
+impl<T: Serialize> Signed<T> {
+    pub fn new(message: T, identity: &Identity) -> Self {
+        let signature = identity.sign(&serde_json::to_vec(&message).expect("Could not serialize"));
+
+        Self {
+            message,
+            public_key: identity.public_key(),
+            signature,
+        }
+    }
+
+    fn verify(&self, options: &RefreshOptions) -> Result<(), RefreshError> {
+        if options.trusted_keys.is_empty() {
+            return Err(RefreshError::Disabled);
+        }
+        if !options.trusted_keys.contains(&self.public_key) {
+            return Err(RefreshError::UntrustedKey);
+        }
+
+        let payload = serde_json::to_vec(&self.message).expect("Could not serialize");
+        if !identity::verify(&self.public_key, &payload, &self.signature) {
+            return Err(RefreshError::BadSignature);
+        }
+
+        Ok(())
+    }
+}
+
+/// Asks a peer about its share of a user, identified by the hash of their email.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShareQuery {
+    pub email: String,
+}
+
+/// The public part of a share, which a dealer needs to deal shares of zero.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ShareInfo {
+    pub index: u8,
+    pub threshold: u8,
+    pub epoch: u32,
+}
+
+/// A share of zero dealt to a peer for the next epoch of a user's share.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshUpdate {
+    pub email: String,
+    pub epoch: u32,
+    pub delta: String,
+    /// Commitments to the dealer's zero polynomials, for verifiable shares.
+    pub commitments: Option<String>,
+}
+
+/// Whether a received share of zero completed the refresh of a share.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refreshed {
+    /// Waiting for the shares of zero of other peers.
+    Pending,
+    Applied,
+}
+
+impl From<Refreshed> for HttpResponse {
+    fn from(refreshed: Refreshed) -> Self {
+        match refreshed {
+            Refreshed::Pending => HttpResponseBuilder::new(StatusCode::ACCEPTED).finish(),
+            Refreshed::Applied => HttpResponseBuilder::new(StatusCode::OK).finish(),
+        }
+    }
+}
+
+/// Why a refresh message was rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshError {
+    /// This process does not trust any key, so it takes no part in refreshes.
+    Disabled,
+    UntrustedKey,
+    BadSignature,
+    UnknownUser,
+    /// The share of zero is not for the epoch after the current one.
+    Stale,
+    Invalid(sharing::Error),
+    Database(String),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "this server does not take part in refreshes"),
+            Self::UntrustedKey => write!(f, "the sending key is not trusted"),
+            Self::BadSignature => write!(f, "the message signature is not valid"),
+            Self::UnknownUser => write!(f, "no share is held for this user"),
+            Self::Stale => write!(f, "the share of zero is not for the next epoch"),
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<sharing::Error> for RefreshError {
+    fn from(e: sharing::Error) -> Self {
+        Self::Invalid(e)
+    }
+}
+
+impl From<sqlx::Error> for RefreshError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e.to_string())
+    }
+}
+
+impl From<RefreshError> for HttpResponse {
+    fn from(e: RefreshError) -> Self {
+        let status = match e {
+            RefreshError::Disabled | RefreshError::UnknownUser => StatusCode::NOT_FOUND,
+            RefreshError::UntrustedKey | RefreshError::BadSignature => StatusCode::FORBIDDEN,
+            RefreshError::Stale => StatusCode::CONFLICT,
+            RefreshError::Invalid(_) => StatusCode::BAD_REQUEST,
+            RefreshError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpResponseBuilder::new(status).json(e.to_string())
+    }
+}
+
+/// Answers a dealer asking about the share of a user.
+pub async fn share_info(
+    base: &BaseAuthenticator,
+    options: &RefreshOptions,
+    query: Signed<ShareQuery>,
+) -> Result<ShareInfo, RefreshError> {
+    query.verify(options)?;
+
+    let rec = sqlx::query!(
+        "SELECT secret_component FROM authenticated WHERE email=$1",
+        query.message.email
+    )
+    .fetch_optional(&base.pool)
+    .await?
+    .ok_or(RefreshError::UnknownUser)?;
+
+    let share = Share::decode(&rec.secret_component.unwrap_or_default())?;
+
+    Ok(ShareInfo {
+        index: share.index,
+        threshold: share.threshold,
+        epoch: share.epoch,
+    })
+}
+
+/// Stages a share of zero, and refreshes the user's share once every trusted key dealt one.
+#[tracing::instrument(name = "refresh.receive", skip_all, fields(dealer = %update.public_key, epoch = update.message.epoch))]
+pub async fn receive(
+    base: &BaseAuthenticator,
+    server_ty: ServerType,
+    options: &RefreshOptions,
+    update: Signed<RefreshUpdate>,
+) -> Result<Refreshed, RefreshError> {
+    update.verify(options)?;
+
+    let Signed {
+        message: update,
+        public_key: dealer,
+        ..
+    } = update;
+
+    let mut tx = base.pool.begin().await?;
+
+    // Locks the share until the transaction ends, so concurrent deliveries apply it once.
+    let current = sqlx::query!(
+        "SELECT secret_component, commitments, epoch FROM authenticated WHERE email=$1 FOR UPDATE",
+        update.email
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(RefreshError::UnknownUser)?;
+
+    if update.epoch as i64 != current.epoch + 1 {
+        return Err(RefreshError::Stale);
+    }
+
+    let share = Share::decode(&current.secret_component.unwrap_or_default())?;
+    let delta = Share::decode(&update.delta)?;
+    share.refresh(std::slice::from_ref(&delta))?;
+
+    // A share of zero must match the commitments it was dealt with, which must commit to zero.
+    let commitments = current
+        .commitments
+        .as_deref()
+        .map(Commitments::decode)
+        .transpose()?;
+    match (&commitments, &update.commitments) {
+        (Some(commitments), Some(dealt)) => {
+            let dealt = Commitments::decode(dealt)?;
+            dealt.verify(&delta)?;
+            commitments.refresh(&[dealt])?;
+        }
+        (Some(_), None) => return Err(sharing::Error::MalformedCommitments.into()),
+        (None, Some(_)) => return Err(sharing::Error::NotVerifiable.into()),
+        (None, None) => {}
+    }
+
+    sqlx::query!(
+        "INSERT INTO refresh_updates (email, epoch, dealer, delta, commitments) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+        update.email,
+        update.epoch as i64,
+        dealer,
+        update.delta,
+        update.commitments,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let staged: Vec<_> = sqlx::query!(
+        "SELECT dealer, delta, commitments FROM refresh_updates WHERE email=$1 AND epoch=$2",
+        update.email,
+        update.epoch as i64,
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .filter(|rec| options.trusted_keys.contains(&rec.dealer))
+    .collect();
+
+    if staged.len() < options.trusted_keys.len() {
+        tx.commit().await?;
+        return Ok(Refreshed::Pending);
+    }
+
+    let deltas = staged
+        .iter()
+        .map(|rec| Share::decode(&rec.delta))
+        .collect::<Result<Vec<_>, _>>()?;
+    let refreshed = share.refresh(&deltas)?;
+
+    let commitments = match commitments {
+        Some(commitments) => {
+            let dealt = staged
+                .iter()
+                .map(|rec| Commitments::decode(rec.commitments.as_deref().unwrap_or_default()))
+                .collect::<Result<Vec<_>, _>>()?;
+            for (delta, dealt) in deltas.iter().zip(&dealt) {
+                dealt.verify(delta)?;
+            }
+
+            let commitments = commitments.refresh(&dealt)?;
+            commitments.verify(&refreshed)?;
+            Some(commitments.encode())
+        }
+        None => None,
+    };
+
+    sqlx::query!(
+        "UPDATE authenticated SET secret_component=$2, commitments=$3, epoch=$4 WHERE email=$1",
+        update.email,
+        refreshed.encode(),
+        commitments,
+        refreshed.epoch as i64,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    audit::append_to(
+        &mut tx,
+        server_ty,
+        AuditEvent::Refreshed,
+        update.email.clone(),
+        Origin::default(),
+    )
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM refresh_updates WHERE email=$1 AND epoch<=$2",
+        update.email,
+        refreshed.epoch as i64,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Refreshed::Applied)
+}
+
+/// Deals a refresh round from this process every `options.interval`, until the process exits.
+///
+/// Failed rounds are logged and retried on the next interval.
+pub async fn run(options: RefreshOptions, servers: Vec<Server>) {
+    let period = match options.interval {
+        Some(period) => period,
+        None => return,
+    };
+
+    let client = reqwest::Client::new();
+    let mut interval = actix_web::rt::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        round(&client, &options, &servers).await;
+    }
+}
+
+/// Deals shares of zero for every user of every server whose key the peers trust.
+pub async fn round(client: &reqwest::Client, options: &RefreshOptions, servers: &[Server]) {
+    for server in servers
+        .iter()
+        .filter(|server| options.trusted_keys.contains(&server.identity.public_key()))
+    {
+        let users = sqlx::query!("SELECT email, secret_component FROM authenticated")
+            .fetch_all(&server.database)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Could not list users of {:?}: {}", server.server_ty, e);
+                vec![]
+            });
+
+        for user in users {
+            let res = match Share::decode(&user.secret_component.unwrap_or_default()) {
+                Ok(share) => deal(client, options, &server.identity, &user.email, &share).await,
+                Err(e) => Err(e.to_string()),
+            };
+
+            if let Err(e) = res {
+                tracing::warn!("Could not refresh a share of {:?}: {}", server.server_ty, e);
+            }
+        }
+    }
+}
+
+/// Deals shares of zero for the next epoch of `share` to every peer, or, when some peers are
+/// behind, deals the shares of zero of the latest epoch again to them.
+async fn deal(
+    client: &reqwest::Client,
+    options: &RefreshOptions,
+    identity: &Identity,
+    email: &str,
+    share: &Share,
+) -> Result<(), String> {
+    let query = Signed::new(
+        ShareQuery {
+            email: email.into(),
+        },
+        identity,
+    );
+
+    let mut peers = vec![];
+    for peer in &options.peers {
+        let info: ShareInfo = client
+            .post(format!("{}/refresh/share", peer.trim_end_matches('/')))
+            .json(&query)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if info.threshold != share.threshold {
+            return Err(format!("{} is not at the threshold of this share", peer));
+        }
+        peers.push((peer, info));
+    }
+
+    let epochs = || {
+        peers
+            .iter()
+            .map(|(_, info)| info.epoch)
+            .chain([share.epoch])
+    };
+    let latest = epochs().max().unwrap_or(share.epoch);
+    if epochs().any(|epoch| epoch + 1 < latest) {
+        return Err("peers are more than one epoch apart".into());
+    }
+
+    // The shares of zero of the latest epoch are dealt from the share as it was before it.
+    let (epoch, like) = if epochs().all(|epoch| epoch == latest) {
+        (latest + 1, share.clone())
+    } else {
+        (
+            latest,
+            Share {
+                epoch: latest - 1,
+                ..share.clone()
+            },
+        )
+    };
+    let indices: Vec<u8> = peers.iter().map(|(_, info)| info.index).collect();
+
+    // Dealing again for the same epoch, after a peer failed, deals the same shares of zero.
+    let mut rng = StdRng::from_seed(seed(identity, email, epoch));
+    let (zeros, commitments) = sharing::refresh::zero_shares_with_rng(&like, &indices, &mut rng)
+        .map_err(|e| e.to_string())?;
+    let commitments = commitments.as_ref().map(Commitments::encode);
+
+    for ((peer, info), zero) in peers.iter().zip(zeros) {
+        // Peers at the epoch already added their share of zero.
+        if info.epoch >= epoch {
+            continue;
+        }
+
+        let update = Signed::new(
+            RefreshUpdate {
+                email: email.into(),
+                epoch,
+                delta: zero.encode(),
+                commitments: commitments.clone(),
+            },
+            identity,
+        );
+
+        client
+            .post(format!("{}/refresh", peer.trim_end_matches('/')))
+            .json(&update)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Randomness of the zero polynomials dealt by `identity` for a user and epoch.
+fn seed(identity: &Identity, email: &str, epoch: u32) -> [u8; 32] {
+    let signature = identity.sign(format!("refresh seed {} {}", email, epoch).as_bytes());
+
+    Sha256::digest(signature.as_bytes()).into()
+}
@@ -0,0 +1,367 @@
+//! Service discovery.
+//!
+//! Authenticator servers announce themselves to a registry node every heartbeat, signing each
+//! announcement with their [`Identity`]. The registry only accepts announcements signed by a
+//! trusted key, and forgets a server once it has not heard from it for the TTL. A url belongs to the
+//! key that first announced it, so a trusted key cannot take over the entry of another server.
+//! `GET /` lists the live servers, and falls back to the static `ACTIVE_SERVERS` when there are
+//! none.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_web::{post, web, HttpResponse, HttpResponseBuilder};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::api::health::Readiness;
+use crate::config::{RegistryOptions, Server, ServerPublicData, ServerType};
+use crate::identity::{self, Identity};
+
+/// What a server tells the registry about itself.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Announcement {
+    pub url: String,
+    pub server_ty: ServerType,
+    pub public_key: String,
+    /// Whether the server passed its readiness checks.
+    pub ready: bool,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedAnnouncement {
+    pub announcement: Announcement,
+    /// Signature of the JSON encoded `announcement`, by its `public_key`.
+    pub signature: String,
+}
+
+impl Announcement {
+    pub fn sign(self, identity: &Identity) -> SignedAnnouncement {
+        let signature = identity.sign(&self.payload());
+
+        SignedAnnouncement {
+            announcement: self,
+            signature,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Could not serialize announcement")
+    }
+}
+
+/// Why an announcement was rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegistryError {
+    /// This process does not trust any key, so it is not a registry node.
+    Disabled,
+    UntrustedKey,
+    BadSignature,
+    /// The announcement is older than the TTL, or than one already received.
+    Stale,
+    /// The announcement is dated further in the future than clocks may drift apart.
+    FutureTimestamp,
+    /// The url was first announced by another key.
+    UrlTaken,
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "this server is not a registry"),
+            Self::UntrustedKey => write!(f, "the announcing key is not trusted"),
+            Self::BadSignature => write!(f, "the announcement signature is not valid"),
+            Self::Stale => write!(f, "the announcement is stale"),
+            Self::FutureTimestamp => write!(f, "the announcement is dated in the future"),
+            Self::UrlTaken => write!(f, "the url is announced by another key"),
+        }
+    }
+}
+
+impl From<RegistryError> for HttpResponse {
+    fn from(e: RegistryError) -> Self {
+        let status = match e {
+            RegistryError::Disabled => StatusCode::NOT_FOUND,
+            RegistryError::UntrustedKey | RegistryError::BadSignature | RegistryError::UrlTaken => {
+                StatusCode::FORBIDDEN
+            }
+            RegistryError::Stale | RegistryError::FutureTimestamp => StatusCode::BAD_REQUEST,
+        };
+
+        HttpResponseBuilder::new(status).json(e.to_string())
+    }
+}
+
+/// How far ahead of the registry's clock an announcement may be dated.
+const MAX_CLOCK_SKEW: u64 = 30;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The live servers known to a registry node, by url.
+pub struct Registry {
+    trusted_keys: Vec<String>,
+    ttl: Duration,
+    servers: Mutex<HashMap<String, (Announcement, Instant)>>,
+    /// The key each url was first announced by, kept after the server expires.
+    owners: Mutex<HashMap<String, String>>,
+}
+
+impl Registry {
+    pub fn new(options: &RegistryOptions) -> Self {
+        Self {
+            trusted_keys: options.trusted_keys.clone(),
+            ttl: options.ttl,
+            servers: Mutex::new(HashMap::new()),
+            owners: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn announce(&self, signed: SignedAnnouncement) -> Result<(), RegistryError> {
+        let SignedAnnouncement {
+            announcement,
+            signature,
+        } = signed;
+
+        if self.trusted_keys.is_empty() {
+            return Err(RegistryError::Disabled);
+        }
+        if !self.trusted_keys.contains(&announcement.public_key) {
+            return Err(RegistryError::UntrustedKey);
+        }
+        if !identity::verify(
+            &announcement.public_key,
+            &announcement.payload(),
+            &signature,
+        ) {
+            return Err(RegistryError::BadSignature);
+        }
+        if now().saturating_sub(announcement.timestamp) > self.ttl.as_secs() {
+            return Err(RegistryError::Stale);
+        }
+        // A timestamp in the future would make every later heartbeat look stale.
+        if announcement.timestamp > now() + MAX_CLOCK_SKEW {
+            return Err(RegistryError::FutureTimestamp);
+        }
+
+        let mut servers = self.servers.lock().unwrap();
+
+        let mut owners = self.owners.lock().unwrap();
+        let owner = owners
+            .entry(announcement.url.clone())
+            .or_insert_with(|| announcement.public_key.clone());
+        if *owner != announcement.public_key {
+            return Err(RegistryError::UrlTaken);
+        }
+
+        // Replaying an older announcement must not revive a server, or undo a change in health.
+        if let Some((known, _)) = servers.get(&announcement.url) {
+            if known.timestamp > announcement.timestamp {
+                return Err(RegistryError::Stale);
+            }
+        }
+
+        servers.insert(announcement.url.clone(), (announcement, Instant::now()));
+
+        Ok(())
+    }
+
+    /// Servers heard from within the TTL, by url.
+    pub fn live(&self) -> Vec<ServerPublicData> {
+        self.live_at(Instant::now())
+    }
+
+    fn live_at(&self, now: Instant) -> Vec<ServerPublicData> {
+        let mut servers = self.servers.lock().unwrap();
+        servers.retain(|_, (_, seen)| now.saturating_duration_since(*seen) <= self.ttl);
+
+        let mut live: Vec<ServerPublicData> = servers
+            .values()
+            .map(|(announcement, _)| ServerPublicData {
+                url: announcement.url.clone(),
+                server_ty: announcement.server_ty,
+                public_key: Some(announcement.public_key.clone()),
+                ready: Some(announcement.ready),
+            })
+            .collect();
+        live.sort_by(|a, b| a.url.cmp(&b.url));
+
+        live
+    }
+}
+
+#[post("/registry/announce")]
+pub async fn announce(
+    registry: web::Data<Registry>,
+    request: web::Json<SignedAnnouncement>,
+) -> HttpResponse {
+    let url = request.announcement.url.clone();
+
+    match registry.announce(request.0) {
+        Ok(()) => HttpResponseBuilder::new(StatusCode::NO_CONTENT).finish(),
+        Err(e) => {
+            tracing::warn!("Rejected announcement from {}: {}", url, e);
+            e.into()
+        }
+    }
+}
+
+/// Announces every mounted server, under `public_url`, to the registry at `options.url`, every
+/// `options.heartbeat`.
+///
+/// Each announcement is signed by the identity of its server. Runs until the process exits.
+/// Failed announcements are logged and retried on the next beat.
+pub async fn heartbeat(options: RegistryOptions, public_url: String, servers: Vec<Server>) {
+    let url = match options.url {
+        Some(url) => url,
+        None => return,
+    };
+
+    let client = reqwest::Client::new();
+    let announce_url = format!("{}/registry/announce", url.trim_end_matches('/'));
+    let mut interval = actix_web::rt::time::interval(options.heartbeat);
+
+    loop {
+        interval.tick().await;
+
+        for server in &servers {
+            let announcement = Announcement {
+                url: format!("{}{}", public_url.trim_end_matches('/'), server.prefix),
+                server_ty: server.server_ty,
+                public_key: server.identity.public_key(),
+                ready: Readiness::of(server).await.is_ready(),
+                timestamp: now(),
+            };
+
+            let res = client
+                .post(&announce_url)
+                .timeout(options.heartbeat)
+                .json(&announcement.sign(&server.identity))
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+
+            if let Err(e) = res {
+                tracing::warn!(
+                    "Could not announce {:?} to {}: {}",
+                    server.server_ty,
+                    url,
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(identity: &Identity) -> Registry {
+        Registry::new(&RegistryOptions {
+            trusted_keys: vec![identity.public_key()],
+            ..Default::default()
+        })
+    }
+
+    fn announcement(identity: &Identity, timestamp: u64) -> Announcement {
+        Announcement {
+            url: "https://email.test".into(),
+            server_ty: ServerType::Email,
+            public_key: identity.public_key(),
+            ready: true,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn live_servers() {
+        let identity = Identity::generate();
+        let registry = registry(&identity);
+
+        registry
+            .announce(announcement(&identity, now()).sign(&identity))
+            .unwrap();
+
+        let live = registry.live();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].public_key, Some(identity.public_key()));
+
+        let expired = Instant::now() + registry.ttl + Duration::from_secs(1);
+        assert!(registry.live_at(expired).is_empty());
+    }
+
+    #[test]
+    fn rejected_announcements() {
+        let identity = Identity::generate();
+        let registry = registry(&identity);
+
+        let untrusted = Identity::generate();
+        assert_eq!(
+            registry.announce(announcement(&untrusted, now()).sign(&untrusted)),
+            Err(RegistryError::UntrustedKey)
+        );
+
+        let mut tampered = announcement(&identity, now()).sign(&identity);
+        tampered.announcement.url = "https://attacker.test".into();
+        assert_eq!(
+            registry.announce(tampered),
+            Err(RegistryError::BadSignature)
+        );
+
+        let old = now() - registry.ttl.as_secs() - 1;
+        assert_eq!(
+            registry.announce(announcement(&identity, old).sign(&identity)),
+            Err(RegistryError::Stale)
+        );
+
+        registry
+            .announce(announcement(&identity, now()).sign(&identity))
+            .unwrap();
+        assert_eq!(
+            registry.announce(announcement(&identity, now() - 1).sign(&identity)),
+            Err(RegistryError::Stale)
+        );
+
+        let early = now() + MAX_CLOCK_SKEW + 60;
+        assert_eq!(
+            registry.announce(announcement(&identity, early).sign(&identity)),
+            Err(RegistryError::FutureTimestamp)
+        );
+
+        assert!(Registry::new(&RegistryOptions::default())
+            .announce(announcement(&identity, now()).sign(&identity))
+            .is_err());
+    }
+    #[test]
+    fn urls_belong_to_their_first_key() {
+        let identity = Identity::generate();
+        let other = Identity::generate();
+        let registry = Registry::new(&RegistryOptions {
+            trusted_keys: vec![identity.public_key(), other.public_key()],
+            ..Default::default()
+        });
+
+        registry
+            .announce(announcement(&identity, now()).sign(&identity))
+            .unwrap();
+        assert_eq!(
+            registry.announce(announcement(&other, now()).sign(&other)),
+            Err(RegistryError::UrlTaken)
+        );
+
+        // Still refused once the server expired.
+        registry.live_at(Instant::now() + registry.ttl + Duration::from_secs(1));
+        assert_eq!(
+            registry.announce(announcement(&other, now()).sign(&other)),
+            Err(RegistryError::UrlTaken)
+        );
+    }
+}
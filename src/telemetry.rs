@@ -0,0 +1,198 @@
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use tracing::Instrument;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// Header a request ID is read from, and echoed back on.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Installs the global subscriber, writing JSON to stdout.
+///
+/// The level is read from `RUST_LOG` (default `info`). With the `otlp` feature, spans are also
+/// exported to the collector at `OTEL_EXPORTER_OTLP_ENDPOINT` (default `http://localhost:4317`).
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let fmt = tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(false);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otlp::layer())
+        .init();
+}
+
+/// Flushes any spans that have not been exported yet.
+pub fn shutdown() {
+    otlp::shutdown();
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::Subscriber;
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub(super) fn layer<S>() -> Option<impl tracing_subscriber::Layer<S>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .unwrap_or_else(|_| "http://localhost:4317".into());
+
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .install_batch(opentelemetry::runtime::Tokio);
+
+        match tracer {
+            Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+            Err(e) => {
+                eprintln!("Could not start the OTLP exporter: {}", e);
+                None
+            }
+        }
+    }
+
+    pub(super) fn shutdown() {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+mod otlp {
+    use tracing_subscriber::layer::Identity;
+
+    pub(super) fn layer() -> Option<Identity> {
+        None
+    }
+
+    pub(super) fn shutdown() {}
+}
+
+/// Hides personal data (emails, OTPs) from traces unless `LOG_PII=1` is set.
+pub fn redact(value: &str) -> &str {
+    if std::env::var("LOG_PII").map(|v| v == "1").unwrap_or(false) {
+        value
+    } else {
+        "[redacted]"
+    }
+}
+
+/// Middleware opening a span for every request, tagged with a request ID.
+///
+/// The ID is taken from the `x-request-id` header when the caller provides one, otherwise it is
+/// generated. Either way it is returned on the response.
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+        let span = tracing::info_span!(
+            "http.request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+
+        let start = Instant::now();
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let mut res = fut.await?;
+
+                tracing::info!(
+                    status = res.status().as_u16(),
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "request completed"
+                );
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::config::{Config, ServerType};
+
+    #[actix_web::test]
+    async fn request_id() {
+        let config = Config::test(ServerType::Email).await;
+        let app = crate::test::build_test_app!(config).await;
+
+        let req = test::TestRequest::get()
+            .uri("/healthz")
+            .insert_header((REQUEST_ID_HEADER, "foobar-request"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "foobar-request"
+        );
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().contains_key(REQUEST_ID_HEADER));
+    }
+}
@@ -0,0 +1,157 @@
+//! A local stand-in for the device API at `BIOMETRIC_API_URL`.
+//!
+//! `POST /requestAuth` asks a device to authenticate its user, `POST /requestChange` to approve a
+//! change to their devices, and `GET /status` answers whether they did, as decided for each device
+//! by [`MockBiometric::set`].
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::{web, App, HttpResponse, HttpServer};
+
+use crate::api::device::{AuthRequest, ChangeRequest, DeviceStatus, StatusResponse};
+
+/// Timeout of the requests test servers send to the device API.
+pub(crate) const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What the user does when a device asks them to authenticate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Approve,
+    Deny,
+    /// The user does not answer.
+    Pending,
+    /// The device API answers after the request timed out.
+    Timeout,
+    /// The device API answers with a server error.
+    Unavailable,
+}
+
+#[derive(Debug)]
+struct State {
+    default: Outcome,
+    outcomes: HashMap<String, Outcome>,
+    /// Every request to authenticate, in order.
+    requested: Vec<AuthRequest>,
+    /// Every request to approve a change, in order.
+    changes: Vec<ChangeRequest>,
+    status_calls: usize,
+}
+
+async fn request_auth(
+    state: web::Data<Mutex<State>>,
+    request: web::Json<AuthRequest>,
+) -> HttpResponse {
+    state.lock().unwrap().requested.push(request.0);
+
+    HttpResponse::Ok().finish()
+}
+
+async fn request_change(
+    state: web::Data<Mutex<State>>,
+    request: web::Json<ChangeRequest>,
+) -> HttpResponse {
+    state.lock().unwrap().changes.push(request.0);
+
+    HttpResponse::Ok().finish()
+}
+
+async fn status(state: web::Data<Mutex<State>>, request: web::Query<AuthRequest>) -> HttpResponse {
+    let outcome = {
+        let mut state = state.lock().unwrap();
+        state.status_calls += 1;
+
+        // Requests never made cannot have been approved.
+        let changed = state.changes.iter().any(|change| {
+            change.device_id == request.device_id && change.request_id == request.request_id
+        });
+        if !state.requested.contains(&request) && !changed {
+            return HttpResponse::Ok().json(StatusResponse {
+                status: DeviceStatus::Denied,
+            });
+        }
+
+        state
+            .outcomes
+            .get(&request.device_id)
+            .copied()
+            .unwrap_or(state.default)
+    };
+
+    let status = match outcome {
+        Outcome::Approve => DeviceStatus::Approved,
+        Outcome::Deny => DeviceStatus::Denied,
+        Outcome::Pending => DeviceStatus::Pending,
+        Outcome::Timeout => {
+            actix_web::rt::time::sleep(TIMEOUT * 2).await;
+            DeviceStatus::Approved
+        }
+        Outcome::Unavailable => return HttpResponse::ServiceUnavailable().finish(),
+    };
+
+    HttpResponse::Ok().json(StatusResponse { status })
+}
+
+/// A mock device API served on a local port, until the test ends.
+pub(crate) struct MockBiometric {
+    url: String,
+    state: web::Data<Mutex<State>>,
+}
+
+impl MockBiometric {
+    /// Serves the mock, where devices without an outcome `set` answer with `default`.
+    pub(crate) fn start(default: Outcome) -> Self {
+        let state = web::Data::new(Mutex::new(State {
+            default,
+            outcomes: HashMap::new(),
+            requested: vec![],
+            changes: vec![],
+            status_calls: 0,
+        }));
+
+        let data = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/requestAuth", web::post().to(request_auth))
+                .route("/requestChange", web::post().to(request_change))
+                .route("/status", web::get().to(status))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        Self { url, state }
+    }
+
+    /// The url to configure as `BIOMETRIC_API_URL`.
+    pub(crate) fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub(crate) fn set(&self, device_id: &str, outcome: Outcome) {
+        self.state
+            .lock()
+            .unwrap()
+            .outcomes
+            .insert(device_id.into(), outcome);
+    }
+
+    /// The requests to authenticate, in order.
+    pub(crate) fn requested(&self) -> Vec<AuthRequest> {
+        self.state.lock().unwrap().requested.clone()
+    }
+
+    /// The requests to approve a change, in order.
+    pub(crate) fn changes(&self) -> Vec<ChangeRequest> {
+        self.state.lock().unwrap().changes.clone()
+    }
+
+    /// How many times `/status` was asked for, retries included.
+    pub(crate) fn status_calls(&self) -> usize {
+        self.state.lock().unwrap().status_calls
+    }
+}
@@ -0,0 +1,654 @@
+use super::*;
+
+use actix_web::test;
+
+#[cfg(feature = "biometric")]
+pub(crate) mod biometric;
+
+macro_rules! build_test_app {
+    ($config:ident) => {
+        actix_web::test::init_service({
+            let mounts = $config.mounts();
+
+            let crate::config::Config {
+                host,
+                servers,
+                active_servers,
+                registry,
+                refresh,
+                identity,
+                ..
+            } = $config;
+
+            let _host = host.clone();
+
+            let app = actix_web::App::new()
+                .wrap(crate::telemetry::RequestTracing)
+                .wrap(crate::metrics::RequestMetrics)
+                .app_data(active_servers.clone())
+                .app_data(mounts)
+                .app_data(identity.clone())
+                .app_data(actix_web::web::Data::new(crate::registry::Registry::new(
+                    &registry,
+                )))
+                .app_data(actix_web::web::Data::new(refresh.clone()))
+                .service(crate::config::root)
+                .service(crate::registry::announce)
+                .service(crate::api::health::healthz);
+
+            servers
+                .iter()
+                .fold(app, |app, server| app.service(crate::mount(server)))
+        })
+    };
+}
+
+pub type TestApp = Config;
+
+impl TestApp {
+    pub(crate) async fn _get_base(&self) -> Vec<config::ServerPublicData> {
+        let app = build_test_app!(self).await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    pub(crate) async fn _get_server_ty(&self) -> config::ServerType {
+        let app = build_test_app!(self).await;
+        let req = test::TestRequest::get().uri("/ty").to_request();
+
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    pub(crate) async fn get_status(&self) -> api::VerificationStatus {
+        let app = build_test_app!(self).await;
+        let req = test::TestRequest::post()
+            .uri("/status")
+            .set_json(serde_json::json!({
+                "email": "benjcape@gmail.com"
+            }))
+            .to_request();
+
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    pub(crate) async fn register<T>(&self, secret: &str, data: &T) -> String
+    where
+        T: Serialize,
+    {
+        let app = build_test_app!(self).await;
+        let req = test::TestRequest::post()
+            .uri("/register")
+            .set_json(serde_json::json!({
+                "email": "benjcape@gmail.com",
+                "secret_component": secret,
+                "data": data
+            }))
+            .to_request();
+
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    pub(crate) async fn verify_register(&self, otp: &str) {
+        let app = build_test_app!(self).await;
+        let req = test::TestRequest::post()
+            .uri("/register/verify")
+            .set_json(serde_json::json!({
+                "email": "benjcape@gmail.com",
+                "otp": otp
+            }))
+            .to_request();
+
+        test::call_service(&app, req).await;
+    }
+
+    pub(crate) async fn auth(&self) -> serde_json::Value {
+        let app = build_test_app!(self).await;
+        let req = test::TestRequest::post()
+            .uri("/authenticate")
+            .set_json(serde_json::json!({
+                "email": "benjcape@gmail.com"
+            }))
+            .to_request();
+
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    pub(crate) async fn verify_auth<T>(&self, data: &T) -> Option<String>
+    where
+        T: Serialize,
+    {
+        let app = build_test_app!(self).await;
+        let req = test::TestRequest::post()
+            .uri("/authenticate/verify")
+            .set_json(serde_json::json!({
+                "email": "benjcape@gmail.com",
+                "data": data
+            }))
+            .to_request();
+
+        let released: api::Released = test::call_and_read_body_json(&app, req).await;
+        released.secret_component
+    }
+}
+
+/// A well formed share, as registered by clients.
+pub(crate) fn share() -> String {
+    sharing::split(b"foobar", 2, 3).unwrap()[0].encode()
+}
+
+/// Serves the servers of `config` on a local port, for tests calling them over HTTP.
+pub(crate) fn serve(config: &Config) -> std::net::SocketAddr {
+    let (servers, options) = (config.servers.clone(), config.refresh.clone());
+    let server = actix_web::HttpServer::new(move || {
+        servers.iter().fold(
+            actix_web::App::new().app_data(actix_web::web::Data::new(options.clone())),
+            |app, server| app.service(crate::mount(server)),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+
+    addr
+}
+
+pub(crate) use build_test_app;
+use serde::Serialize;
+
+#[actix_web::test]
+async fn mounted_servers() {
+    let config = Config::test_mounted(&[config::ServerType::Email, config::ServerType::QA]).await;
+    let public_keys: Vec<_> = config
+        .servers
+        .iter()
+        .map(|server| Some(server.identity.public_key()))
+        .collect();
+    let app = build_test_app!(config).await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let servers: Vec<config::ServerPublicData> = test::call_and_read_body_json(&app, req).await;
+    let urls: Vec<_> = servers.iter().map(|server| server.url.as_str()).collect();
+    assert_eq!(
+        urls,
+        ["http://localhost:8080/email", "http://localhost:8080/qa"]
+    );
+
+    // Shares are signed by the identity of their server, which the listing attributes them to.
+    let listed: Vec<_> = servers
+        .iter()
+        .map(|server| server.public_key.clone())
+        .collect();
+    assert_eq!(listed, public_keys);
+
+    let req = test::TestRequest::get().uri("/qa/ty").to_request();
+    let server_ty: config::ServerType = test::call_and_read_body_json(&app, req).await;
+    assert!(matches!(server_ty, config::ServerType::QA));
+
+    let req = test::TestRequest::post()
+        .uri("/qa/register")
+        .set_json(serde_json::json!({
+            "email": "benjcape@gmail.com",
+            "secret_component": share(),
+            "data": [
+                { "question": "Who?", "answer": "Me" },
+                { "question": "Where?", "answer": "Here" },
+                { "question": "When?", "answer": "Now" },
+            ]
+        }))
+        .to_request();
+    let otp: String = test::call_and_read_body_json(&app, req).await;
+
+    let req = test::TestRequest::post()
+        .uri("/qa/register/verify")
+        .set_json(serde_json::json!({
+            "email": "benjcape@gmail.com",
+            "otp": otp
+        }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // Each server keeps its users in its own database.
+    for (prefix, expected) in [("/qa", "\"Verified\""), ("/email", "")] {
+        let req = test::TestRequest::post()
+            .uri(&format!("{}/status", prefix))
+            .set_json(serde_json::json!({ "email": "benjcape@gmail.com" }))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, expected);
+    }
+}
+
+#[actix_web::test]
+async fn forged_host() {
+    use crate::identity::{verify, SIGNATURE_HEADER};
+
+    let config = Config::test_mounted(&[config::ServerType::Email, config::ServerType::QA]).await;
+    let public_key = config.identity.public_key();
+    let app = build_test_app!(config).await;
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Host", "attacker.test"))
+        .insert_header(("X-Forwarded-Host", "attacker.test"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    let signature = res
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = test::read_body(res).await;
+
+    assert!(verify(&public_key, &body, &signature));
+    assert!(!String::from_utf8(body.to_vec())
+        .unwrap()
+        .contains("attacker.test"));
+
+    let servers: Vec<config::ServerPublicData> = serde_json::from_slice(&body).unwrap();
+    let urls: Vec<_> = servers.iter().map(|server| server.url.as_str()).collect();
+    assert_eq!(
+        urls,
+        ["http://localhost:8080/email", "http://localhost:8080/qa"]
+    );
+}
+
+#[actix_web::test]
+async fn registry_lists_announced_servers() {
+    let identity = crate::identity::Identity::generate();
+
+    let mut config = Config::test(config::ServerType::Email).await;
+    config.registry.trusted_keys = vec![identity.public_key()];
+    let app = build_test_app!(config).await;
+
+    // Nothing announced yet, so the static configuration is listed.
+    let req = test::TestRequest::get().uri("/").to_request();
+    let servers: Vec<config::ServerPublicData> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(servers[0].url, "https://server.test:8080");
+
+    let announcement = crate::registry::Announcement {
+        url: "https://qa.test".into(),
+        server_ty: config::ServerType::QA,
+        public_key: identity.public_key(),
+        ready: true,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    };
+    let req = test::TestRequest::post()
+        .uri("/registry/announce")
+        .set_json(announcement.sign(&identity))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        actix_web::http::StatusCode::NO_CONTENT
+    );
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let servers: Vec<config::ServerPublicData> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(servers.len(), 1);
+    assert_eq!(servers[0].url, "https://qa.test");
+    assert_eq!(servers[0].public_key, Some(identity.public_key()));
+    assert_eq!(servers[0].ready, Some(true));
+}
+
+/// Three mounted servers holding verifiable shares of the same user, served on a local port, and
+/// listing each other as refresh peers.
+async fn refresh_peers() -> (Config, Vec<sharing::Share>) {
+    let mut config = Config::test_mounted(&[
+        config::ServerType::Email,
+        config::ServerType::QA,
+        config::ServerType::Password,
+    ])
+    .await;
+
+    let (shares, commitments) = sharing::feldman::split(b"foobar", 2, 3).unwrap();
+    for (server, share) in config.servers.iter().zip(&shares) {
+        let base = crate::api::base::BaseAuthenticator::new(server);
+        let res = base
+            .verify_register(
+                "benjcape@gmail.com",
+                &share.encode(),
+                Some(commitments.encode()),
+                serde_json::Value::Null,
+            )
+            .await;
+        assert!(res.is_none());
+    }
+
+    config.refresh.trusted_keys = config
+        .servers
+        .iter()
+        .map(|server| server.identity.public_key())
+        .collect();
+
+    let addr = serve(&config);
+
+    config.refresh.peers = config
+        .servers
+        .iter()
+        .map(|server| format!("http://{}{}", addr, server.prefix))
+        .collect();
+
+    (config, shares)
+}
+
+/// The share of the user held by each server, checked against its commitments, and its epoch.
+async fn refreshed_shares(config: &Config) -> Vec<(sharing::Share, i64)> {
+    use sharing::{Commitments, Share};
+
+    let mut refreshed = vec![];
+    for server in &config.servers {
+        let (secret_component, published, epoch): (String, String, i64) =
+            sqlx::query_as("SELECT secret_component, commitments, epoch FROM authenticated")
+                .fetch_one(&server.database)
+                .await
+                .unwrap();
+
+        let share = Share::decode(&secret_component).unwrap();
+        assert_eq!(
+            Commitments::decode(&published).unwrap().verify(&share),
+            Ok(())
+        );
+        refreshed.push((share, epoch));
+    }
+
+    refreshed
+}
+
+#[actix_web::test]
+async fn refresh_round() {
+    use crate::refresh::{RefreshUpdate, Signed};
+
+    let (config, shares) = refresh_peers().await;
+
+    let client = reqwest::Client::new();
+    crate::refresh::round(&client, &config.refresh, &config.servers).await;
+
+    let (refreshed, epochs): (Vec<_>, Vec<_>) = refreshed_shares(&config).await.into_iter().unzip();
+    assert_eq!(epochs, [1, 1, 1]);
+
+    // Refreshing is recorded in the audit log of every server.
+    for server in &config.servers {
+        let entries = crate::audit::entries(&server.database).await.unwrap();
+        assert_eq!(
+            entries.last().unwrap().event,
+            format!("{:?}", crate::audit::AuditEvent::Refreshed)
+        );
+    }
+
+    assert_ne!(refreshed[0], shares[0]);
+    assert_eq!(sharing::combine(&refreshed[1..]).unwrap(), b"foobar");
+    assert_eq!(
+        sharing::combine(&[shares[0].clone(), refreshed[1].clone()]),
+        Err(sharing::Error::EpochMismatch)
+    );
+
+    // Replaying a share of zero of the applied epoch is rejected as stale.
+    let (zeros, _) = sharing::refresh::zero_shares(&shares[0], &[1, 2, 3]).unwrap();
+    let update = Signed::new(
+        RefreshUpdate {
+            email: crate::api::base::BaseAuthenticator::hash("benjcape@gmail.com"),
+            epoch: 1,
+            delta: zeros[0].encode(),
+            commitments: None,
+        },
+        &config.servers[0].identity,
+    );
+    let res = client
+        .post(format!("{}/refresh", config.refresh.peers[0]))
+        .json(&update)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+
+    let untrusted = Signed::new(update.message, &crate::identity::Identity::generate());
+    let res = client
+        .post(format!("{}/refresh", config.refresh.peers[0]))
+        .json(&untrusted)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn missed_refresh() {
+    let (config, _) = refresh_peers().await;
+    let client = reqwest::Client::new();
+
+    // The first server cannot reach the third, which misses its share of zero.
+    let mut unreachable = config.refresh.clone();
+    unreachable.peers.truncate(2);
+    crate::refresh::round(&client, &unreachable, &config.servers[..1]).await;
+    crate::refresh::round(&client, &config.refresh, &config.servers[1..]).await;
+
+    let epochs: Vec<_> = refreshed_shares(&config)
+        .await
+        .into_iter()
+        .map(|(_, epoch)| epoch)
+        .collect();
+    assert_eq!(epochs, [1, 1, 0]);
+
+    // The next round deals it again, and the third server catches up.
+    crate::refresh::round(&client, &config.refresh, &config.servers).await;
+
+    let (refreshed, epochs): (Vec<_>, Vec<_>) = refreshed_shares(&config).await.into_iter().unzip();
+    assert_eq!(epochs, [1, 1, 1]);
+    assert_eq!(sharing::combine(&refreshed[1..]).unwrap(), b"foobar");
+    assert_eq!(
+        sharing::combine(&[refreshed[0].clone(), refreshed[2].clone()]).unwrap(),
+        b"foobar"
+    );
+}
+
+#[actix_web::test]
+async fn non_zero_delta() {
+    use crate::identity::Identity;
+    use crate::refresh::{RefreshUpdate, Signed};
+    use sharing::{Commitments, Share};
+
+    let mut config = Config::test_mounted(&[config::ServerType::Email]).await;
+
+    let (shares, commitments) = sharing::feldman::split(b"foobar", 2, 3).unwrap();
+    let base = crate::api::base::BaseAuthenticator::new(&config.servers[0]);
+    let res = base
+        .verify_register(
+            "benjcape@gmail.com",
+            &shares[0].encode(),
+            Some(commitments.encode()),
+            serde_json::Value::Null,
+        )
+        .await;
+    assert!(res.is_none());
+
+    let dealer = Identity::generate();
+    config.refresh.trusted_keys = vec![dealer.public_key()];
+    let addr = serve(&config);
+    let url = format!("http://{}{}/refresh", addr, config.servers[0].prefix);
+
+    let client = reqwest::Client::new();
+    let send = |delta: &Share, dealt: &Commitments| {
+        let update = Signed::new(
+            RefreshUpdate {
+                email: crate::api::base::BaseAuthenticator::hash("benjcape@gmail.com"),
+                epoch: 1,
+                delta: delta.encode(),
+                commitments: Some(dealt.encode()),
+            },
+            &dealer,
+        );
+        let req = client.post(&url).json(&update);
+        async move { req.send().await.unwrap().status() }
+    };
+
+    // Shares of another secret match their commitments, but do not commit to zero.
+    let (mut other, dealt) = sharing::feldman::split(b"barfoo", 2, 3).unwrap();
+    other[0].epoch = 1;
+    assert_eq!(
+        send(&other[0], &dealt).await,
+        reqwest::StatusCode::BAD_REQUEST
+    );
+
+    // Shares of zero must match the commitments dealt with them.
+    let (zeros, dealt) = sharing::refresh::zero_shares(&shares[0], &[1, 2, 3]).unwrap();
+    let dealt = dealt.unwrap();
+    assert_eq!(
+        send(&zeros[1], &dealt).await,
+        reqwest::StatusCode::BAD_REQUEST
+    );
+    let mut tampered = zeros[0].clone();
+    tampered.payload = other[0].payload.clone();
+    assert_eq!(
+        send(&tampered, &dealt).await,
+        reqwest::StatusCode::BAD_REQUEST
+    );
+
+    let (secret_component, epoch): (String, i64) =
+        sqlx::query_as("SELECT secret_component, epoch FROM authenticated")
+            .fetch_one(&config.servers[0].database)
+            .await
+            .unwrap();
+    assert_eq!(secret_component, shares[0].encode());
+    assert_eq!(epoch, 0);
+
+    assert_eq!(send(&zeros[0], &dealt).await, reqwest::StatusCode::OK);
+    let (secret_component, published): (String, String) =
+        sqlx::query_as("SELECT secret_component, commitments FROM authenticated")
+            .fetch_one(&config.servers[0].database)
+            .await
+            .unwrap();
+    let refreshed = Share::decode(&secret_component).unwrap();
+    assert_eq!(
+        Commitments::decode(&published).unwrap().verify(&refreshed),
+        Ok(())
+    );
+    assert_eq!(
+        sharing::combine(&[refreshed, shares[1].refresh(&zeros[1..2]).unwrap()]).unwrap(),
+        b"foobar"
+    );
+}
+
+#[actix_web::test]
+async fn client_sdk() {
+    use crate::api::base::BaseAuthenticator;
+    use simple_syrup_client::{
+        EmailClient, Error, QaClient, QuestionAnswer, ServerType, VerificationStatus,
+    };
+
+    let config = Config::test_mounted(&[config::ServerType::Email, config::ServerType::QA]).await;
+    let addr = serve(&config);
+    let email = "benjcape@gmail.com";
+
+    // Registrations of earlier runs are kept, so find the one of this share.
+    async fn prepared(
+        base: &BaseAuthenticator,
+        secret_component: &str,
+    ) -> crate::api::base::Prepared {
+        base.get_prepared("benjcape@gmail.com")
+            .await
+            .into_iter()
+            .find(|(_, sec, _, _)| sec == secret_component)
+            .unwrap()
+    }
+
+    let qa = QaClient::new(format!("http://{}{}", addr, config.servers[1].prefix));
+    let qa_base = BaseAuthenticator::new(&config.servers[1]);
+    let answers: Vec<_> = ["Who?", "Where?", "When?"]
+        .iter()
+        .map(|question| QuestionAnswer {
+            question: question.to_string(),
+            answer: "Me".into(),
+        })
+        .collect();
+
+    assert_eq!(qa.server_ty().await.unwrap(), ServerType::QA);
+    assert_eq!(qa.questions().await.unwrap(), crate::api::qa::CATALOG);
+    assert_eq!(qa.status(email).await.unwrap(), None);
+
+    let e = qa
+        .register(email, "not a share", None, answers.clone())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(e, Error::BadRequest(message) if message.starts_with("secret_component is not a valid share"))
+    );
+
+    let secret_component = share();
+    qa.register(email, &secret_component, None, answers.clone())
+        .await
+        .unwrap();
+    let (id, ..) = prepared(&qa_base, &secret_component).await;
+    assert!(matches!(
+        qa.verify_register(email, "not an otp").await,
+        Err(Error::Unauthorized)
+    ));
+    qa.verify_register(email, &qa_base.current_otp(&id))
+        .await
+        .unwrap();
+    assert_eq!(
+        qa.status(email).await.unwrap(),
+        Some(VerificationStatus::Verified)
+    );
+
+    let challenge = qa.challenge(email).await.unwrap();
+    assert_eq!(challenge.questions, ["Who?", "Where?", "When?"]);
+    assert_eq!(challenge.required, 2);
+    let mut asked: Vec<_> = challenge
+        .questions
+        .iter()
+        .map(|question| QuestionAnswer {
+            question: question.clone(),
+            answer: "Me".into(),
+        })
+        .collect();
+    asked[0].answer = "You".into();
+    asked[1].answer = "You".into();
+    assert!(matches!(
+        qa.verify_authentication(email, asked.clone()).await,
+        Err(Error::Unauthorized)
+    ));
+    asked[1].answer = "me ".into();
+    assert_eq!(
+        qa.verify_authentication(email, asked).await.unwrap(),
+        simple_syrup_client::Released {
+            secret_component: Some(secret_component),
+            commitments: None,
+        }
+    );
+
+    // A verifiable share, released with the OTP emailed on authentication.
+    let client = EmailClient::new(format!("http://{}{}", addr, config.servers[0].prefix));
+    let email_base = BaseAuthenticator::new(&config.servers[0]);
+    let (shares, commitments) = sharing::feldman::split(b"foobar", 2, 3).unwrap();
+
+    client
+        .register(
+            email,
+            &shares[0].encode(),
+            Some(&commitments.encode()),
+            String::new(),
+        )
+        .await
+        .unwrap();
+    let (id, ..) = prepared(&email_base, &shares[0].encode()).await;
+    client
+        .verify_register(email, &email_base.current_otp(&id))
+        .await
+        .unwrap();
+
+    client.authenticate(email).await.unwrap();
+    let id = email_base.get_authenticated_id(email).await.unwrap();
+    let released = client
+        .verify_authentication(email, email_base.current_otp(&id.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(released.secret_component, Some(shares[0].encode()));
+    assert_eq!(released.commitments, Some(commitments.encode()));
+}